@@ -0,0 +1,236 @@
+use crate::errors::{error, Result};
+use crate::os::unix::rename_no_replace;
+use rand::RngCore;
+use std::cell::Cell;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::path::PathBuf;
+use walkdir::WalkDir;
+
+/**
+ * Prefix of the temporary files written before being moved into place
+ */
+pub const TEMP_PREFIX: &str = ".tmp-";
+
+/**
+ * Access to the files of a repository.
+ *
+ * All paths are relative to the root of the repository and use '/' as
+ * separator, e.g. "keys/0123456789abcdef.dek". FileStorage lays out the
+ * objects of a repository as such files, so the same layout can be kept on
+ * anything providing these operations.
+ */
+pub trait Backend {
+    /**
+     * Read the complete content of a file
+     */
+    fn read(&self, path: &str) -> Result<Vec<u8>>;
+    /**
+     * Read length bytes starting at offset from a file
+     */
+    fn read_range(&self, path: &str, offset: u64, length: u64) -> Result<Vec<u8>>;
+    /**
+     * Write a file, so that it either exists completely or not at all. Unless
+     * overwrite is set, an existing file is never replaced and writing it fails.
+     */
+    fn write(&self, path: &str, data: &[u8], overwrite: bool) -> Result<()>;
+    fn exists(&self, path: &str) -> Result<bool>;
+    /**
+     * The size of a file in bytes
+     */
+    fn size(&self, path: &str) -> Result<u64>;
+    /**
+     * List all files below the given directory together with their sizes. The
+     * names are relative to the directory. A missing directory is empty.
+     */
+    fn list(&self, dir: &str) -> Result<Vec<(String, u64)>>;
+    fn remove(&self, path: &str) -> Result<()>;
+    /**
+     * Create a directory. Backends without directories ignore this.
+     */
+    fn create_dir(&self, dir: &str) -> Result<()>;
+    /**
+     * Also flush the parent directory to disk after writing a file. Backends,
+     * that don't write to a local file system, ignore this.
+     */
+    fn set_sync_directories(&self, _sync_directories: bool) {}
+}
+
+/**
+ * A repository in a directory of the local file system
+ */
+pub struct LocalBackend {
+    path: PathBuf,
+    sync_directories: Cell<bool>,
+}
+
+impl LocalBackend {
+    pub fn new(path: &Path) -> LocalBackend {
+        LocalBackend {
+            path: path.to_path_buf(),
+            sync_directories: Cell::new(false),
+        }
+    }
+}
+
+impl Backend for LocalBackend {
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        fs::read(self.path.join(path)).or_else(|e| error("Could not read file", Some(e.into())))
+    }
+    fn read_range(&self, path: &str, offset: u64, length: u64) -> Result<Vec<u8>> {
+        let mut file = File::open(self.path.join(path))
+            .or_else(|e| error("Could not open file", Some(e.into())))?;
+        let size = file
+            .metadata()
+            .or_else(|e| error("Could not read file size", Some(e.into())))?
+            .len();
+        match offset.checked_add(length) {
+            Some(end) if end <= size => {}
+            _ => return error("The range lies outside of the file", None),
+        }
+        file.seek(SeekFrom::Start(offset))
+            .or_else(|e| error("Could not read file", Some(e.into())))?;
+        let mut data = vec![0; length as usize];
+        file.read_exact(&mut data)
+            .or_else(|e| error("Could not read file", Some(e.into())))?;
+        Ok(data)
+    }
+    fn write(&self, path: &str, data: &[u8], overwrite: bool) -> Result<()> {
+        let file_path = self.path.join(path);
+        if let Some(parent_path) = file_path.parent() {
+            fs::create_dir_all(parent_path)
+                .or_else(|e| error("Could not create parent directory", Some(e.into())))?;
+        }
+        write_atomic(&file_path, data, overwrite, self.sync_directories.get())
+    }
+    fn exists(&self, path: &str) -> Result<bool> {
+        Ok(self.path.join(path).exists())
+    }
+    fn size(&self, path: &str) -> Result<u64> {
+        fs::metadata(self.path.join(path))
+            .map(|m| m.len())
+            .or_else(|e| error("Could not read file size", Some(e.into())))
+    }
+    fn list(&self, dir: &str) -> Result<Vec<(String, u64)>> {
+        let dir_path = self.path.join(dir);
+        if !dir_path.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut files = vec![];
+        for entry in WalkDir::new(&dir_path).min_depth(1) {
+            let entry = entry.or_else(|e| error("Could not read directory", Some(e.into())))?;
+            if !entry.file_type().is_file()
+                || entry.file_name().to_string_lossy().starts_with(TEMP_PREFIX)
+            {
+                continue;
+            }
+            let name = entry
+                .path()
+                .strip_prefix(&dir_path)
+                .or_else(|e| error("Could not read directory", Some(e.into())))?
+                .to_string_lossy()
+                .into_owned();
+            let size = entry
+                .metadata()
+                .or_else(|e| error("Could not read file size", Some(e.into())))?
+                .len();
+            files.push((name, size));
+        }
+        Ok(files)
+    }
+    fn remove(&self, path: &str) -> Result<()> {
+        fs::remove_file(self.path.join(path))
+            .or_else(|e| error("Could not remove file", Some(e.into())))
+    }
+    fn create_dir(&self, dir: &str) -> Result<()> {
+        fs::create_dir_all(self.path.join(dir))
+            .or_else(|e| error("Could not create directory", Some(e.into())))
+    }
+    fn set_sync_directories(&self, sync_directories: bool) {
+        self.sync_directories.set(sync_directories);
+    }
+}
+
+/**
+ * Write a file, so that it either exists completely or not at all, even if
+ * the process crashes or the disk runs full. The data is written to a
+ * temporary file in the target directory, flushed to disk and then moved to
+ * the final name. Unless overwrite is set, an existing file is never replaced.
+ */
+fn write_atomic(path: &Path, data: &[u8], overwrite: bool, sync_directory: bool) -> Result<()> {
+    let parent_path = match path.parent() {
+        Some(parent_path) => parent_path,
+        None => return error("File has no parent directory", None),
+    };
+    let temp_path = parent_path.join(format!(
+        "{}{:016x}",
+        TEMP_PREFIX,
+        rand::thread_rng().next_u64()
+    ));
+    let result = write_synced(&temp_path, data).and_then(|_| {
+        if overwrite {
+            fs::rename(&temp_path, path)
+                .or_else(|e| error("Could not move file into place", Some(e.into())))
+        } else {
+            move_no_replace(&temp_path, path, data)
+        }
+    });
+    if result.is_err() || !overwrite {
+        fs::remove_file(&temp_path).ok();
+    }
+    result?;
+    if sync_directory {
+        File::open(parent_path)
+            .and_then(|dir| dir.sync_all())
+            .or_else(|e| error("Could not sync directory", Some(e.into())))?;
+    }
+    Ok(())
+}
+
+/**
+ * Give a temporary file its final name, unless a file with that name exists.
+ * Linking fails if the target exists, which makes the check and the creation
+ * a single atomic step. File systems without hard links (e.g. exFAT, SMB or
+ * FUSE mounts) get a rename, that never replaces the target, instead. If even
+ * that is not supported, the final name is created exclusively and written.
+ */
+fn move_no_replace(temp_path: &Path, path: &Path, data: &[u8]) -> Result<()> {
+    match fs::hard_link(temp_path, path) {
+        Err(e) if is_unsupported(&e, &[libc::EPERM, libc::EOPNOTSUPP, libc::ENOSYS]) => {}
+        result => {
+            return result.or_else(|e| error("Could not move file into place", Some(e.into())))
+        }
+    }
+    match rename_no_replace(temp_path, path) {
+        Err(e) if is_unsupported(&e, &[libc::EINVAL, libc::EOPNOTSUPP, libc::ENOSYS]) => {}
+        result => {
+            return result.or_else(|e| error("Could not move file into place", Some(e.into())))
+        }
+    }
+    // a crash may leave an incomplete file behind, but an existing file is
+    // still never replaced
+    write_synced(path, data)
+}
+
+fn is_unsupported(e: &std::io::Error, codes: &[i32]) -> bool {
+    match e.raw_os_error() {
+        Some(code) => codes.contains(&code),
+        None => false,
+    }
+}
+
+fn write_synced(path: &Path, data: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .or_else(|e| error("Could not create file", Some(e.into())))?;
+    file.write_all(data)
+        .or_else(|e| error("Could not write file", Some(e.into())))?;
+    file.sync_all()
+        .or_else(|e| error("Could not flush file to disk", Some(e.into())))
+}
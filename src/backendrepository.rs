@@ -0,0 +1,1369 @@
+use super::backup::BackupInstance;
+use super::backupobject::BackupObject;
+use super::backupobject::BackupObjectReader;
+use super::errors::{error, Error, Result};
+use super::repository::Repository;
+use crate::backend::{Backend, LocalBackend};
+use crate::backup::EntryList;
+use crate::compression::Compression;
+use crate::crypto::decode_keyed_block;
+use crate::crypto::encode_keyed_block;
+use crate::crypto::keyed_block_key_index;
+use crate::crypto::keyed_hash;
+use crate::crypto::Cipher;
+use crate::crypto::CryptoBlock;
+use crate::crypto::DataEncryptionKey;
+use crate::crypto::InputKey;
+use crate::crypto::KdfParams;
+use crate::crypto::KeySet;
+use crate::crypto::MasterKey;
+use crate::crypto::KEYED_BLOCK_HEADER_SIZE;
+use crate::http::HttpBackend;
+use crate::pack::{PackBuilder, PackIndex};
+use crate::repository::BackrubRepositoryMeta;
+use crate::repository::BackupBlockId;
+use crate::repository::RepositoryConfig;
+use crate::repository::{
+    CURRENT_VERSION, FEATURE_KEYED_BLOCK_IDS, FEATURE_PACK_FILES, FEATURE_PASSWORD_SLOTS,
+    KNOWN_FEATURES,
+};
+use crate::s3::{S3Backend, S3Config};
+use crate::sftp::SftpBackend;
+use crate::storage::{FileStorage, ObjectKind, Storage};
+use log;
+use rand::rngs;
+use rand::RngCore;
+use rmp_serde::Deserializer;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+use std::path::Path;
+use std::rc::Rc;
+
+use rmp_serde::Serializer;
+
+/**
+ * A repository on top of any storage, by default files kept by a backend. The
+ * repository takes care of the encryption and encoding of all objects, of
+ * naming blocks and of collecting them in pack files, while the storage only
+ * keeps the resulting objects.
+ */
+pub struct BackendRepository<S: Storage + 'static = FileStorage> {
+    storage: Rc<S>,
+    repo_info: Option<BackrubRepositoryMeta>,
+    keys: HashMap<u64, DataEncryptionKey>,
+    current_key: Option<(u64, DataEncryptionKey)>,
+    block_id_key: Option<Vec<u8>>,
+    compression: Option<Compression>,
+    master_key: Option<MasterKey>,
+    key_slot: Option<String>,
+    /**
+     * Every location of a block in the pack files. Another client may have
+     * written a block into several packs, so all of them are kept.
+     */
+    pack_index: RefCell<HashMap<BackupBlockId, Vec<PackLocation>>>,
+    pending: RefCell<PackBuilder>,
+    /**
+     * The files skipped when opening the repository, because they couldn't
+     * be read, together with the reason
+     */
+    unreadable: RefCell<Vec<(String, String)>>,
+}
+
+/**
+ * The position of a block in a pack file of the repository
+ */
+#[derive(Clone)]
+struct PackLocation {
+    pack: String,
+    offset: u64,
+    length: u64,
+}
+
+/**
+ * Where to read a block from
+ */
+enum BlockLocation {
+    /**
+     * A block stored in its own file by older versions
+     */
+    Loose(BackupBlockId),
+    Packed(String, u64, u64),
+    /**
+     * A block, that has not been written to a pack file yet
+     */
+    Buffered(Vec<u8>),
+}
+
+impl BackendRepository {
+    /**
+     * Create a repository in a directory of the local file system
+     */
+    pub fn new(path: &Path) -> BackendRepository {
+        BackendRepository::with_backend(Rc::new(LocalBackend::new(path)))
+    }
+    /**
+     * Create a repository for the given location (see backend_for_location)
+     */
+    pub fn for_location(location: &Path) -> Result<BackendRepository> {
+        Ok(BackendRepository::with_backend(backend_for_location(
+            location,
+        )?))
+    }
+    /**
+     * Create a repository keeping its files in the given backend
+     */
+    pub fn with_backend(backend: Rc<dyn Backend>) -> BackendRepository {
+        BackendRepository::with_storage(Rc::new(FileStorage::new(backend)))
+    }
+}
+
+impl<S: Storage + 'static> BackendRepository<S> {
+    /**
+     * Create a repository keeping its objects in the given storage
+     */
+    pub fn with_storage(storage: Rc<S>) -> BackendRepository<S> {
+        BackendRepository {
+            storage,
+            repo_info: None,
+            keys: HashMap::new(),
+            current_key: None,
+            block_id_key: None,
+            compression: None,
+            master_key: None,
+            key_slot: None,
+            pack_index: RefCell::new(HashMap::new()),
+            pending: RefCell::new(PackBuilder::default()),
+            unreadable: RefCell::new(vec![]),
+        }
+    }
+    /**
+     * The password slot the repository was opened with. Repositories, whose
+     * keys are encrypted with the password directly, don't use slots.
+     */
+    pub fn key_slot(&self) -> Option<&str> {
+        self.key_slot.as_deref()
+    }
+    /**
+     * Also flush the parent directory to disk after writing a file. This makes
+     * sure the new name survives a crash, but makes writing considerably slower.
+     */
+    pub fn set_sync_directories(&mut self, sync_directories: bool) {
+        self.storage.set_sync_directories(sync_directories);
+    }
+    /**
+     * Use the most recently generated key for encrypting new blocks
+     */
+    fn select_current_key(&mut self) -> Result<()> {
+        let current = self
+            .keys
+            .iter()
+            .max_by_key(|(_, key)| key.created_at)
+            .map(|(index, key)| (*index, key.clone()));
+        match current {
+            Some(current) => {
+                self.current_key = Some(current);
+                Ok(())
+            }
+            None => error("Repository has no data encryption key", None),
+        }
+    }
+    fn opened_slot(&self) -> Result<&str> {
+        match &self.key_slot {
+            Some(slot) => Ok(slot),
+            None => error("The repository has no password slots", None),
+        }
+    }
+    fn master_key(&self) -> Result<&MasterKey> {
+        match &self.master_key {
+            Some(master_key) => Ok(master_key),
+            None => error("Repository must be opened before managing keys", None),
+        }
+    }
+    /**
+     * The key derivation parameters of the password the repository was opened with
+     */
+    pub fn current_kdf(&self) -> Result<KdfParams> {
+        match &self.key_slot {
+            Some(slot) => Ok(read_key_slot(&*self.storage, slot)?.kdf),
+            None => Ok(self.meta()?.kdf()),
+        }
+    }
+    /**
+     * The key derivation parameters for a new password
+     */
+    fn new_password_kdf(&self) -> Result<KdfParams> {
+        let kdf = match &self.meta()?.kdf {
+            Some(kdf) => kdf.clone(),
+            None => self.current_kdf()?,
+        };
+        Ok(kdf.with_new_salt())
+    }
+    /**
+     * Move the data keys of a repository, that are encrypted with the password
+     * directly, under a new random master key, which is stored in a password slot
+     * for the given password.
+     *
+     * The keys are written under new names first and the old ones are only
+     * removed once the slots exist, so an interrupted conversion leaves a
+     * usable repository behind.
+     */
+    fn convert_to_key_slots(&mut self, kdf: KdfParams, password_key: MasterKey) -> Result<()> {
+        let master_key = MasterKey::generate();
+        for (index, key) in &self.keys {
+            write_key_file(
+                &*self.storage,
+                &data_key_name(*index, true),
+                &master_key,
+                &key.value,
+                key.created_at,
+                true,
+            )?;
+        }
+        let block_id_key = match &self.block_id_key {
+            Some(key) => key,
+            None => return error("block ID key not loaded", None),
+        };
+        write_key_file(
+            &*self.storage,
+            block_id_key_name(true),
+            &master_key,
+            block_id_key,
+            0,
+            true,
+        )?;
+        let slot = format!("{:016x}", rand::thread_rng().next_u64());
+        write_key_slot(
+            &*self.storage,
+            &slot,
+            &master_key,
+            &password_key,
+            kdf,
+            false,
+        )?;
+        remove_password_keys(&*self.storage)?;
+        self.master_key = Some(master_key);
+        self.key_slot = Some(slot);
+        Ok(())
+    }
+    /**
+     * All places a block may be read from, in the order they should be tried
+     */
+    fn locate(&self, id: &BackupBlockId) -> Vec<BlockLocation> {
+        if let Some(block) = self.pending.borrow().get(id) {
+            return vec![BlockLocation::Buffered(Vec::from(block))];
+        }
+        match self.pack_index.borrow().get(id) {
+            Some(locations) => locations
+                .iter()
+                .map(|l| BlockLocation::Packed(l.pack.clone(), l.offset, l.length))
+                .collect(),
+            None => vec![BlockLocation::Loose(id.clone())],
+        }
+    }
+    fn block_verifier(&self) -> Result<BlockVerifier> {
+        Ok(BlockVerifier {
+            keys: self.keys()?.clone(),
+            block_id_key: self.block_id_key.clone(),
+        })
+    }
+    /**
+     * Read the indexes of all pack files in the repository
+     */
+    fn load_pack_index(&self) -> Result<()> {
+        let mut pack_index: HashMap<BackupBlockId, Vec<PackLocation>> = HashMap::new();
+        // repositories created by older versions don't have an index yet
+        for (pack, _) in self.storage.list(ObjectKind::PackIndex)? {
+            let index = match read_pack_index(&*self.storage, &pack, self.keys()?) {
+                Ok(index) => index,
+                Err(e) => {
+                    skip_unreadable(
+                        &mut self.unreadable.borrow_mut(),
+                        format!("pack index {}", pack),
+                        &e,
+                    );
+                    continue;
+                }
+            };
+            for block in index.blocks {
+                pack_index.entry(block.id).or_default().push(PackLocation {
+                    pack: pack.clone(),
+                    offset: block.offset,
+                    length: block.length,
+                });
+            }
+        }
+        self.pack_index.replace(pack_index);
+        Ok(())
+    }
+    /**
+     * Write the blocks collected in the builder as a new pack file. The index
+     * of the pack is written after the pack itself, so the index never refers
+     * to missing data.
+     */
+    fn write_pack(&self, builder: PackBuilder) -> Result<()> {
+        let (name, data, index) = builder.finish();
+        // packs are named by their content, so replacing an existing one is harmless
+        self.storage.put(ObjectKind::Pack, &name, &data, true)?;
+        let mut index_buffer = vec![];
+        index
+            .serialize(&mut Serializer::new(&mut index_buffer))
+            .or_else(|e| error("Could not serialize pack index", Some(e.into())))?;
+        let mut encoded_index = vec![];
+        encode_keyed_block(
+            &mut encoded_index,
+            &index_buffer,
+            self.current_key()?,
+            self.meta()?.compression,
+        )?;
+        self.storage
+            .put(ObjectKind::PackIndex, &name, &encoded_index, true)?;
+        log::debug!("Wrote pack {} with {} blocks", name, index.blocks.len());
+        let mut pack_index = self.pack_index.borrow_mut();
+        for block in index.blocks {
+            pack_index.entry(block.id).or_default().push(PackLocation {
+                pack: name.clone(),
+                offset: block.offset,
+                length: block.length,
+            });
+        }
+        Ok(())
+    }
+    /**
+     * Rewrite a pack file without the given blocks
+     */
+    fn repack(&self, pack: &str, removed: &HashSet<BackupBlockId>) -> Result<()> {
+        let index = read_pack_index(&*self.storage, pack, self.keys()?)?;
+        let pack_size = self.meta()?.pack_size;
+        let mut builder = PackBuilder::default();
+        for block in index.blocks.iter().filter(|b| !removed.contains(&b.id)) {
+            builder.add(
+                &block.id,
+                &self
+                    .storage
+                    .get_range(ObjectKind::Pack, pack, block.offset, block.length)?,
+            );
+            if builder.size() >= pack_size {
+                self.write_pack(std::mem::take(&mut builder))?;
+            }
+        }
+        if !builder.is_empty() {
+            self.write_pack(builder)?;
+        }
+        self.storage.delete(ObjectKind::PackIndex, pack)?;
+        self.storage.delete(ObjectKind::Pack, pack)?;
+        let mut pack_index = self.pack_index.borrow_mut();
+        for block in index.blocks {
+            if let Some(locations) = pack_index.get_mut(&block.id) {
+                locations.retain(|l| l.pack != pack);
+                if locations.is_empty() {
+                    pack_index.remove(&block.id);
+                }
+            }
+        }
+        Ok(())
+    }
+    /**
+     * Move the blocks stored in their own files by older versions into pack files.
+     * The files are only removed after the pack containing them has been written,
+     * so this can simply be started again after an interruption.
+     */
+    fn pack_loose_blocks(&self) -> Result<()> {
+        let pack_size = self.meta()?.pack_size;
+        let mut builder = PackBuilder::default();
+        let mut packed = vec![];
+        for (id, _) in list_loose_blocks(&*self.storage)? {
+            if !self.pack_index.borrow().contains_key(&id) {
+                builder.add(&id, &self.storage.get(ObjectKind::Block, &id.to_str())?);
+            }
+            packed.push(id);
+            if builder.size() >= pack_size {
+                self.write_pack(std::mem::take(&mut builder))?;
+                remove_loose_blocks(&*self.storage, &packed)?;
+                packed.clear();
+            }
+        }
+        if !builder.is_empty() {
+            self.write_pack(builder)?;
+        }
+        remove_loose_blocks(&*self.storage, &packed)
+    }
+    fn open_instance_file(&self, name: &str) -> Result<BackupInstance> {
+        let data = self
+            .storage
+            .get(ObjectKind::Instance, name)
+            .or_else(|e| error("Could not open instance", Some(e.into())))?;
+        let mut deserializer = Deserializer::new(Cursor::new(data));
+        let instance = Deserialize::deserialize(&mut deserializer)
+            .or_else(|e| error("Could not deserialize instance", Some(e.into())))?;
+        Ok(instance)
+    }
+}
+
+impl<S: Storage + 'static> Drop for BackendRepository<S> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            log::error!("Could not write buffered blocks to the repository: {}", e);
+        }
+    }
+}
+
+impl<S: Storage + 'static> Repository for BackendRepository<S> {
+    fn meta(&self) -> Result<&BackrubRepositoryMeta> {
+        match &self.repo_info {
+            Some(info) => Ok(info),
+            None => error(
+                "Internal error. Tried to access meta data before repository was loaded",
+                None,
+            ),
+        }
+    }
+    fn initialize(&self, input_key: InputKey, config: &RepositoryConfig) -> Result<()> {
+        config.chunker.validate()?;
+        self.storage.create().or_else(|e| {
+            error(
+                "Could not create backup repository directory",
+                Some(e.into()),
+            )
+        })?;
+        if !self.storage.exists(ObjectKind::Meta, META_FILE)? {
+            create_backrub_infrastructure(&*self.storage, &input_key, config)?;
+            Ok(())
+        } else {
+            error(
+                "Cannot initialize already initialized repository again.",
+                None,
+            )
+        }
+    }
+    fn open(&mut self, input_key: InputKey) -> Result<()> {
+        let ri = load_meta_data(&*self.storage)?;
+        ri.check_readable()?;
+        let mut unreadable = vec![];
+        let slots = load_key_slots(&*self.storage, &mut unreadable)?;
+        let master_key = if slots.is_empty() && unreadable.is_empty() {
+            self.key_slot = None;
+            ri.kdf().derive(&input_key)?
+        } else {
+            let (slot, master_key) = open_key_slot(&slots, &input_key)?;
+            self.key_slot = Some(slot);
+            master_key
+        };
+        let key_slots = self.key_slot.is_some();
+        let keys = load_keys(&*self.storage, &master_key, key_slots, &mut unreadable)?;
+        self.unreadable.replace(unreadable);
+        self.block_id_key = load_block_id_key(&*self.storage, &master_key, key_slots)?;
+        self.repo_info = Some(ri);
+        let mut key_map = HashMap::new();
+        for key in keys {
+            key_map.insert(key.0, key.1);
+        }
+        self.keys = key_map;
+        self.master_key = Some(master_key);
+        self.select_current_key()?;
+        self.load_pack_index()
+    }
+
+    fn add_key(&mut self) -> Result<u64> {
+        self.meta()?.check_writable()?;
+        let master_key = self.master_key()?;
+        let key_slots = self.key_slot.is_some();
+        let newest = self.current_key()?.1.created_at;
+        let key_index =
+            create_data_encryption_key(&*self.storage, master_key, key_slots, newest + 1)?;
+        let (_, key) = read_data_encryption_key(
+            &*self.storage,
+            &data_key_name(key_index, key_slots),
+            master_key,
+        )?;
+        self.keys.insert(key_index, key);
+        self.select_current_key()?;
+        Ok(key_index)
+    }
+
+    fn change_password(&mut self, new_key: InputKey) -> Result<()> {
+        self.meta()?.check_writable()?;
+        let kdf = self.new_password_kdf()?;
+        let password_key = kdf.derive(&new_key)?;
+        write_key_slot(
+            &*self.storage,
+            self.opened_slot()?,
+            self.master_key()?,
+            &password_key,
+            kdf,
+            true,
+        )
+    }
+
+    fn add_password(&mut self, new_key: InputKey) -> Result<String> {
+        self.meta()?.check_writable()?;
+        let kdf = self.new_password_kdf()?;
+        let password_key = kdf.derive(&new_key)?;
+        let slot = format!("{:016x}", rand::thread_rng().next_u64());
+        write_key_slot(
+            &*self.storage,
+            &slot,
+            self.master_key()?,
+            &password_key,
+            kdf,
+            false,
+        )?;
+        Ok(slot)
+    }
+
+    fn upgrade_kdf(&mut self, key: InputKey, kdf: KdfParams) -> Result<()> {
+        self.meta()?.check_writable()?;
+        let password_key = kdf.derive(&key)?;
+        write_key_slot(
+            &*self.storage,
+            self.opened_slot()?,
+            self.master_key()?,
+            &password_key,
+            kdf.clone(),
+            true,
+        )?;
+        let mut meta = load_meta_data(&*self.storage)?;
+        meta.kdf = Some(kdf);
+        write_meta_data(&*self.storage, &meta, true)?;
+        self.repo_info = Some(meta);
+        Ok(())
+    }
+
+    fn migrate(&mut self) -> Result<Vec<&'static str>> {
+        let mut meta = load_meta_data(&*self.storage)?;
+        meta.check_readable()?;
+        if meta.version > CURRENT_VERSION {
+            return error(
+                "The repository was written by a newer version of backrub",
+                None,
+            );
+        }
+        let mut added = vec![];
+        for feature in KNOWN_FEATURES {
+            if meta.has_feature(feature) {
+                continue;
+            }
+            log::info!("Migrating repository to {}", feature);
+            match *feature {
+                // Blocks stored under the hash of their encrypted content
+                // stay valid, since block IDs are only compared, never recomputed.
+                FEATURE_KEYED_BLOCK_IDS => {
+                    if self.block_id_key.is_none() {
+                        let key_slots = self.key_slot.is_some();
+                        let key =
+                            create_block_id_key(&*self.storage, self.master_key()?, key_slots)?;
+                        self.block_id_key = Some(key);
+                    }
+                }
+                FEATURE_PASSWORD_SLOTS => {
+                    if self.key_slot.is_none() {
+                        // the master key of these repositories is derived from the password
+                        let password_key = self.master_key()?.clone();
+                        self.convert_to_key_slots(meta.kdf(), password_key)?;
+                    } else {
+                        // finish an interrupted conversion to password slots
+                        remove_password_keys(&*self.storage)?;
+                    }
+                    if meta.kdf.is_none() {
+                        meta.kdf = Some(self.current_kdf()?);
+                    }
+                }
+                FEATURE_PACK_FILES => self.pack_loose_blocks()?,
+                _ => return error("No migration for this repository feature", None),
+            }
+            meta.features.push(feature.to_string());
+            write_meta_data(&*self.storage, &meta, true)?;
+            added.push(*feature);
+        }
+        if meta.version != CURRENT_VERSION {
+            meta.version = CURRENT_VERSION;
+            write_meta_data(&*self.storage, &meta, true)?;
+        }
+        self.repo_info = Some(meta);
+        Ok(added)
+    }
+
+    fn remove_password(&self, slot: &str) -> Result<()> {
+        self.meta()?.check_writable()?;
+        let slots = load_key_slots(&*self.storage, &mut vec![])?;
+        if !slots.iter().any(|(name, _)| name == slot) {
+            return error("There is no password slot with this name", None);
+        }
+        if slots.len() == 1 {
+            return error(
+                "Refusing to remove the last password of the repository",
+                None,
+            );
+        }
+        self.storage
+            .delete(ObjectKind::Key, &key_slot_name(slot))
+            .or_else(|e| error("Could not remove password slot", Some(e.into())))
+    }
+
+    fn list_passwords(&self) -> Result<Vec<(String, u64)>> {
+        Ok(load_key_slots(&*self.storage, &mut vec![])?
+            .into_iter()
+            .map(|(name, slot)| (name, slot.created_at))
+            .collect())
+    }
+
+    fn add_block(&self, data: &[u8]) -> Result<(BackupBlockId, usize)> {
+        self.meta()?.check_writable()?;
+        let id = self.block_id(data)?;
+        if self.has_block(&id)? {
+            log::trace!("Block {} already present in repository", id);
+            return Ok((id, 0));
+        }
+        let mut encoded_block = vec![];
+        let compression = match self.compression {
+            Some(compression) => compression,
+            None => self.meta()?.compression,
+        };
+        encode_keyed_block(&mut encoded_block, data, self.current_key()?, compression)?;
+        let full = {
+            let mut pending = self.pending.borrow_mut();
+            pending.add(&id, &encoded_block);
+            pending.size() >= self.meta()?.pack_size
+        };
+        if full {
+            self.flush()?;
+        }
+        log::debug!("Added block of size {} with id {}", data.len(), id);
+        Ok((id, encoded_block.len()))
+    }
+
+    fn store_entry_list(&self, entries: &EntryList) -> Result<(BackupBlockId, usize)> {
+        let mut output_block = vec![];
+        entries
+            .serialize(&mut Serializer::new(&mut output_block))
+            .or_else(|e| error("Could not serialize entry list", Some(e.into())))?;
+        self.add_block(&output_block)
+    }
+
+    fn load_entry_list(&self, entry_list_id: &BackupBlockId) -> Result<EntryList> {
+        let decoded_block = self
+            .read_block_data(entry_list_id)
+            .or_else(|e| error("Could not read entry list block", Some(e.into())))?;
+        let mut list_deserializer = Deserializer::new(Cursor::new(&decoded_block));
+        Deserialize::deserialize(&mut list_deserializer)
+            .or_else(|e| error("Could not deserialize entry list", Some(e.into())))
+    }
+    fn finish_backup(&self, backup: BackupInstance, overwrite: bool) -> Result<()> {
+        self.meta()?.check_writable()?;
+        // the instance must never refer to blocks, that are not stored yet
+        self.flush()?;
+        if !overwrite && self.storage.exists(ObjectKind::Instance, &backup.name)? {
+            return error("An instance with this name already exists", None);
+        }
+        let mut buffer = vec![];
+        backup
+            .serialize(&mut Serializer::new(&mut buffer))
+            .or_else(|e| error("Could not serialize instance", Some(e.into())))?;
+        self.storage
+            .put(ObjectKind::Instance, &backup.name, &buffer, overwrite)?;
+        log::info!("Finished writing instance {} to repository.", backup.name);
+        Ok(())
+    }
+    fn open_object(&self, id: &BackupBlockId) -> Result<BackupObject> {
+        let decoded_block = self
+            .read_block_data(id)
+            .or_else(|e| error("Could not open object", Some(e.into())))?;
+        Deserialize::deserialize(&mut Deserializer::new(&mut Cursor::new(&decoded_block)))
+            .or_else(|e| error("Could not deserialize object", Some(e.into())))
+    }
+    fn open_object_reader(&self, meta: BackupObject) -> Result<Box<dyn BackupObjectReader>> {
+        Ok(Box::new(BackendObjectReader {
+            storage: self.storage.clone(),
+            verifier: self.block_verifier()?,
+            blocks: meta
+                .blocks
+                .iter()
+                .map(|id| (id.clone(), self.locate(id)))
+                .collect(),
+        }))
+    }
+    fn block_id(&self, data: &[u8]) -> Result<BackupBlockId> {
+        match &self.block_id_key {
+            Some(key) => BackupBlockId::from_bytes(&keyed_hash(key, data)?),
+            None => error(
+                "The repository has no block ID key. Run backrub migrate to create it.",
+                None,
+            ),
+        }
+    }
+    fn has_block(&self, id: &BackupBlockId) -> Result<bool> {
+        for location in self.locate(id) {
+            let exists = match location {
+                BlockLocation::Buffered(_) => true,
+                BlockLocation::Packed(pack, _, _) => {
+                    self.storage.exists(ObjectKind::Pack, &pack)?
+                }
+                BlockLocation::Loose(id) => self.storage.exists(ObjectKind::Block, &id.to_str())?,
+            };
+            if exists {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+    fn read_block(&self, id: &BackupBlockId) -> Result<Vec<u8>> {
+        let (block, _) = self
+            .block_verifier()?
+            .read(&*self.storage, id, &self.locate(id))?;
+        Ok(block)
+    }
+    fn read_block_key_index(&self, id: &BackupBlockId) -> Result<u64> {
+        let header = match self.locate(id).into_iter().next() {
+            Some(BlockLocation::Packed(pack, offset, length)) => self.storage.get_range(
+                ObjectKind::Pack,
+                &pack,
+                offset,
+                std::cmp::min(length, KEYED_BLOCK_HEADER_SIZE),
+            )?,
+            Some(location) => read_location(&*self.storage, &location)?,
+            None => return error("Block is not stored in the repository", None),
+        };
+        keyed_block_key_index(Cursor::new(header))
+    }
+    fn read_block_data(&self, id: &BackupBlockId) -> Result<Vec<u8>> {
+        let (_, data) = self
+            .block_verifier()?
+            .read(&*self.storage, id, &self.locate(id))?;
+        Ok(data)
+    }
+    fn list_blocks(&self) -> Result<Vec<(BackupBlockId, usize)>> {
+        let pack_index = self.pack_index.borrow();
+        let mut blocks: Vec<(BackupBlockId, usize)> = pack_index
+            .iter()
+            .map(|(id, locations)| (id.clone(), locations[0].length as usize))
+            .collect();
+        // blocks of an interrupted migration may be present in both places
+        blocks.extend(
+            list_loose_blocks(&*self.storage)?
+                .into_iter()
+                .filter(|(id, _)| !pack_index.contains_key(id)),
+        );
+        Ok(blocks)
+    }
+    fn list_unreadable_files(&self) -> Vec<(String, String)> {
+        self.unreadable.borrow().clone()
+    }
+    fn remove_blocks(&self, ids: &[BackupBlockId]) -> Result<()> {
+        self.meta()?.check_writable()?;
+        self.flush()?;
+        let mut affected_packs: HashMap<String, HashSet<BackupBlockId>> = HashMap::new();
+        for id in ids {
+            let packs: Option<Vec<String>> = self
+                .pack_index
+                .borrow()
+                .get(id)
+                .map(|locations| locations.iter().map(|l| l.pack.clone()).collect());
+            match packs {
+                Some(packs) => {
+                    for pack in packs {
+                        affected_packs.entry(pack).or_default().insert(id.clone());
+                    }
+                }
+                None => self
+                    .storage
+                    .delete(ObjectKind::Block, &id.to_str())
+                    .or_else(|e| error("Could not remove block", Some(e.into())))?,
+            }
+        }
+        for (pack, removed) in affected_packs {
+            log::debug!("Removing {} blocks from pack {}", removed.len(), pack);
+            self.repack(&pack, &removed)?;
+        }
+        Ok(())
+    }
+    fn flush(&self) -> Result<()> {
+        let pending = self.pending.replace(PackBuilder::default());
+        if pending.is_empty() {
+            return Ok(());
+        }
+        self.write_pack(pending)
+    }
+    fn list_instances(&self) -> Result<Vec<BackupInstance>> {
+        let instances = self
+            .list_instance_names()?
+            .into_iter()
+            .filter_map(|name| self.open_instance_file(&name).ok());
+        Ok(instances.collect())
+    }
+    fn list_instance_names(&self) -> Result<Vec<String>> {
+        let entries = self
+            .storage
+            .list(ObjectKind::Instance)
+            .or_else(|e| error("Could not open backup instances", Some(e.into())))?;
+        Ok(entries.into_iter().map(|(name, _)| name).collect())
+    }
+    fn open_instance(&self, name: &str) -> Result<BackupInstance> {
+        self.open_instance_file(name)
+    }
+    fn remove_instance(&self, name: &str) -> Result<()> {
+        self.meta()?.check_writable()?;
+        self.storage
+            .delete(ObjectKind::Instance, name)
+            .or_else(|e| error("Could not remove instance", Some(e.into())))
+    }
+    fn write_lock(&self, name: &str, data: &[u8], replace: bool) -> Result<()> {
+        self.storage.put(ObjectKind::Lock, name, data, replace)
+    }
+    fn read_lock(&self, name: &str) -> Result<Vec<u8>> {
+        self.storage
+            .get(ObjectKind::Lock, name)
+            .or_else(|e| error("Could not read lock", Some(e.into())))
+    }
+    fn list_locks(&self) -> Result<Vec<String>> {
+        let entries = self
+            .storage
+            .list(ObjectKind::Lock)
+            .or_else(|e| error("Could not read lock storage", Some(e.into())))?;
+        Ok(entries.into_iter().map(|(name, _)| name).collect())
+    }
+    fn remove_lock(&self, name: &str) -> Result<()> {
+        self.storage
+            .delete(ObjectKind::Lock, name)
+            .or_else(|e| error("Could not remove lock", Some(e.into())))
+    }
+    fn keys(&self) -> Result<&HashMap<u64, DataEncryptionKey>> {
+        Ok(&self.keys)
+    }
+    fn set_compression(&mut self, compression: Compression) {
+        self.compression = Some(compression);
+    }
+    fn current_key(&self) -> Result<&(u64, DataEncryptionKey)> {
+        if let Some(key) = &self.current_key {
+            Ok(key)
+        } else {
+            error("current key not loaded", None)
+        }
+    }
+}
+
+/**
+ * Name of the meta data file marking the root of a repository
+ */
+const META_FILE: &str = "backrub";
+
+/**
+ * Access the files of the repository at the given location. Locations of the
+ * form s3://bucket/prefix refer to a bucket of an S3 compatible object store,
+ * sftp://user@host/path to a directory on an SSH server, http://host:port/ to
+ * a repository served by `backrub serve` and everything else is a local path.
+ */
+pub fn backend_for_location(location: &Path) -> Result<Rc<dyn Backend>> {
+    match location.to_str() {
+        Some(url) if url.starts_with("s3://") => {
+            Ok(Rc::new(S3Backend::new(url, S3Config::from_env()?)?))
+        }
+        Some(url) if url.starts_with("sftp://") => Ok(Rc::new(SftpBackend::connect(url)?)),
+        Some(url) if url.starts_with("http://") || url.starts_with("https://") => Ok(Rc::new(
+            HttpBackend::new(url, std::env::var("BACKRUB_HTTP_TOKEN").ok())?,
+        )),
+        _ => Ok(Rc::new(LocalBackend::new(location))),
+    }
+}
+
+fn load_meta_data(storage: &dyn Storage) -> Result<BackrubRepositoryMeta> {
+    let data = storage.get(ObjectKind::Meta, META_FILE).or_else(|e| {
+        error(
+            "Could not open meta file. Is this a backrub repository?",
+            Some(e.into()),
+        )
+    })?;
+    let mut deserializer = Deserializer::new(Cursor::new(data));
+    let meta: BackrubRepositoryMeta = Deserialize::deserialize(&mut deserializer)
+        .or_else(|e| error("Could not deserialize repository meta data", Some(e.into())))?;
+    Ok(meta)
+}
+
+/**
+ * Load the data encryption keys. Keys, that can't be decrypted, are skipped
+ * and added to unreadable, unless no key can be read at all, which means, that
+ * the master key is wrong.
+ */
+fn load_keys(
+    storage: &dyn Storage,
+    master_key: &MasterKey,
+    key_slots: bool,
+    unreadable: &mut Vec<(String, String)>,
+) -> Result<Vec<(u64, DataEncryptionKey)>> {
+    let extension = format!(".{}", data_key_extension(key_slots));
+    let mut keys = vec![];
+    let mut first_error = None;
+    for (name, _) in storage
+        .list(ObjectKind::Key)
+        .or_else(|err| error("Could not read key storage", Some(err.into())))?
+        .into_iter()
+        .filter(|(name, _)| name.ends_with(&extension))
+    {
+        match read_data_encryption_key(storage, &name, master_key) {
+            Ok(key) => keys.push(key),
+            Err(e) => {
+                skip_unreadable(unreadable, format!("key {}", name), &e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match first_error {
+        Some(e) if keys.is_empty() => Err(e),
+        _ => Ok(keys),
+    }
+}
+
+/**
+ * Skip a file, that can't be read. A single damaged or forged file, e.g. one
+ * added by a client of an append-only server, must not make the whole
+ * repository unusable, so it is only reported by check.
+ */
+fn skip_unreadable(unreadable: &mut Vec<(String, String)>, file: String, e: &Error) {
+    log::warn!("Skipping unreadable {}: {}", file, e);
+    unreadable.push((file, e.to_string()));
+}
+
+/**
+ * Load the key used to derive block IDs from the block content.
+ *
+ * Repositories created before keyed block IDs were introduced don't have
+ * such a key. It is only created when migrating them, so that reading
+ * an older repository never writes to it.
+ */
+fn load_block_id_key(
+    storage: &dyn Storage,
+    master_key: &MasterKey,
+    key_slots: bool,
+) -> Result<Option<Vec<u8>>> {
+    let key_name = block_id_key_name(key_slots);
+    if !storage.exists(ObjectKind::Key, key_name)? {
+        return Ok(None);
+    }
+    Ok(Some(read_key_file(storage, key_name, master_key)?.1))
+}
+
+fn read_pack_index(storage: &dyn Storage, pack: &str, keys: &KeySet) -> Result<PackIndex> {
+    let data = storage
+        .get(ObjectKind::PackIndex, pack)
+        .or_else(|e| error("Could not open pack index", Some(e.into())))?;
+    let decoded_index = decode_keyed_block(Cursor::new(data), keys)?;
+    Deserialize::deserialize(&mut Deserializer::new(Cursor::new(&decoded_index)))
+        .or_else(|e| error("Could not deserialize pack index", Some(e.into())))
+}
+
+fn read_location(storage: &dyn Storage, location: &BlockLocation) -> Result<Vec<u8>> {
+    match location {
+        BlockLocation::Loose(id) => storage
+            .get(ObjectKind::Block, &id.to_str())
+            .or_else(|e| error("Could not read block", Some(e.into()))),
+        BlockLocation::Packed(pack, offset, length) => storage
+            .get_range(ObjectKind::Pack, pack, *offset, *length)
+            .or_else(|e| error("Could not read block from pack", Some(e.into()))),
+        BlockLocation::Buffered(block) => Ok(block.clone()),
+    }
+}
+
+/**
+ * List the blocks stored in their own files by older versions
+ */
+fn list_loose_blocks(storage: &dyn Storage) -> Result<Vec<(BackupBlockId, usize)>> {
+    let mut blocks = vec![];
+    let entries = storage
+        .list(ObjectKind::Block)
+        .or_else(|e| error("Could not read block storage", Some(e.into())))?;
+    for (name, size) in entries {
+        match BackupBlockId::from_hex(&name) {
+            Ok(id) => blocks.push((id, size as usize)),
+            Err(_) => log::warn!("Ignoring unexpected file {} in block storage", name),
+        }
+    }
+    Ok(blocks)
+}
+
+fn remove_loose_blocks(storage: &dyn Storage, ids: &[BackupBlockId]) -> Result<()> {
+    for id in ids {
+        storage
+            .delete(ObjectKind::Block, &id.to_str())
+            .or_else(|e| error("Could not remove block", Some(e.into())))?;
+    }
+    Ok(())
+}
+
+/**
+ * Checks blocks read from the storage against their IDs. Otherwise a client
+ * able to add pack files could make the index point to different data.
+ */
+struct BlockVerifier {
+    keys: KeySet,
+    block_id_key: Option<Vec<u8>>,
+}
+
+impl BlockVerifier {
+    /**
+     * Read a block from the first of its locations holding the expected
+     * content. Returns the raw block together with its plain data.
+     */
+    fn read(
+        &self,
+        storage: &dyn Storage,
+        id: &BackupBlockId,
+        locations: &[BlockLocation],
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let mut result = error("Block is not stored in the repository", None);
+        for location in locations {
+            match read_location(storage, location) {
+                Ok(block) => match self.decode(id, &block) {
+                    Ok(data) => return Ok((block, data)),
+                    Err(e) => {
+                        log::warn!("Could not read {} from one of its locations: {}", id, e);
+                        result = Err(e);
+                    }
+                },
+                Err(e) => result = Err(e),
+            }
+        }
+        result
+    }
+
+    /**
+     * Decode a block, making sure it belongs to the ID
+     */
+    fn decode(&self, id: &BackupBlockId, block: &[u8]) -> Result<Vec<u8>> {
+        let data = decode_keyed_block(Cursor::new(block), &self.keys)?;
+        // blocks written before keyed block IDs were introduced are named by
+        // the hash of their encrypted content
+        if Sha3_256::digest(block).as_slice() == id.as_bytes() {
+            return Ok(data);
+        }
+        match &self.block_id_key {
+            Some(key) if keyed_hash(key, &data)? == id.as_bytes() => Ok(data),
+            _ => error("The content of the block does not match its ID", None),
+        }
+    }
+}
+
+pub struct BackendObjectReader {
+    storage: Rc<dyn Storage>,
+    verifier: BlockVerifier,
+    blocks: Vec<(BackupBlockId, Vec<BlockLocation>)>,
+}
+
+impl BackupObjectReader for BackendObjectReader {
+    fn blocks<'a>(&'a self) -> Box<dyn Iterator<Item = Result<Vec<u8>>> + 'a> {
+        Box::new(self.blocks.iter().map(move |(id, locations)| {
+            self.verifier
+                .read(&*self.storage, id, locations)
+                .map(|(_, data)| data)
+        }))
+    }
+}
+
+fn create_backrub_infrastructure(
+    storage: &dyn Storage,
+    master_password: &InputKey,
+    config: &RepositoryConfig,
+) -> Result<()> {
+    log::debug!("Initialize key derivation");
+    let kdf = config.kdf.params()?;
+    let meta = BackrubRepositoryMeta {
+        version: CURRENT_VERSION,
+        title: String::from("backrub backup repository."),
+        salt: kdf.salt.clone(),
+        iterations: std::cmp::min(kdf.iterations, u16::MAX as u32) as u16,
+        id: format!("{:016x}", rand::thread_rng().next_u64()),
+        chunker: config.chunker.clone(),
+        compression: config.compression,
+        kdf: Some(kdf.clone()),
+        features: KNOWN_FEATURES.iter().map(|f| f.to_string()).collect(),
+        pack_size: config.pack_size,
+    };
+    log::debug!("Creating initial data encryption key");
+    let master_key = MasterKey::generate();
+    create_data_encryption_key(storage, &master_key, true, 0)?;
+    log::debug!("Creating block ID key");
+    create_block_id_key(storage, &master_key, true)?;
+    log::debug!("Creating password slot");
+    let password_key = kdf.derive(master_password)?;
+    let slot = format!("{:016x}", rand::thread_rng().next_u64());
+    write_key_slot(storage, &slot, &master_key, &password_key, kdf, false)?;
+    // the marker file is written last, so an interrupted initialization
+    // doesn't leave a repository behind, that looks usable
+    log::debug!("Creating main meta file");
+    write_meta_data(storage, &meta, false)
+}
+
+fn write_meta_data(
+    storage: &dyn Storage,
+    meta: &BackrubRepositoryMeta,
+    overwrite: bool,
+) -> Result<()> {
+    let mut buffer = vec![];
+    meta.serialize(&mut Serializer::new(&mut buffer))
+        .or_else(|e| error("Could not serialize repository marker", Some(e.into())))?;
+    storage.put(ObjectKind::Meta, META_FILE, &buffer, overwrite)
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedDataEncryptionKey {
+    pub created_at: u64,        // UNIX timestamp of the key generation
+    pub key_block: CryptoBlock, // The encrypted data encryption key
+}
+
+/**
+ * Generate a new data encryption key and return its index. The creation time
+ * of the key is never earlier than not_before, so that keys generated within
+ * the same second still have a well-defined order.
+ */
+fn create_data_encryption_key(
+    storage: &dyn Storage,
+    master_key: &MasterKey,
+    key_slots: bool,
+    not_before: u64,
+) -> Result<u64> {
+    let mut key_bytes = [0; 32];
+    rngs::OsRng.fill_bytes(&mut key_bytes);
+    let key_index = rand::thread_rng().next_u64();
+    write_key_file(
+        storage,
+        &data_key_name(key_index, key_slots),
+        master_key,
+        &key_bytes,
+        unix_time()?.max(not_before),
+        false,
+    )?;
+    Ok(key_index)
+}
+
+fn create_block_id_key(
+    storage: &dyn Storage,
+    master_key: &MasterKey,
+    key_slots: bool,
+) -> Result<Vec<u8>> {
+    let mut key_bytes = [0; 32];
+    rngs::OsRng.fill_bytes(&mut key_bytes);
+    write_key_file(
+        storage,
+        block_id_key_name(key_slots),
+        master_key,
+        &key_bytes,
+        unix_time()?,
+        false,
+    )?;
+    Ok(Vec::from(key_bytes))
+}
+
+/**
+ * Extension of the data key files. Keys protected by password slots are named
+ * differently from keys encrypted with the password directly, so that both
+ * can exist side by side while a repository is converted.
+ */
+fn data_key_extension(key_slots: bool) -> &'static str {
+    if key_slots {
+        "dek"
+    } else {
+        "key"
+    }
+}
+
+fn data_key_name(key_index: u64, key_slots: bool) -> String {
+    format!("{:016x}.{}", key_index, data_key_extension(key_slots))
+}
+
+fn block_id_key_name(key_slots: bool) -> &'static str {
+    if key_slots {
+        "block_id.mac"
+    } else {
+        "block_id"
+    }
+}
+
+fn unix_time() -> Result<u64> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .or_else(|e| {
+            error(
+                "Could not get the current time as a UNIX timestamp",
+                Some(e.into()),
+            )
+        })?
+        .as_secs())
+}
+
+/**
+ * Store a key in the key storage, encrypted with the master key
+ */
+fn write_key_file(
+    storage: &dyn Storage,
+    name: &str,
+    master_key: &MasterKey,
+    key_bytes: &[u8],
+    created_at: u64,
+    overwrite: bool,
+) -> Result<()> {
+    let cipher = Cipher::new(&DataEncryptionKey::from(master_key));
+    let encrypted_key_block = cipher.encrypt_block(key_bytes)?;
+    let key_storage = EncryptedDataEncryptionKey {
+        created_at,
+        key_block: encrypted_key_block,
+    };
+    let mut buffer = vec![];
+    key_storage
+        .serialize(&mut Serializer::new(&mut buffer))
+        .or_else(|e| error("Could not store data encryption key", Some(e.into())))?;
+    storage.put(ObjectKind::Key, name, &buffer, overwrite)
+}
+
+/**
+ * A password slot. Each slot holds a copy of the master key, encrypted with
+ * the key derived from one password, so that each password can be changed or
+ * revoked independently.
+ */
+#[derive(Serialize, Deserialize)]
+struct KeySlot {
+    pub created_at: u64,
+    pub kdf: KdfParams,
+    pub key_block: CryptoBlock, // The encrypted master key
+}
+
+fn key_slot_name(slot: &str) -> String {
+    format!("{}.slot", slot)
+}
+
+fn read_key_slot(storage: &dyn Storage, slot: &str) -> Result<KeySlot> {
+    let data = storage
+        .get(ObjectKind::Key, &key_slot_name(slot))
+        .or_else(|e| error("Could not read password slot", Some(e.into())))?;
+    Deserialize::deserialize(&mut Deserializer::new(Cursor::new(data)))
+        .or_else(|e| error("Could not deserialize password slot", Some(e.into())))
+}
+
+/**
+ * Load the password slots. Slots, that can't be read, are skipped and added to
+ * unreadable.
+ */
+fn load_key_slots(
+    storage: &dyn Storage,
+    unreadable: &mut Vec<(String, String)>,
+) -> Result<Vec<(String, KeySlot)>> {
+    let entries = storage
+        .list(ObjectKind::Key)
+        .or_else(|e| error("Could not read key storage", Some(e.into())))?;
+    let mut slots = vec![];
+    for (name, _) in entries {
+        if let Some(slot) = name.strip_suffix(".slot") {
+            match read_key_slot(storage, slot) {
+                Ok(key_slot) => slots.push((slot.to_string(), key_slot)),
+                Err(e) => skip_unreadable(unreadable, format!("password slot {}", slot), &e),
+            }
+        }
+    }
+    Ok(slots)
+}
+
+/**
+ * Find the slot matching the password and decrypt the master key stored in it
+ */
+fn open_key_slot(slots: &[(String, KeySlot)], input_key: &InputKey) -> Result<(String, MasterKey)> {
+    for (name, slot) in slots {
+        let password_key = slot.kdf.derive(input_key)?;
+        if let Ok(master_key) = MasterKey::unwrap(&slot.key_block, &password_key) {
+            log::debug!("Opened password slot {}", name);
+            return Ok((name.clone(), master_key));
+        }
+    }
+    error("The password doesn't match any password slot", None)
+}
+
+fn write_key_slot(
+    storage: &dyn Storage,
+    slot: &str,
+    master_key: &MasterKey,
+    password_key: &MasterKey,
+    kdf: KdfParams,
+    overwrite: bool,
+) -> Result<()> {
+    let key_slot = KeySlot {
+        created_at: unix_time()?,
+        kdf,
+        key_block: master_key.wrap(password_key)?,
+    };
+    let mut buffer = vec![];
+    key_slot
+        .serialize(&mut Serializer::new(&mut buffer))
+        .or_else(|e| error("Could not serialize password slot", Some(e.into())))?;
+    storage.put(ObjectKind::Key, &key_slot_name(slot), &buffer, overwrite)
+}
+
+/**
+ * Remove the keys encrypted with the password directly, which are superseded
+ * by the password slots
+ */
+fn remove_password_keys(storage: &dyn Storage) -> Result<()> {
+    let entries = storage
+        .list(ObjectKind::Key)
+        .or_else(|e| error("Could not read key storage", Some(e.into())))?;
+    let extension = format!(".{}", data_key_extension(false));
+    for (name, _) in entries {
+        if name.ends_with(&extension) || name == block_id_key_name(false) {
+            storage
+                .delete(ObjectKind::Key, &name)
+                .or_else(|e| error("Could not remove key file", Some(e.into())))?;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Load a key from the key storage. Returns the creation time and the decrypted key.
+ */
+fn read_key_file(
+    storage: &dyn Storage,
+    name: &str,
+    master_key: &MasterKey,
+) -> Result<(u64, Vec<u8>)> {
+    let data = storage
+        .get(ObjectKind::Key, name)
+        .or_else(|e| error("Could not read key file", Some(e.into())))?;
+    let cipher = Cipher::new(&DataEncryptionKey::from(master_key));
+    let mut deserializer = Deserializer::new(Cursor::new(data));
+    let encrypted_key: EncryptedDataEncryptionKey = Deserialize::deserialize(&mut deserializer)
+        .or_else(|e| error("Could not deserialize key block", Some(e.into())))?;
+    let key = cipher.decrypt_block(&encrypted_key.key_block)?;
+    Ok((encrypted_key.created_at, key))
+}
+
+fn read_data_encryption_key(
+    storage: &dyn Storage,
+    name: &str,
+    master_key: &MasterKey,
+) -> Result<(u64, DataEncryptionKey)> {
+    let (created_at, key) = read_key_file(storage, name, master_key)?;
+    let key_index = match name.split('.').next() {
+        Some(key_index_str) if !key_index_str.is_empty() => u64::from_str_radix(key_index_str, 16)
+            .or_else(|e| error("Could not parse key index", Some(e.into())))?,
+        _ => return error("Key file name has wrong format", None),
+    };
+    Ok((
+        key_index,
+        DataEncryptionKey {
+            value: key,
+            created_at,
+        },
+    ))
+}
+
+#[derive(Debug)]
+pub struct BackrubBlock {
+    pub id: String,
+    pub data: Vec<u8>,
+}
+
+/**
+ * The definition of a specific backup instance
+ */
+#[derive(Serialize, Deserialize)]
+struct BackrubBackup {
+    name: String,       // the name of the backup. Must be unique.File
+    root_block: String, // the root block ID of the backup tree
+}
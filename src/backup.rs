@@ -14,6 +14,11 @@ pub struct BackupInstance {
     pub name: String,
     pub time: u64,
     pub entry_list_id: BackupBlockId,
+    /**
+     * Free-form tags used to group instances, e.g. for retention policies
+     */
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 impl Display for BackupInstance {
@@ -21,7 +26,11 @@ impl Display for BackupInstance {
         let date: DateTime<Local> = std::time::SystemTime::UNIX_EPOCH
             .add(std::time::Duration::from_secs(self.time))
             .into();
-        write!(fmt, "Name: {}\ncreated at: {}", &self.name, date)
+        write!(fmt, "Name: {}\ncreated at: {}", &self.name, date)?;
+        if !self.tags.is_empty() {
+            write!(fmt, "\ntags: {}", self.tags.join(", "))?;
+        }
+        Ok(())
     }
 }
 
@@ -40,7 +49,7 @@ impl From<Vec<BackupEntry>> for EntryList {
 /**
  * The possible meta data types attached to a backup object
  */
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub enum Meta {
     UnixMeta(UnixFsMeta),
 }
@@ -92,6 +101,27 @@ pub enum EntryType {
      * The file is a (sym)link with the gven link data
      */
     Link(LinkData),
+    /**
+     * The file is a hard link to a file stored earlier in the same instance
+     */
+    HardLink(HardLinkData),
+    /**
+     * The entry is a character device
+     */
+    CharDevice(DeviceData),
+    /**
+     * The entry is a block device
+     */
+    BlockDevice(DeviceData),
+    /**
+     * The entry is a named pipe
+     */
+    Fifo,
+    /**
+     * The entry is a Unix domain socket. Sockets are recorded, but can't be
+     * restored, as they only exist while a process is listening on them.
+     */
+    Socket,
 }
 
 impl Display for EntryType {
@@ -100,6 +130,11 @@ impl Display for EntryType {
             EntryType::File(_) => write!(f, "File"),
             EntryType::Dir => write!(f, "Dir"),
             EntryType::Link(_) => write!(f, "Link"),
+            EntryType::HardLink(_) => write!(f, "HardLink"),
+            EntryType::CharDevice(device) => write!(f, "CharDevice({})", device),
+            EntryType::BlockDevice(device) => write!(f, "BlockDevice({})", device),
+            EntryType::Fifo => write!(f, "Fifo"),
+            EntryType::Socket => write!(f, "Socket"),
         }
     }
 }
@@ -125,3 +160,29 @@ pub struct LinkData {
      */
     pub target: String,
 }
+
+/**
+ * Data associated with a hard link
+ */
+#[derive(Serialize, Deserialize)]
+pub struct HardLinkData {
+    /**
+     * The name of the entry holding the data of the linked file
+     */
+    pub target: String,
+}
+
+/**
+ * Data associated with a device
+ */
+#[derive(Serialize, Deserialize)]
+pub struct DeviceData {
+    pub major: u64,
+    pub minor: u64,
+}
+
+impl Display for DeviceData {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.major, self.minor)
+    }
+}
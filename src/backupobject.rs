@@ -1,3 +1,4 @@
+use crate::errors::Result;
 use crate::repository::BackupBlockId;
 use serde::{Deserialize, Serialize};
 
@@ -7,12 +8,20 @@ use serde::{Deserialize, Serialize};
  */
 pub trait BackupObjectReader {
     /**
-     * The iterator representing the data blocks stored in the object
+     * The iterator representing the plain data of the blocks stored in the
+     * object. Blocks, that cannot be read from the repository, are reported as
+     * errors instead of ending the iteration.
      */
-    fn blocks<'a>(&'a self) -> Box<dyn Iterator<Item = Vec<u8>> + 'a>;
+    fn blocks<'a>(&'a self) -> Box<dyn Iterator<Item = Result<Vec<u8>>> + 'a>;
 }
 
 #[derive(Deserialize, Serialize, Eq, PartialEq)]
 pub struct BackupObject {
     pub blocks: Vec<BackupBlockId>,
+    /**
+     * The plain sizes of the blocks. Objects written by older versions don't
+     * record them.
+     */
+    #[serde(default)]
+    pub sizes: Vec<u64>,
 }
@@ -0,0 +1,261 @@
+use crate::backendrepository::BackendRepository;
+use crate::backup::EntryType;
+use crate::common::read_key;
+use crate::common::KeySource;
+use crate::errors::{error, Result};
+use crate::lock::RepositoryLock;
+use crate::repository::BackupBlockId;
+use crate::repository::Repository;
+use std::collections::HashMap;
+use std::path::Path;
+
+/**
+ * Selection of the blocks, whose data is re-read and verified during a check
+ */
+pub enum DataCheck {
+    /**
+     * Only check the structure of the repository
+     */
+    None,
+    /**
+     * Verify the data of all blocks
+     */
+    All,
+    /**
+     * Verify a random sample of the given percentage of blocks
+     */
+    Percentage(f64),
+    /**
+     * Verify the n-th of m subsets of blocks. Checking all m subsets one after
+     * another (e.g. in consecutive runs) verifies every block exactly once.
+     */
+    Subset(u32, u32),
+}
+
+impl DataCheck {
+    /**
+     * Verify a random sample of blocks, the percentage must be between 0 and 100
+     */
+    pub fn percentage(percentage: f64) -> Result<DataCheck> {
+        if (0.0..=100.0).contains(&percentage) {
+            Ok(DataCheck::Percentage(percentage))
+        } else {
+            error("The percentage of blocks must be between 0 and 100", None)
+        }
+    }
+
+    /**
+     * Parse a subset specification of the form "n/m"
+     */
+    pub fn parse_subset(subset: &str) -> Result<DataCheck> {
+        let mut parts = subset.splitn(2, '/').map(|p| p.parse::<u32>());
+        match (parts.next(), parts.next()) {
+            (Some(Ok(n)), Some(Ok(m))) if n >= 1 && n <= m => Ok(DataCheck::Subset(n, m)),
+            _ => error("Subsets must be given as n/m with 1 <= n <= m", None),
+        }
+    }
+
+    fn selects(&self, id: &BackupBlockId) -> bool {
+        match self {
+            DataCheck::None => false,
+            DataCheck::All => true,
+            DataCheck::Percentage(percentage) => rand::random::<f64>() * 100.0 < *percentage,
+            DataCheck::Subset(n, m) => {
+                let bytes = id.as_bytes();
+                let value = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                value % m == n - 1
+            }
+        }
+    }
+}
+
+/**
+ * A file in a backup instance, that cannot be restored completely
+ */
+pub struct DamagedFile {
+    pub name: String,
+    pub reason: String,
+}
+
+/**
+ * A backup instance with damaged contents
+ */
+pub struct DamagedInstance {
+    pub name: String,
+    /**
+     * The reason, if the instance cannot be read at all
+     */
+    pub reason: Option<String>,
+    pub files: Vec<DamagedFile>,
+}
+
+/**
+ * The result of a repository check
+ */
+pub struct CheckReport {
+    pub checked_instances: usize,
+    pub checked_blocks: usize,
+    pub damaged_blocks: Vec<(BackupBlockId, String)>,
+    pub damaged_instances: Vec<DamagedInstance>,
+    /**
+     * Key, password slot and pack index files, that couldn't be read
+     */
+    pub unreadable_files: Vec<(String, String)>,
+}
+
+impl CheckReport {
+    pub fn is_healthy(&self) -> bool {
+        self.damaged_blocks.is_empty()
+            && self.damaged_instances.is_empty()
+            && self.unreadable_files.is_empty()
+    }
+}
+
+/**
+ * entry point for the check sub-command
+ */
+pub fn check(repository: &Path, key_source: &KeySource, data_check: &DataCheck) -> Result<()> {
+    let mut repo = BackendRepository::for_location(repository)?;
+    let key = read_key(key_source)?;
+    repo.open(key)?;
+    let _lock = RepositoryLock::shared(&repo)?;
+    let report = check_repository(&repo, data_check)?;
+    println!(
+        "Checked {} instances and the data of {} blocks",
+        report.checked_instances, report.checked_blocks
+    );
+    for (file, reason) in &report.unreadable_files {
+        println!("Unreadable {}: {}", file, reason);
+    }
+    for (id, reason) in &report.damaged_blocks {
+        println!("Damaged {}: {}", id, reason);
+    }
+    for instance in &report.damaged_instances {
+        match &instance.reason {
+            Some(reason) => println!("Damaged instance {}: {}", instance.name, reason),
+            None => println!("Damaged instance {}", instance.name),
+        }
+        for file in &instance.files {
+            println!("  {}: {}", file.name, file.reason);
+        }
+    }
+    if report.is_healthy() {
+        println!("No errors found");
+        Ok(())
+    } else {
+        error("The repository is damaged", None)
+    }
+}
+
+/**
+ * Check the integrity of the repository.
+ *
+ * The structural check makes sure, that the entry list, the object descriptors
+ * and the blocks of every instance exist and can be decoded. Depending on the
+ * data check selection, the content of blocks is additionally decrypted and
+ * verified against the block ID.
+ */
+pub fn check_repository(repo: &dyn Repository, data_check: &DataCheck) -> Result<CheckReport> {
+    let mut report = CheckReport {
+        checked_instances: 0,
+        checked_blocks: 0,
+        damaged_blocks: vec![],
+        damaged_instances: vec![],
+        unreadable_files: repo.list_unreadable_files(),
+    };
+    // verify the data first, so that damaged blocks can be attributed to the files using them
+    let mut damaged_blocks = HashMap::new();
+    for (id, _) in repo.list_blocks()? {
+        if data_check.selects(&id) {
+            log::debug!("Verifying data of {}", id);
+            report.checked_blocks += 1;
+            if let Err(reason) = verify_block(repo, &id) {
+                damaged_blocks.insert(id, reason);
+            }
+        }
+    }
+    let mut objects = HashMap::new();
+    for name in repo.list_instance_names()? {
+        log::info!("Checking instance {}", name);
+        report.checked_instances += 1;
+        let mut instance = DamagedInstance {
+            name,
+            reason: None,
+            files: vec![],
+        };
+        if let Err(reason) = check_instance(repo, &mut instance, &damaged_blocks, &mut objects) {
+            instance.reason = Some(reason);
+        }
+        if instance.reason.is_some() || !instance.files.is_empty() {
+            report.damaged_instances.push(instance);
+        }
+    }
+    report.damaged_blocks = damaged_blocks.into_iter().collect();
+    Ok(report)
+}
+
+fn check_instance(
+    repo: &dyn Repository,
+    instance: &mut DamagedInstance,
+    damaged_blocks: &HashMap<BackupBlockId, String>,
+    objects: &mut HashMap<BackupBlockId, Option<String>>,
+) -> std::result::Result<(), String> {
+    let backup = repo
+        .open_instance(&instance.name)
+        .map_err(|e| e.to_string())?;
+    if let Some(reason) = damaged_blocks.get(&backup.entry_list_id) {
+        return Err(format!("entry list is damaged: {}", reason));
+    }
+    let entries = repo
+        .load_entry_list(&backup.entry_list_id)
+        .map_err(|e| e.to_string())?;
+    for entry in entries.0 {
+        if let EntryType::File(file_data) = &entry.entry_type {
+            // objects are shared between instances, so check each of them only once
+            let result = objects
+                .entry(file_data.block_list_id.clone())
+                .or_insert_with(|| check_object(repo, &file_data.block_list_id, damaged_blocks));
+            if let Some(reason) = result {
+                instance.files.push(DamagedFile {
+                    name: entry.name.clone(),
+                    reason: reason.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_object(
+    repo: &dyn Repository,
+    id: &BackupBlockId,
+    damaged_blocks: &HashMap<BackupBlockId, String>,
+) -> Option<String> {
+    if let Some(reason) = damaged_blocks.get(id) {
+        return Some(format!("object descriptor is damaged: {}", reason));
+    }
+    let object = match repo.open_object(id) {
+        Ok(object) => object,
+        Err(e) => return Some(e.to_string()),
+    };
+    for block in &object.blocks {
+        if let Some(reason) = damaged_blocks.get(block) {
+            return Some(format!("{} is damaged: {}", block, reason));
+        }
+        match repo.has_block(block) {
+            Ok(true) => {}
+            Ok(false) => return Some(format!("{} is missing", block)),
+            Err(e) => return Some(e.to_string()),
+        }
+    }
+    None
+}
+
+/**
+ * Reading the data of a block decrypts it and verifies it against the ID
+ */
+fn verify_block(repo: &dyn Repository, id: &BackupBlockId) -> std::result::Result<(), String> {
+    repo.read_block_data(id)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,201 @@
+use crate::errors::{error, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+/**
+ * Parameters of the content-defined chunker.
+ *
+ * These are stored in the repository meta data, so that every client writing
+ * to a repository cuts its data at the same boundaries.
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChunkerParams {
+    /**
+     * The minimum size of a chunk in bytes (except for the last chunk of a stream)
+     */
+    pub min_size: u32,
+    /**
+     * The targeted average size of a chunk in bytes. Must be a power of two.
+     */
+    pub avg_size: u32,
+    /**
+     * The maximum size of a chunk in bytes
+     */
+    pub max_size: u32,
+}
+
+impl Default for ChunkerParams {
+    fn default() -> Self {
+        ChunkerParams {
+            min_size: 512 * 1024,
+            avg_size: 1024 * 1024,
+            max_size: 8 * 1024 * 1024,
+        }
+    }
+}
+
+impl ChunkerParams {
+    /**
+     * Check whether the parameters describe a usable chunker configuration
+     */
+    pub fn validate(&self) -> Result<()> {
+        if self.min_size < 64 {
+            return error("The minimum chunk size must be at least 64 bytes", None);
+        }
+        if !self.avg_size.is_power_of_two() {
+            return error("The average chunk size must be a power of two", None);
+        }
+        if self.min_size > self.avg_size || self.avg_size > self.max_size {
+            return error(
+                "Chunk sizes must satisfy minimum <= average <= maximum",
+                None,
+            );
+        }
+        Ok(())
+    }
+
+    fn masks(&self) -> (u64, u64) {
+        // normalized chunking: use a stricter mask before reaching the average
+        // size and a more lenient one afterwards to narrow the size distribution
+        let bits = self.avg_size.trailing_zeros();
+        (top_bits(bits + 2), top_bits(bits.saturating_sub(2)))
+    }
+}
+
+fn top_bits(count: u32) -> u64 {
+    match count {
+        0 => 0,
+        c if c >= 64 => u64::MAX,
+        c => !0u64 << (64 - c),
+    }
+}
+
+/**
+ * The gear table used by the rolling hash.
+ *
+ * The values are generated with a fixed splitmix64 sequence. They MUST NOT be
+ * changed, as this would change all chunk boundaries and break deduplication
+ * against existing repositories.
+ */
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x6261_636b_7275_6221; // "backrub!"
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/**
+ * Find the end of the first chunk in the given data.
+ *
+ * The data is expected to contain at least max_size bytes, unless it is the
+ * final piece of the stream.
+ */
+pub fn find_boundary(data: &[u8], params: &ChunkerParams) -> usize {
+    let min_size = params.min_size as usize;
+    if data.len() <= min_size {
+        return data.len();
+    }
+    let max_size = std::cmp::min(data.len(), params.max_size as usize);
+    let normal_size = std::cmp::min(max_size, params.avg_size as usize);
+    let (mask_small, mask_large) = params.masks();
+    let mut hash: u64 = 0;
+    let mut i = min_size;
+    while i < normal_size {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & mask_small == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    while i < max_size {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & mask_large == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    max_size
+}
+
+/**
+ * Iterator cutting a byte stream into content-defined chunks
+ */
+pub struct Chunker<R: Read> {
+    reader: R,
+    params: ChunkerParams,
+    /**
+     * Holds twice the maximum chunk size, so that the unread data only has to
+     * be moved to the front after at least one maximum chunk size was consumed
+     */
+    buffer: Vec<u8>,
+    /**
+     * The unread data is buffer[start..end]
+     */
+    start: usize,
+    end: usize,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    pub fn new(reader: R, params: &ChunkerParams) -> Self {
+        Chunker {
+            reader,
+            params: params.clone(),
+            buffer: vec![],
+            start: 0,
+            end: 0,
+            eof: false,
+        }
+    }
+
+    fn fill_buffer(&mut self) -> Result<()> {
+        let max_size = self.params.max_size as usize;
+        if self.eof || self.end - self.start >= max_size {
+            return Ok(());
+        }
+        if self.buffer.is_empty() {
+            self.buffer = vec![0; 2 * max_size];
+        }
+        if self.buffer.len() - self.start < max_size {
+            self.buffer.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+        }
+        while !self.eof && self.end - self.start < max_size {
+            match self.reader.read(&mut self.buffer[self.end..]) {
+                Ok(0) => self.eof = true,
+                Ok(bytes) => self.end += bytes,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return error("Could not read from input", Some(e.into())),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.fill_buffer() {
+            return Some(Err(e));
+        }
+        if self.start == self.end {
+            return None;
+        }
+        let data = &self.buffer[self.start..self.end];
+        let chunk = data[..find_boundary(data, &self.params)].to_vec();
+        self.start += chunk.len();
+        Some(Ok(chunk))
+    }
+}
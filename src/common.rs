@@ -1,12 +1,129 @@
 use super::crypto::InputKey;
 use super::errors::{error, Result};
+use nix::fcntl::{fcntl, FcntlArg};
 use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::mem::ManuallyDrop;
+use std::os::unix::io::FromRawFd;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
 
-pub fn read_key() -> Result<InputKey> {
-    let key = std::env::var("BACKRUB_KEY")
-        .or_else(|_| rpassword::prompt_password_stdout("Repository password: "))
+/**
+ * The places to read the repository password from
+ *
+ * If none of the password sources is set, the password is taken from the
+ * environment or prompted for.
+ */
+#[derive(Default)]
+pub struct KeySource {
+    /**
+     * Read the password from the first line of this file
+     */
+    pub password_file: Option<PathBuf>,
+    /**
+     * Run this shell command and read the password from the first line of its output
+     */
+    pub password_command: Option<String>,
+    /**
+     * Read the password from the first line of this (already open) file descriptor
+     */
+    pub password_fd: Option<i32>,
+    /**
+     * A file, whose content is required in addition to the password
+     */
+    pub keyfile: Option<PathBuf>,
+}
+
+impl KeySource {
+    fn read_password(&self) -> Result<Option<String>> {
+        if let Some(path) = &self.password_file {
+            let file = File::open(path)
+                .or_else(|e| error("Could not open password file", Some(e.into())))?;
+            return first_line(file).map(Some);
+        }
+        if let Some(command) = &self.password_command {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .stdin(Stdio::inherit())
+                .stderr(Stdio::inherit())
+                .output()
+                .or_else(|e| error("Could not run password command", Some(e.into())))?;
+            if !output.status.success() {
+                return error("The password command failed", None);
+            }
+            return first_line(output.stdout.as_slice()).map(Some);
+        }
+        if let Some(fd) = self.password_fd {
+            if fd <= 2 {
+                return error(
+                    "The password can't be read from stdin, stdout or stderr",
+                    None,
+                );
+            }
+            fcntl(fd, FcntlArg::F_GETFD)
+                .or_else(|e| error("Invalid password file descriptor", Some(e.into())))?;
+            // the descriptor belongs to the caller, so it is only borrowed and never closed
+            let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+            return first_line(&*file).map(Some);
+        }
+        Ok(None)
+    }
+
+    fn apply_keyfile(&self, key: InputKey) -> Result<InputKey> {
+        match &self.keyfile {
+            Some(path) => {
+                let content = std::fs::read(path)
+                    .or_else(|e| error("Could not read key file", Some(e.into())))?;
+                if content.is_empty() {
+                    return error("The key file is empty", None);
+                }
+                Ok(key.with_keyfile(&content))
+            }
+            None => Ok(key),
+        }
+    }
+}
+
+fn first_line<R: Read>(input: R) -> Result<String> {
+    let mut line = String::new();
+    BufReader::new(input)
+        .read_line(&mut line)
         .or_else(|e| error("Could not read password.", Some(e.into())))?;
-    Ok(InputKey::from(key.as_bytes()))
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+pub fn read_key(source: &KeySource) -> Result<InputKey> {
+    let key = match source.read_password()? {
+        Some(key) => key,
+        None => std::env::var("BACKRUB_KEY")
+            .or_else(|_| rpassword::prompt_password_stdout("Repository password: "))
+            .or_else(|e| error("Could not read password.", Some(e.into())))?,
+    };
+    source.apply_keyfile(InputKey::from(key.as_bytes()))
+}
+
+/**
+ * Read a new password, e.g. for changing the repository password. Without
+ * a password source or BACKRUB_NEW_KEY being set, the password has to be
+ * entered twice.
+ */
+pub fn read_new_key(source: &KeySource) -> Result<InputKey> {
+    if let Some(key) = source.read_password()? {
+        return source.apply_keyfile(InputKey::from(key.as_bytes()));
+    }
+    if let Ok(key) = std::env::var("BACKRUB_NEW_KEY") {
+        return source.apply_keyfile(InputKey::from(key.as_bytes()));
+    }
+    let key = rpassword::prompt_password_stdout("New repository password: ")
+        .or_else(|e| error("Could not read password.", Some(e.into())))?;
+    let repeated = rpassword::prompt_password_stdout("Repeat new repository password: ")
+        .or_else(|e| error("Could not read password.", Some(e.into())))?;
+    if key != repeated {
+        return error("The passwords don't match.", None);
+    }
+    source.apply_keyfile(InputKey::from(key.as_bytes()))
 }
 
 pub struct ByteSize(pub usize);
@@ -0,0 +1,95 @@
+use crate::errors::{error, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::str::FromStr;
+
+/**
+ * The compression applied to blocks before encrypting them
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    /**
+     * zstd compression with the given compression level
+     */
+    Zstd(i32),
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Lz4 => write!(f, "lz4"),
+            Compression::Zstd(level) => write!(f, "zstd:{}", level),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    /**
+     * Parse a compression setting of the form "none", "lz4", "zstd" or "zstd:<level>"
+     */
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zstd" => Ok(Compression::Zstd(zstd::DEFAULT_COMPRESSION_LEVEL)),
+            _ => match s.strip_prefix("zstd:").map(|level| level.parse::<i32>()) {
+                Some(Ok(level)) if zstd::compression_level_range().contains(&level) => {
+                    Ok(Compression::Zstd(level))
+                }
+                _ => error("Unknown compression setting", None),
+            },
+        }
+    }
+}
+
+/**
+ * The codec a stored block was actually compressed with
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum Codec {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+/**
+ * Compress a block with the given compression setting.
+ *
+ * Blocks that don't shrink are returned unchanged and tagged with Codec::None.
+ */
+pub fn compress(data: &[u8], compression: Compression) -> Result<(Codec, Vec<u8>)> {
+    let (codec, compressed) = match compression {
+        Compression::None => return Ok((Codec::None, Vec::from(data))),
+        Compression::Lz4 => (Codec::Lz4, lz4_flex::compress_prepend_size(data)),
+        Compression::Zstd(level) => (
+            Codec::Zstd,
+            zstd::bulk::compress(data, level)
+                .or_else(|e| error("Could not compress block", Some(e.into())))?,
+        ),
+    };
+    if compressed.len() < data.len() {
+        Ok((codec, compressed))
+    } else {
+        Ok((Codec::None, Vec::from(data)))
+    }
+}
+
+/**
+ * Restore the original data of a block compressed with the given codec
+ */
+pub fn decompress(data: Vec<u8>, codec: Codec) -> Result<Vec<u8>> {
+    match codec {
+        Codec::None => Ok(data),
+        Codec::Lz4 => lz4_flex::decompress_size_prepended(&data)
+            .or_else(|e| error("Could not decompress block", Some(e.into()))),
+        Codec::Zstd => zstd::stream::decode_all(data.as_slice())
+            .or_else(|e| error("Could not decompress block", Some(e.into()))),
+    }
+}
@@ -1,58 +1,110 @@
+use super::backendrepository::BackendRepository;
 use super::backup::BackupInstance;
 use super::common::read_key;
+use super::common::KeySource;
 use super::errors::{error, Result};
-use super::fsrepository::FsRepository;
 use super::fssource::FsSource;
 use super::repository::Repository;
 use crate::backup::BackupEntry;
+use crate::backup::DeviceData;
 use crate::backup::EntryList;
 use crate::backup::EntryType;
 use crate::backup::FileEntryData;
+use crate::backup::HardLinkData;
 use crate::backup::LinkData;
 use crate::backup::Meta;
 use crate::backupobject::BackupObject;
 use crate::blockcache;
 use crate::blockcache::BlockCache;
+use crate::chunker::Chunker;
+use crate::chunker::ChunkerParams;
 use crate::common::human_readable;
 use crate::common::ByteSize;
-use crate::crypto::encode_keyed_block;
-use crate::crypto::DataEncryptionKey;
+use crate::compression::Compression;
 use crate::errors::warning;
 use crate::errors::Error;
 use crate::filter::FilterFn;
-use crate::fssource::FsBlockSource;
+use crate::lock::RepositoryLock;
 use crate::os::unix::get_meta_data;
+use crate::os::unix::hard_link_id;
+use crate::os::unix::stream_meta_data;
+use crate::os::unix::without_access_time;
+use crate::os::unix::UnixFsMeta;
+use crate::os::unix::XattrFilter;
 use crate::regexfilter::regex_direntry_filter;
 use crate::repository::BackupBlockId;
 use rmp_serde::Serializer;
 use serde::Serialize;
 use sha3::{Digest, Sha3_256};
+use std::collections::HashMap;
 use std::path::Path;
 use std::path::PathBuf;
 use std::time::SystemTime;
 
+/**
+ * Options controlling how a backup instance is created
+ */
+#[derive(Default)]
+pub struct BackupOptions {
+    /**
+     * Override the default compression of the repository
+     */
+    pub compression: Option<Compression>,
+    /**
+     * The tags to attach to the new instance
+     */
+    pub tags: Vec<String>,
+    /**
+     * Replace an existing instance with the same name
+     */
+    pub overwrite: bool,
+    /**
+     * Flush directories to disk after writing files to the repository
+     */
+    pub sync_directories: bool,
+    /**
+     * Also store the data read from stdin as a file with this name
+     */
+    pub stdin_name: Option<String>,
+    /**
+     * The extended attributes to back up
+     */
+    pub xattr_filter: XattrFilter,
+}
+
 /**
  * entry point for the create sub-command
  */
 
 pub fn make_backup(
     repository: &str,
+    key_source: &KeySource,
     source_paths: &Vec<String>,
     cache_dir: &Path,
     name: &str,
     exclude: &Option<Vec<String>>,
+    options: &BackupOptions,
 ) -> Result<()> {
-    let mut repo = FsRepository::new(&Path::new(&repository));
-    let key = read_key()?;
+    let mut repo = BackendRepository::for_location(Path::new(repository))?;
+    let key = read_key(key_source)?;
     let start = std::time::SystemTime::now();
     repo.open(key)?;
-    if repo.meta()?.version != 1 {
-        return error("This repository has an unsupported version", None);
+    if let Some(compression) = options.compression {
+        repo.set_compression(compression);
+    }
+    repo.set_sync_directories(options.sync_directories);
+    if !options.overwrite && repo.list_instance_names()?.iter().any(|n| n == name) {
+        return error(
+            "An instance with this name already exists. Use --overwrite to replace it.",
+            None,
+        );
     }
+    let mut lock = RepositoryLock::shared(&repo)?;
+    repo.meta()?.check_writable()?;
     let repo_cache_dir = cache_dir.join(&repo.meta()?.id);
     let cache = blockcache::open(&repo_cache_dir)?;
     cache.ensure()?;
-    let current_key = repo.current_key()?;
+    let chunker_params = &repo.meta()?.chunker;
     let exclude_filter: Option<Box<FilterFn>> = exclude
         .as_ref()
         .map(|e| regex_direntry_filter(&e))
@@ -61,7 +113,12 @@ pub fn make_backup(
         &|obj| exclude_filter.is_none() || !exclude_filter.as_ref().unwrap()(obj);
     let sources: Vec<(PathBuf, FsSource<&dyn Fn(&walkdir::DirEntry) -> bool>)> = source_paths
         .iter()
-        .map(|p| (PathBuf::from(p), FsSource::new(&p, &filter_fn)))
+        .map(|p| {
+            (
+                PathBuf::from(p),
+                FsSource::new(p, &filter_fn, chunker_params),
+            )
+        })
         .collect();
 
     let now = SystemTime::now()
@@ -69,11 +126,29 @@ pub fn make_backup(
         .expect("Could not get current time");
     let mut backup_entries = EntryList::from(vec![]);
     let mut total_size: usize = 0;
+    // the names of the files with more than one link, by device and inode
+    let mut hard_links: HashMap<(u64, u64), String> = HashMap::new();
+    // read stdin first, so the program writing to it doesn't have to wait
+    if let Some(stdin_name) = &options.stdin_name {
+        log::info!("Backing up stdin as {}", stdin_name);
+        let (entry, size) = backup_stdin(stdin_name, chunker_params, &repo, &mut lock)?;
+        backup_entries.0.push(entry);
+        total_size += size;
+    }
     for (path, source) in sources {
         log::debug!("Start reading from source {}", path.to_string_lossy());
         for object in source.objects() {
+            lock.refresh()?;
             log::info!("Backing up {}", object.path().to_string_lossy());
-            let result = backup_object(&path, &source, &repo, &cache, &current_key, object);
+            let result = backup_object(
+                &options.xattr_filter,
+                &source,
+                &repo,
+                &cache,
+                &mut lock,
+                &mut hard_links,
+                object,
+            );
             match result {
                 Ok((entry, size)) => {
                     backup_entries.0.push(entry);
@@ -91,11 +166,15 @@ pub fn make_backup(
     }
     log::info!("Finishing backup");
     let (entry_list_id, size) = repo.store_entry_list(&backup_entries)?;
-    repo.finish_backup(BackupInstance {
-        name: String::from(name),
-        time: now.as_secs(),
-        entry_list_id: entry_list_id,
-    })
+    repo.finish_backup(
+        BackupInstance {
+            name: String::from(name),
+            time: now.as_secs(),
+            entry_list_id,
+            tags: options.tags.clone(),
+        },
+        options.overwrite,
+    )
     .or_else(|e| error("Could not finish backup instance", Some(e.into())))?;
     total_size += size;
     log::info!("Finished backup");
@@ -108,11 +187,12 @@ pub fn make_backup(
 }
 
 fn backup_object<F>(
-    path: &Path,
+    xattr_filter: &XattrFilter,
     source: &FsSource<F>,
-    repo: &FsRepository,
+    repo: &BackendRepository,
     cache: &impl BlockCache,
-    current_key: &(u64, DataEncryptionKey),
+    lock: &mut RepositoryLock,
+    hard_links: &mut HashMap<(u64, u64), String>,
     object: walkdir::DirEntry,
 ) -> Result<(BackupEntry, usize)>
 where
@@ -120,13 +200,23 @@ where
 {
     let file_type = object.file_type();
     if file_type.is_file() {
-        backup_file(path, source, repo, cache, current_key, object)
+        let meta_data = get_meta_data(object.path(), xattr_filter)?;
+        let link_id = hard_link_id(&meta_data);
+        if let Some(target) = link_id.and_then(|id| hard_links.get(&id)) {
+            return backup_hard_link(object, target, meta_data);
+        }
+        let (entry, size) = backup_file(source, repo, cache, lock, object, meta_data)?;
+        // only files, that made it into the backup, may be linked to
+        if let Some(id) = link_id {
+            hard_links.insert(id, entry.name.clone());
+        }
+        Ok((entry, size))
     } else if file_type.is_dir() {
-        backup_dir(path, object)
+        backup_dir(xattr_filter, object)
     } else if file_type.is_symlink() {
-        backup_link(path, object)
+        backup_link(xattr_filter, object)
     } else {
-        warning("Unsupported object type", None)
+        backup_special(xattr_filter, object)
     }
 }
 
@@ -153,21 +243,25 @@ fn get_relative_name<'a>(entry: &'a walkdir::DirEntry, base: &'a Path) -> Result
 }
 
 fn backup_file<F>(
-    path: &Path,
     source: &FsSource<F>,
-    repo: &FsRepository,
+    repo: &BackendRepository,
     cache: &impl BlockCache,
-    current_key: &(u64, DataEncryptionKey),
+    lock: &mut RepositoryLock,
     file: walkdir::DirEntry,
+    source_meta_data: Meta,
 ) -> Result<(BackupEntry, usize)>
 where
     F: Fn(&walkdir::DirEntry) -> bool,
 {
     let source_name = get_name(&file)?;
     let source_name_relative = get_relative_name(&file, &Path::new("/"))?;
-    let source_meta_data = get_meta_data(&file.path())?;
-    let meta_block = get_meta_block(&source_name, &source_meta_data)?;
-    if let Ok(Some(backup_id)) = cache.get_backup_block_id(&meta_block) {
+    let meta_block = get_meta_block(source_name, &without_access_time(&source_meta_data))?;
+    let cached_id = match cache.get_backup_block_id(&meta_block) {
+        // the block may have been pruned from the repository in the meantime
+        Ok(Some(backup_id)) if repo.has_block(&backup_id)? => Some(backup_id),
+        _ => None,
+    };
+    if let Some(backup_id) = cached_id {
         log::trace!("Block cache hit for \"{}\"", source_name);
         Ok((
             BackupEntry {
@@ -181,13 +275,16 @@ where
         ))
     } else {
         log::trace!("Block cache miss for \"{}\"", source_name);
-        let blocks = source.open_entry(&source_name)?;
-        let mut object = BackupObject { blocks: vec![] };
+        let blocks = source.open_entry(source_name)?;
+        let mut object = BackupObject {
+            blocks: vec![],
+            sizes: vec![],
+        };
         let mut size = 0;
-        let block_sum = backup_blocks(blocks, &mut object, &repo, cache, &current_key)?;
+        let block_sum = backup_blocks(blocks, &mut object, repo, lock)?;
         size += block_sum;
         log::debug!("Adding object descriptor to repository");
-        let (id, descriptor_size) = finish_object(&object, &repo, &current_key)?;
+        let (id, descriptor_size) = finish_object(&object, repo)?;
         log::debug!("New object: {}", id);
         size += descriptor_size;
         cache.add_block(&meta_block, &id)?;
@@ -202,19 +299,22 @@ where
     }
 }
 
-fn backup_dir(path: &Path, dir: walkdir::DirEntry) -> Result<(BackupEntry, usize)> {
+fn backup_dir(xattr_filter: &XattrFilter, dir: walkdir::DirEntry) -> Result<(BackupEntry, usize)> {
     let source_name_relative = get_relative_name(&dir, &Path::new("/"))?;
     Ok((
         BackupEntry {
             name: String::from(source_name_relative),
             entry_type: EntryType::Dir,
-            meta: get_meta_data(dir.path())?,
+            meta: get_meta_data(dir.path(), xattr_filter)?,
         },
         0,
     ))
 }
 
-fn backup_link(path: &Path, link: walkdir::DirEntry) -> Result<(BackupEntry, usize)> {
+fn backup_link(
+    xattr_filter: &XattrFilter,
+    link: walkdir::DirEntry,
+) -> Result<(BackupEntry, usize)> {
     let source_name_relative = get_relative_name(&link, &Path::new("/"))?;
     let link_target = std::fs::read_link(link.path())
         .or_else(|e| error("Could not read link target", Some(e.into())))?;
@@ -229,34 +329,125 @@ fn backup_link(path: &Path, link: walkdir::DirEntry) -> Result<(BackupEntry, usi
             entry_type: EntryType::Link(LinkData {
                 target: String::from(link_target_string),
             }),
-            meta: get_meta_data(link.path())?,
+            meta: get_meta_data(link.path(), xattr_filter)?,
         },
         0,
     ))
 }
 
+/**
+ * Record a device, named pipe or socket. They have no content, so the entry
+ * and its meta data describe them completely.
+ */
+fn backup_special(
+    xattr_filter: &XattrFilter,
+    special: walkdir::DirEntry,
+) -> Result<(BackupEntry, usize)> {
+    let source_name_relative = get_relative_name(&special, Path::new("/"))?;
+    let meta = get_meta_data(special.path(), xattr_filter)?;
+    let entry_type = match &meta {
+        Meta::UnixMeta(UnixFsMeta::CharDevice(device)) => EntryType::CharDevice(DeviceData {
+            major: device.major,
+            minor: device.minor,
+        }),
+        Meta::UnixMeta(UnixFsMeta::BlockDevice(device)) => EntryType::BlockDevice(DeviceData {
+            major: device.major,
+            minor: device.minor,
+        }),
+        Meta::UnixMeta(UnixFsMeta::Fifo(_)) => EntryType::Fifo,
+        Meta::UnixMeta(UnixFsMeta::Socket(_)) => EntryType::Socket,
+        _ => return warning("Unsupported object type", None),
+    };
+    Ok((
+        BackupEntry {
+            name: String::from(source_name_relative),
+            entry_type,
+            meta,
+        },
+        0,
+    ))
+}
+
+/**
+ * Record a further link to a file, whose data is already part of the backup
+ */
+fn backup_hard_link(
+    link: walkdir::DirEntry,
+    target: &str,
+    meta: Meta,
+) -> Result<(BackupEntry, usize)> {
+    let source_name_relative = get_relative_name(&link, Path::new("/"))?;
+    log::debug!("{} is a hard link to {}", source_name_relative, target);
+    Ok((
+        BackupEntry {
+            name: String::from(source_name_relative),
+            entry_type: EntryType::HardLink(HardLinkData {
+                target: String::from(target),
+            }),
+            meta,
+        },
+        0,
+    ))
+}
+
+/**
+ * Store the data read from stdin as a file. There is no file to take the meta
+ * data from, so the file belongs to the current user and is only accessible
+ * by them.
+ */
+fn backup_stdin(
+    name: &str,
+    chunker_params: &ChunkerParams,
+    repo: &BackendRepository,
+    lock: &mut RepositoryLock,
+) -> Result<(BackupEntry, usize)> {
+    let name = name.trim_start_matches('/');
+    if name.is_empty()
+        || name
+            .split('/')
+            .any(|c| c.is_empty() || c == "." || c == "..")
+    {
+        return error("Invalid name for the data read from stdin", None);
+    }
+    let stdin = std::io::stdin();
+    let mut object = BackupObject {
+        blocks: vec![],
+        sizes: vec![],
+    };
+    let mut size = backup_blocks(
+        Chunker::new(stdin.lock(), chunker_params),
+        &mut object,
+        repo,
+        lock,
+    )?;
+    let length = object.sizes.iter().sum();
+    let (id, descriptor_size) = finish_object(&object, repo)?;
+    size += descriptor_size;
+    log::info!("Read {} bytes from stdin", ByteSize(length as usize));
+    Ok((
+        BackupEntry {
+            name: String::from(name),
+            entry_type: EntryType::File(FileEntryData { block_list_id: id }),
+            meta: stream_meta_data(length),
+        },
+        size,
+    ))
+}
+
 fn backup_blocks(
-    blocks: FsBlockSource,
+    blocks: impl Iterator<Item = Result<Vec<u8>>>,
     object: &mut BackupObject,
-    repo: &FsRepository,
-    cache: &impl BlockCache,
-    key: &(u64, DataEncryptionKey),
+    repo: &BackendRepository,
+    lock: &mut RepositoryLock,
 ) -> Result<usize> {
     let mut stored_size = 0;
     for block in blocks {
-        let backup_id = if let Ok(Some(backup_block_id)) = cache.get_backup_block_id(&block) {
-            log::trace!("Block cache hit for {}", backup_block_id);
-            backup_block_id
-        } else {
-            let mut output_block = vec![];
-            encode_keyed_block(&mut output_block, &block, &key)?;
-            let (id, size) = repo.add_block(&output_block)?;
-            log::trace!("Block cache miss for {}", id);
-            stored_size += size;
-            cache.add_block(&block, &id)?;
-            id
-        };
-        object.blocks.push(backup_id);
+        let block = block?;
+        lock.refresh()?;
+        let (id, size) = repo.add_block(&block)?;
+        stored_size += size;
+        object.blocks.push(id);
+        object.sizes.push(block.len() as u64);
     }
     log::debug!("Finished copying blocks");
     Ok(stored_size)
@@ -264,17 +455,13 @@ fn backup_blocks(
 
 fn finish_object(
     object: &BackupObject,
-    repo: &FsRepository,
-    current_key: &(u64, DataEncryptionKey),
+    repo: &BackendRepository,
 ) -> Result<(BackupBlockId, usize)> {
     let mut object_buffer = vec![];
     (*object)
         .serialize(&mut Serializer::new(&mut object_buffer))
         .or_else(|e| error("Could not serialize meta data", Some(e.into())))?;
-    let mut storage_buf = vec![];
-    encode_keyed_block(&mut storage_buf, &object_buffer, current_key)?;
-    let (id, size) = repo.add_block(&storage_buf)?;
-    Ok((id, size))
+    repo.add_block(&object_buffer)
 }
 
 fn get_meta_block(path: &str, meta: &Meta) -> Result<Vec<u8>> {
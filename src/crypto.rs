@@ -1,13 +1,19 @@
 use super::errors::{error, Error, Result};
+use crate::compression::{compress, decompress, Codec, Compression};
 use aes_gcm_siv::aead::generic_array::GenericArray;
 use aes_gcm_siv::aead::{Aead, NewAead};
 use aes_gcm_siv::Aes256GcmSiv;
+use hmac::{Hmac, Mac, NewMac};
 use rand::RngCore;
 use rmp_serde::{Deserializer, Serializer};
 use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
 use std::collections::HashMap;
+use std::fmt::Display;
 use std::io::Read;
 use std::io::Write;
+use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Serialize, Deserialize)]
 pub struct CryptoBlock {
@@ -26,6 +32,11 @@ pub type KeySet = HashMap<u64, DataEncryptionKey>;
 pub struct KeyedCryptoBlock {
     pub key_index: u64,
     pub block: CryptoBlock,
+    /**
+     * The codec the block data was compressed with before encryption
+     */
+    #[serde(default)]
+    pub codec: Codec,
 }
 
 pub struct Cipher {
@@ -49,12 +60,12 @@ impl Cipher {
             cipher: Aes256GcmSiv::new(GenericArray::from_slice(&key.value)),
         }
     }
-    pub fn encrypt_block(&self, block: &Vec<u8>) -> Result<CryptoBlock> {
+    pub fn encrypt_block(&self, block: &[u8]) -> Result<CryptoBlock> {
         let mut nonce = [0; 12];
         rand::rngs::OsRng.fill_bytes(&mut nonce);
         let encrypted = self
             .cipher
-            .encrypt(GenericArray::from_slice(&nonce), block.as_ref())
+            .encrypt(GenericArray::from_slice(&nonce), block)
             .or_else(|_e| error("Encrypting block failed", None))?;
         Ok(CryptoBlock {
             data: encrypted,
@@ -74,17 +85,39 @@ impl Cipher {
  * This is to clearly distinguish between user input keys, master keys (used for key encryption)
  * and data encryption keys (used for actually encrypting data)
  */
-pub struct InputKey(Vec<u8>);
+#[derive(Clone)]
+pub struct InputKey {
+    password: Vec<u8>,
+    keyfile: Option<Vec<u8>>,
+}
 
 impl From<&[u8]> for InputKey {
     fn from(key_data: &[u8]) -> Self {
-        Self(Vec::from(key_data))
+        Self {
+            password: Vec::from(key_data),
+            keyfile: None,
+        }
+    }
+}
+
+impl InputKey {
+    /**
+     * Require the content of a key file in addition to the password. The hash
+     * of the file is used as secret input to the key derivation, so the derived
+     * key depends on both.
+     */
+    pub fn with_keyfile(self, keyfile: &[u8]) -> Self {
+        Self {
+            password: self.password,
+            keyfile: Some(Sha3_256::digest(keyfile).to_vec()),
+        }
     }
 }
 
 /**
  * Type for a master key as derived from a user input key
  */
+#[derive(Clone)]
 pub struct MasterKey(Vec<u8>);
 
 impl From<&MasterKey> for DataEncryptionKey {
@@ -99,13 +132,266 @@ impl From<&MasterKey> for DataEncryptionKey {
     }
 }
 
-pub fn derive_key(key: &InputKey, salt: &[u8], iterations: u32) -> Result<MasterKey> {
-    let mut config = argon2::Config::default();
-    config.variant = argon2::Variant::Argon2id;
-    config.time_cost = iterations;
-    argon2::hash_raw(&key.0, &salt, &config)
-        .map(|key| MasterKey(key))
-        .or_else(|e| error("Could not derive master key", Some(e.into())))
+impl MasterKey {
+    /**
+     * Generate a random master key, that isn't derived from a password. Such a
+     * key is stored in the repository wrapped with one or more password keys.
+     */
+    pub fn generate() -> Self {
+        let mut key = vec![0; 32];
+        rand::rngs::OsRng.fill_bytes(&mut key);
+        MasterKey(key)
+    }
+
+    /**
+     * Encrypt this key with another master key
+     */
+    pub fn wrap(&self, wrapping_key: &MasterKey) -> Result<CryptoBlock> {
+        Cipher::new(&DataEncryptionKey::from(wrapping_key)).encrypt_block(&self.0)
+    }
+
+    /**
+     * Decrypt a key encrypted with wrap
+     */
+    pub fn unwrap(block: &CryptoBlock, wrapping_key: &MasterKey) -> Result<MasterKey> {
+        Cipher::new(&DataEncryptionKey::from(wrapping_key))
+            .decrypt_block(block)
+            .map(MasterKey)
+    }
+}
+
+/**
+ * The Argon2 variant used to derive keys from passwords
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum KdfVariant {
+    Argon2d,
+    Argon2i,
+    Argon2id,
+}
+
+impl Display for KdfVariant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KdfVariant::Argon2d => write!(f, "argon2d"),
+            KdfVariant::Argon2i => write!(f, "argon2i"),
+            KdfVariant::Argon2id => write!(f, "argon2id"),
+        }
+    }
+}
+
+impl FromStr for KdfVariant {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "argon2d" => Ok(KdfVariant::Argon2d),
+            "argon2i" => Ok(KdfVariant::Argon2i),
+            "argon2id" => Ok(KdfVariant::Argon2id),
+            _ => error("Unknown key derivation variant", None),
+        }
+    }
+}
+
+impl From<KdfVariant> for argon2::Variant {
+    fn from(variant: KdfVariant) -> Self {
+        match variant {
+            KdfVariant::Argon2d => argon2::Variant::Argon2d,
+            KdfVariant::Argon2i => argon2::Variant::Argon2i,
+            KdfVariant::Argon2id => argon2::Variant::Argon2id,
+        }
+    }
+}
+
+/*
+ * Key derivation parameters of repositories created before they were stored
+ * completely. These are the defaults of the argon2 crate at that time.
+ */
+fn legacy_variant() -> KdfVariant {
+    KdfVariant::Argon2id
+}
+
+fn legacy_memory_kib() -> u32 {
+    4096
+}
+
+fn legacy_lanes() -> u32 {
+    1
+}
+
+/**
+ * Parameters for deriving a key from a password
+ *
+ * All parameters are stored explicitly, so changed defaults of the argon2
+ * crate can't make existing repositories unreadable.
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KdfParams {
+    #[serde(with = "serde_bytes")]
+    pub salt: Vec<u8>,
+    /**
+     * The number of passes over the memory (Argon2 time cost)
+     */
+    pub iterations: u32,
+    #[serde(default = "legacy_variant")]
+    pub variant: KdfVariant,
+    #[serde(default = "legacy_memory_kib")]
+    pub memory_kib: u32,
+    #[serde(default = "legacy_lanes")]
+    pub lanes: u32,
+}
+
+impl Display for KdfParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}, {} KiB memory, {} lanes, {} iterations",
+            self.variant, self.memory_kib, self.lanes, self.iterations
+        )
+    }
+}
+
+impl KdfParams {
+    /**
+     * The parameters used by repositories, that only stored the salt and the
+     * number of iterations
+     */
+    pub fn legacy(salt: &[u8], iterations: u32) -> KdfParams {
+        KdfParams {
+            salt: Vec::from(salt),
+            iterations,
+            variant: legacy_variant(),
+            memory_kib: legacy_memory_kib(),
+            lanes: legacy_lanes(),
+        }
+    }
+
+    /**
+     * Copy the parameters, but use a fresh random salt
+     */
+    pub fn with_new_salt(&self) -> KdfParams {
+        KdfParams {
+            salt: new_salt(),
+            ..self.clone()
+        }
+    }
+
+    pub fn derive(&self, key: &InputKey) -> Result<MasterKey> {
+        let mut config = argon2::Config {
+            variant: self.variant.into(),
+            version: argon2::Version::Version13,
+            mem_cost: self.memory_kib,
+            lanes: self.lanes,
+            time_cost: self.iterations,
+            hash_length: 32,
+            ..Default::default()
+        };
+        if let Some(keyfile) = &key.keyfile {
+            config.secret = keyfile;
+        }
+        argon2::hash_raw(&key.password, &self.salt, &config)
+            .map(MasterKey)
+            .or_else(|e| error("Could not derive master key", Some(e.into())))
+    }
+}
+
+fn new_salt() -> Vec<u8> {
+    let mut salt = vec![0; 16];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    salt
+}
+
+/**
+ * Settings for choosing the key derivation parameters of a new password
+ */
+#[derive(Clone, Debug)]
+pub struct KdfSettings {
+    pub variant: KdfVariant,
+    pub memory_kib: u32,
+    pub lanes: u32,
+    /**
+     * A fixed number of iterations. If not given, the number of iterations is
+     * calibrated, so that deriving a key takes about target_time on this machine.
+     */
+    pub iterations: Option<u32>,
+    pub target_time: Duration,
+}
+
+impl Default for KdfSettings {
+    fn default() -> Self {
+        KdfSettings {
+            variant: KdfVariant::Argon2id,
+            memory_kib: 65536,
+            lanes: 4,
+            iterations: None,
+            target_time: Duration::from_secs(1),
+        }
+    }
+}
+
+impl KdfSettings {
+    /**
+     * Create the key derivation parameters for a new password, calibrating
+     * the number of iterations if required
+     */
+    pub fn params(&self) -> Result<KdfParams> {
+        if self.lanes == 0 || self.memory_kib < 8 * self.lanes {
+            return error(
+                "The key derivation needs at least one lane and 8 KiB of memory per lane",
+                None,
+            );
+        }
+        if self.iterations == Some(0) {
+            return error("The key derivation needs at least one iteration", None);
+        }
+        let mut params = KdfParams {
+            salt: new_salt(),
+            iterations: self.iterations.unwrap_or(1),
+            variant: self.variant,
+            memory_kib: self.memory_kib,
+            lanes: self.lanes,
+        };
+        if self.iterations.is_none() {
+            params.iterations = self.calibrate(&params)?;
+        }
+        log::debug!("Key derivation parameters: {}", params);
+        Ok(params)
+    }
+
+    fn calibrate(&self, params: &KdfParams) -> Result<u32> {
+        log::debug!("Calibrating key derivation function");
+        let test_key = InputKey::from(b"SomeRandomCalibrationKey" as &[u8]);
+        let target = self.target_time.as_secs_f64();
+        let mut test_params = params.clone();
+        loop {
+            let start = std::time::Instant::now();
+            test_params.derive(&test_key)?;
+            let time = start.elapsed().as_secs_f64();
+            if time >= target {
+                return Ok(test_params.iterations);
+            }
+            // estimate the required iterations, but grow at least by one
+            // and at most tenfold per round to stay robust against noise
+            let factor = (target / time.max(0.001)).min(10.0);
+            test_params.iterations = std::cmp::max(
+                test_params.iterations + 1,
+                (test_params.iterations as f64 * factor) as u32,
+            );
+        }
+    }
+}
+
+/**
+ * Calculate the keyed hash of a block of plain data.
+ *
+ * This is used to derive block IDs from the block content without revealing
+ * the content hash to anyone not in possession of the repository keys.
+ */
+pub fn keyed_hash(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let mut mac = Hmac::<Sha3_256>::new_varkey(key)
+        .or_else(|_| error("Invalid block ID key length", None))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
 }
 
 pub fn decode_block<R>(block: R, cipher: &Cipher) -> Result<Vec<u8>>
@@ -131,7 +417,46 @@ where
         is_warning: false,
     })?;
     let cipher = Cipher::new(&key);
-    cipher.decrypt_block(&keyed_block.block)
+    decompress(cipher.decrypt_block(&keyed_block.block)?, keyed_block.codec)
+}
+
+/**
+ * The largest number of bytes a keyed block starts with, before the encrypted
+ * data follows: the array marker of the struct and the key index
+ */
+pub const KEYED_BLOCK_HEADER_SIZE: u64 = 10;
+
+/**
+ * Get the index of the key a keyed block is encrypted with. Only the header of
+ * the block is read, so the rest of it doesn't need to be available and the
+ * block isn't decrypted.
+ */
+pub fn keyed_block_key_index<R>(mut block: R) -> Result<u64>
+where
+    R: Read,
+{
+    let mut header = [0; 2];
+    block
+        .read_exact(&mut header)
+        .or_else(|e| error("Could not read keyed block", Some(e.into())))?;
+    // the struct is serialized as an array of its fields, the first of which
+    // is the key index
+    if !(0x92..=0x9f).contains(&header[0]) {
+        return error("Could not deserialize keyed block", None);
+    }
+    let length = match header[1] {
+        0x00..=0x7f => return Ok(header[1] as u64),
+        0xcc => 1,
+        0xcd => 2,
+        0xce => 4,
+        0xcf => 8,
+        _ => return error("Could not deserialize keyed block", None),
+    };
+    let mut bytes = [0; 8];
+    block
+        .read_exact(&mut bytes[8 - length..])
+        .or_else(|e| error("Could not read keyed block", Some(e.into())))?;
+    Ok(u64::from_be_bytes(bytes))
 }
 
 pub fn encode_block<W>(target: W, block: Vec<u8>, cipher: &Cipher) -> Result<()>
@@ -147,17 +472,20 @@ where
 
 pub fn encode_keyed_block<W>(
     target: W,
-    block: &Vec<u8>,
+    block: &[u8],
     key: &(u64, DataEncryptionKey),
+    compression: Compression,
 ) -> Result<()>
 where
     W: Write,
 {
+    let (codec, compressed_block) = compress(block, compression)?;
     let cipher = Cipher::new(&key.1);
-    let crypto_block = cipher.encrypt_block(block)?;
+    let crypto_block = cipher.encrypt_block(&compressed_block)?;
     let keyed_block = KeyedCryptoBlock {
         key_index: key.0,
         block: crypto_block,
+        codec,
     };
     keyed_block
         .serialize(&mut Serializer::new(target))
@@ -1,6 +1,7 @@
+use crate::chunker::Chunker;
+use crate::chunker::ChunkerParams;
 use crate::errors::error;
 use std::fs::File;
-use std::io::Read;
 use walkdir::DirEntry;
 use walkdir::WalkDir;
 
@@ -10,19 +11,21 @@ where
 {
     path: String,
     filter: &'a F,
+    chunker_params: ChunkerParams,
 }
 
 impl<F> FsSource<'_, F>
 where
     F: Fn(&walkdir::DirEntry) -> bool,
 {
-    pub fn new<'a>(path: &str, filter: &'a F) -> FsSource<'a, F>
+    pub fn new<'a>(path: &str, filter: &'a F, chunker_params: &ChunkerParams) -> FsSource<'a, F>
     where
         F: Fn(&walkdir::DirEntry) -> bool,
     {
         FsSource {
             path: String::from(path),
             filter: filter,
+            chunker_params: chunker_params.clone(),
         }
     }
 
@@ -38,8 +41,9 @@ where
     }
 
     pub fn open_entry(&self, path: &str) -> crate::errors::Result<FsBlockSource> {
+        let file = File::open(path).or_else(|e| error("Could not open entry", Some(e.into())))?;
         Ok(FsBlockSource {
-            file: File::open(&path).or_else(|e| error("Could not open entry", Some(e.into())))?,
+            chunker: Chunker::new(file, &self.chunker_params),
         })
     }
 }
@@ -56,25 +60,17 @@ impl Iterator for FsObjectIterator<'_> {
     }
 }
 
+/**
+ * Source of the data blocks of a single file, cut at content-defined boundaries
+ */
 pub struct FsBlockSource {
-    file: File,
+    chunker: Chunker<File>,
 }
 
 impl Iterator for FsBlockSource {
-    type Item = Vec<u8>;
+    type Item = crate::errors::Result<Vec<u8>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut buf = [0; 1048576];
-        let result = self.file.read(&mut buf);
-        match result {
-            Ok(bytes) => {
-                if bytes > 0 {
-                    Some(buf[..bytes].to_vec())
-                } else {
-                    None
-                }
-            }
-            _ => None,
-        }
+        self.chunker.next()
     }
 }
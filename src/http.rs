@@ -0,0 +1,185 @@
+use crate::backend::Backend;
+use crate::errors::{error, Result};
+use crate::s3::uri_encode;
+use crate::server::CLIENT_HEADER;
+use rand::Rng;
+use std::io::Read;
+use std::time::Duration;
+
+/**
+ * A repository served by `backrub serve` at a location of the form
+ * http://host:port/ (or https:// behind a proxy terminating TLS)
+ */
+pub struct HttpBackend {
+    agent: ureq::Agent,
+    url: String,
+    token: Option<String>,
+    /**
+     * Identifies this client to the server, so that it may remove the locks
+     * it created from an append-only server
+     */
+    client: String,
+}
+
+impl HttpBackend {
+    /**
+     * Create a backend for the given location. The token is sent with every
+     * request, if the server requires one.
+     */
+    pub fn new(location: &str, token: Option<String>) -> Result<HttpBackend> {
+        if !location.starts_with("http://") && !location.starts_with("https://") {
+            return error("HTTP locations must start with http:// or https://", None);
+        }
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(30))
+            .timeout_read(Duration::from_secs(300))
+            .timeout_write(Duration::from_secs(300))
+            .build();
+        Ok(HttpBackend {
+            agent,
+            url: location.trim_end_matches('/').to_string(),
+            token,
+            client: format!("{:032x}", rand::thread_rng().gen::<u128>()),
+        })
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let request = self
+            .agent
+            .request(method, url)
+            .set(CLIENT_HEADER, &self.client);
+        match &self.token {
+            Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.url, uri_encode(path, false))
+    }
+
+    fn dir_url(&self, dir: &str) -> String {
+        if dir.is_empty() {
+            format!("{}/", self.url)
+        } else {
+            format!("{}/", self.url(dir))
+        }
+    }
+
+    fn get(
+        &self,
+        url: &str,
+        range: Option<String>,
+    ) -> std::result::Result<Vec<u8>, Box<ureq::Error>> {
+        let mut request = self.request("GET", url);
+        if let Some(range) = range {
+            request = request.set("Range", &range);
+        }
+        let mut data = vec![];
+        request
+            .call()?
+            .into_reader()
+            .read_to_end(&mut data)
+            .map_err(|e| Box::new(e.into()))?;
+        Ok(data)
+    }
+}
+
+impl Backend for HttpBackend {
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        self.get(&self.url(path), None)
+            .or_else(|e| error("Could not read file from the server", Some(e.into())))
+    }
+    fn read_range(&self, path: &str, offset: u64, length: u64) -> Result<Vec<u8>> {
+        if length == 0 {
+            return Ok(vec![]);
+        }
+        let last = match offset.checked_add(length - 1) {
+            Some(last) => last,
+            None => return error("The range lies outside of the file", None),
+        };
+        let range = format!("bytes={}-{}", offset, last);
+        let data = self
+            .get(&self.url(path), Some(range))
+            .or_else(|e| error("Could not read file from the server", Some(e.into())))?;
+        if data.len() as u64 != length {
+            return error("The server returned an unexpected range of the file", None);
+        }
+        Ok(data)
+    }
+    fn write(&self, path: &str, data: &[u8], overwrite: bool) -> Result<()> {
+        let mut request = self.request("PUT", &self.url(path));
+        if overwrite {
+            request = request.query("overwrite", "true");
+        }
+        match request.send_bytes(data) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(409, _)) => error("The file already exists", None),
+            Err(ureq::Error::Status(403, _)) => error(
+                "The server is append-only and refused to change the file",
+                None,
+            ),
+            Err(ureq::Error::Status(413, _)) => {
+                error("The file is larger than the server accepts", None)
+            }
+            Err(e) => error("Could not write file to the server", Some(e.into())),
+        }
+    }
+    fn exists(&self, path: &str) -> Result<bool> {
+        match self.request("HEAD", &self.url(path)).call() {
+            Ok(_) => Ok(true),
+            Err(ureq::Error::Status(404, _)) => Ok(false),
+            Err(e) => error("Could not query the server", Some(e.into())),
+        }
+    }
+    fn size(&self, path: &str) -> Result<u64> {
+        match self.request("HEAD", &self.url(path)).call() {
+            Ok(response) => match response
+                .header("Content-Length")
+                .and_then(|l| l.parse::<u64>().ok())
+            {
+                Some(size) => Ok(size),
+                None => error("The server didn't report the file size", None),
+            },
+            Err(e) => error("Could not query the server", Some(e.into())),
+        }
+    }
+    /**
+     * Directories are listed by requesting their path with a trailing slash.
+     * The server answers with a line "<size> <name>" for each file.
+     */
+    fn list(&self, dir: &str) -> Result<Vec<(String, u64)>> {
+        let data = self
+            .get(&self.dir_url(dir), None)
+            .or_else(|e| error("Could not list files on the server", Some(e.into())))?;
+        let listing = String::from_utf8(data)
+            .or_else(|e| error("Could not parse file listing", Some(e.into())))?;
+        let mut files = vec![];
+        for line in listing.lines() {
+            match line.split_once(' ') {
+                Some((size, name)) => match size.parse::<u64>() {
+                    Ok(size) => files.push((name.to_string(), size)),
+                    Err(e) => return error("Could not parse file listing", Some(e.into())),
+                },
+                None => return error("Could not parse file listing", None),
+            }
+        }
+        Ok(files)
+    }
+    fn remove(&self, path: &str) -> Result<()> {
+        match self.request("DELETE", &self.url(path)).call() {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(403, _)) => error(
+                "The server is append-only and refused to remove the file",
+                None,
+            ),
+            Err(e) => error("Could not remove file from the server", Some(e.into())),
+        }
+    }
+    fn create_dir(&self, dir: &str) -> Result<()> {
+        self.request("PUT", &self.dir_url(dir))
+            .send_bytes(&[])
+            .map(|_| ())
+            .or_else(|e| error("Could not create directory on the server", Some(e.into())))
+    }
+}
@@ -1,12 +1,13 @@
+use crate::backendrepository::BackendRepository;
 use crate::common::read_key;
+use crate::common::KeySource;
 use crate::errors::Result;
-use crate::fsrepository::FsRepository;
 use crate::repository::Repository;
 use std::path::Path;
 
-pub fn instances(repository: &Path) -> Result<()> {
-    let mut repo = FsRepository::new(&repository);
-    let master_key = read_key()?;
+pub fn instances(repository: &Path, key_source: &KeySource) -> Result<()> {
+    let mut repo = BackendRepository::for_location(repository)?;
+    let master_key = read_key(key_source)?;
     repo.open(master_key)?;
     println!("Opening backup instances...\n");
     for instance in repo.list_instances()? {
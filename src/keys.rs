@@ -0,0 +1,200 @@
+use crate::backendrepository::BackendRepository;
+use crate::common::read_key;
+use crate::common::read_new_key;
+use crate::common::KeySource;
+use crate::crypto::KdfParams;
+use crate::crypto::KdfSettings;
+use crate::errors::Result;
+use crate::lock::RepositoryLock;
+use crate::repository::Repository;
+use chrono::DateTime;
+use chrono::Local;
+use std::collections::HashMap;
+use std::ops::Add;
+use std::path::Path;
+use std::time::Duration;
+
+/**
+ * entry point for the key rotate sub-command
+ */
+pub fn rotate(repository: &Path, key_source: &KeySource) -> Result<()> {
+    let lock_handle = BackendRepository::for_location(repository)?;
+    let _lock = RepositoryLock::exclusive(&lock_handle)?;
+    let mut repo = BackendRepository::for_location(repository)?;
+    let key = read_key(key_source)?;
+    repo.open(key)?;
+    let key_index = repo.add_key()?;
+    println!(
+        "Created key {:016x}. New backups will be encrypted with it.",
+        key_index
+    );
+    Ok(())
+}
+
+/**
+ * entry point for the key list sub-command
+ */
+pub fn list(repository: &Path, key_source: &KeySource) -> Result<()> {
+    let mut repo = BackendRepository::for_location(repository)?;
+    let key = read_key(key_source)?;
+    repo.open(key)?;
+    let _lock = RepositoryLock::shared(&repo)?;
+    let usage = key_usage(&repo)?;
+    let current_index = repo.current_key()?.0;
+    let mut keys: Vec<_> = repo.keys()?.iter().collect();
+    keys.sort_by_key(|(_, key)| key.created_at);
+    for (index, key) in keys {
+        println!(
+            "{:016x}  {}  {:>8} blocks{}",
+            index,
+            format_time(key.created_at),
+            usage.get(index).unwrap_or(&0),
+            if *index == current_index {
+                "  (current)"
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(())
+}
+
+/**
+ * entry point for the key passwd sub-command
+ */
+pub fn passwd(repository: &Path, key_source: &KeySource, new_key_source: &KeySource) -> Result<()> {
+    // locks don't need the keys, so a separate handle can hold the lock,
+    // while the repository itself is modified
+    let lock_handle = BackendRepository::for_location(repository)?;
+    let _lock = RepositoryLock::exclusive(&lock_handle)?;
+    let mut repo = BackendRepository::for_location(repository)?;
+    let key = read_key(key_source)?;
+    repo.open(key)?;
+    let new_key = read_new_key(new_key_source)?;
+    repo.change_password(new_key)?;
+    println!(
+        "Changed the password of slot {}",
+        repo.key_slot().unwrap_or_default()
+    );
+    Ok(())
+}
+
+/**
+ * entry point for the key add-password sub-command
+ */
+pub fn add_password(
+    repository: &Path,
+    key_source: &KeySource,
+    new_key_source: &KeySource,
+) -> Result<()> {
+    let lock_handle = BackendRepository::for_location(repository)?;
+    let _lock = RepositoryLock::exclusive(&lock_handle)?;
+    let mut repo = BackendRepository::for_location(repository)?;
+    let key = read_key(key_source)?;
+    repo.open(key)?;
+    let new_key = read_new_key(new_key_source)?;
+    let slot = repo.add_password(new_key)?;
+    println!("Added password slot {}", slot);
+    Ok(())
+}
+
+/**
+ * entry point for the key kdf-upgrade sub-command
+ */
+pub fn kdf_upgrade(
+    repository: &Path,
+    key_source: &KeySource,
+    settings: &KdfSettings,
+) -> Result<()> {
+    let lock_handle = BackendRepository::for_location(repository)?;
+    let _lock = RepositoryLock::exclusive(&lock_handle)?;
+    let mut repo = BackendRepository::for_location(repository)?;
+    let key = read_key(key_source)?;
+    repo.open(key.clone())?;
+    let current = repo.current_kdf()?;
+    let kdf = settings.params()?;
+    // the work of Argon2 grows with the memory times the number of passes over it
+    let cost = |kdf: &KdfParams| kdf.memory_kib as u64 * kdf.iterations as u64;
+    if kdf.memory_kib < current.memory_kib || cost(&kdf) < cost(&current) {
+        log::warn!(
+            "The new key derivation parameters ({}) may be weaker than the current ones ({})",
+            kdf,
+            current
+        );
+    }
+    repo.upgrade_kdf(key, kdf.clone())?;
+    println!(
+        "Password slot {} now uses {}",
+        repo.key_slot().unwrap_or_default(),
+        kdf
+    );
+    let other_slots = repo.list_passwords()?.len() - 1;
+    if other_slots > 0 {
+        println!(
+            "The {} other password(s) keep their parameters until they are changed with passwd",
+            other_slots
+        );
+    }
+    Ok(())
+}
+
+/**
+ * entry point for the key remove-password sub-command
+ */
+pub fn remove_password(repository: &Path, key_source: &KeySource, slot: &str) -> Result<()> {
+    let lock_handle = BackendRepository::for_location(repository)?;
+    let _lock = RepositoryLock::exclusive(&lock_handle)?;
+    let mut repo = BackendRepository::for_location(repository)?;
+    let key = read_key(key_source)?;
+    repo.open(key)?;
+    if repo.key_slot() == Some(slot) {
+        log::warn!("Removing the password used to open the repository");
+    }
+    repo.remove_password(slot)?;
+    println!("Removed password slot {}", slot);
+    Ok(())
+}
+
+/**
+ * entry point for the key list-passwords sub-command
+ */
+pub fn list_passwords(repository: &Path, key_source: &KeySource) -> Result<()> {
+    let mut repo = BackendRepository::for_location(repository)?;
+    let key = read_key(key_source)?;
+    repo.open(key)?;
+    for (slot, created_at) in repo.list_passwords()? {
+        println!(
+            "{}  {}{}",
+            slot,
+            format_time(created_at),
+            if repo.key_slot() == Some(slot.as_str()) {
+                "  (current)"
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(())
+}
+
+fn format_time(timestamp: u64) -> String {
+    let time: DateTime<Local> = std::time::SystemTime::UNIX_EPOCH
+        .add(Duration::from_secs(timestamp))
+        .into();
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/**
+ * Count the blocks encrypted with each key. Only the headers of the blocks are
+ * read. Blocks, that cannot be read, are not counted.
+ */
+pub fn key_usage(repo: &dyn Repository) -> Result<HashMap<u64, usize>> {
+    let mut usage = HashMap::new();
+    for (id, _) in repo.list_blocks()? {
+        match repo.read_block_key_index(&id) {
+            Ok(key_index) => *usage.entry(key_index).or_insert(0) += 1,
+            Err(e) => log::warn!("Could not read {}: {}", id, e),
+        }
+    }
+    Ok(usage)
+}
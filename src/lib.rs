@@ -1,18 +1,33 @@
+pub mod backend;
+pub mod backendrepository;
 pub mod backup;
 pub mod backupobject;
 pub mod blockcache;
+pub mod check;
+pub mod chunker;
 pub mod common;
+pub mod compression;
 pub mod create;
 pub mod crypto;
 pub mod errors;
 pub mod filter;
-pub mod fsrepository;
 pub mod fssource;
+pub mod http;
 pub mod instances;
+pub mod keys;
+pub mod lock;
+pub mod migrate;
 pub mod os;
+pub mod pack;
 pub mod program;
+pub mod prune;
 pub mod regexfilter;
 pub mod repository;
 pub mod restore;
+pub mod retention;
+pub mod s3;
+pub mod server;
+pub mod sftp;
 pub mod show;
+pub mod storage;
 pub mod types;
@@ -0,0 +1,221 @@
+use crate::backendrepository::BackendRepository;
+use crate::common::read_key;
+use crate::common::KeySource;
+use crate::errors::{error, Result};
+use crate::os::unix::{host_name, process_exists};
+use crate::repository::Repository;
+use rand::RngCore;
+use rmp_serde::{Deserializer, Serializer};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+/**
+ * Locks, that haven't been refreshed for this long, are considered stale
+ */
+pub const STALE_AFTER: Duration = Duration::from_secs(30 * 60);
+
+/**
+ * The interval in which long running operations refresh their locks
+ */
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/**
+ * The content of a lock file
+ */
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LockInfo {
+    pub exclusive: bool,
+    /**
+     * The machine holding the lock
+     */
+    pub host: String,
+    /**
+     * The ID of the process holding the lock
+     */
+    pub pid: u32,
+    /**
+     * UNIX timestamp of the last refresh of the lock
+     */
+    pub time: u64,
+}
+
+impl LockInfo {
+    /**
+     * Check whether the lock is stale, i.e. the process holding it has gone
+     * away or the lock wasn't refreshed for a long time
+     */
+    pub fn is_stale(&self) -> bool {
+        if self.host == host_name() && !process_exists(self.pid) {
+            return true;
+        }
+        now().saturating_sub(self.time) > STALE_AFTER.as_secs()
+    }
+
+    fn conflicts_with(&self, exclusive: bool) -> bool {
+        exclusive || self.exclusive
+    }
+}
+
+impl std::fmt::Display for LockInfo {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            fmt,
+            "{} lock held by PID {} on {} since {}",
+            if self.exclusive {
+                "exclusive"
+            } else {
+                "shared"
+            },
+            self.pid,
+            self.host,
+            chrono::DateTime::<chrono::Local>::from(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(self.time)
+            )
+            .format("%Y-%m-%d %H:%M:%S")
+        )
+    }
+}
+
+/**
+ * A lock on a repository. The lock is released, when it is dropped.
+ *
+ * Any number of shared locks can be held at the same time, while an exclusive
+ * lock can only be taken if no other lock exists.
+ */
+pub struct RepositoryLock<'a> {
+    repo: &'a dyn Repository,
+    name: String,
+    info: LockInfo,
+    refreshed: Instant,
+}
+
+impl<'a> RepositoryLock<'a> {
+    /**
+     * Take a shared lock, e.g. for adding data to or reading from the repository
+     */
+    pub fn shared(repo: &'a dyn Repository) -> Result<RepositoryLock<'a>> {
+        RepositoryLock::acquire(repo, false)
+    }
+
+    /**
+     * Take an exclusive lock, e.g. for removing data from the repository
+     */
+    pub fn exclusive(repo: &'a dyn Repository) -> Result<RepositoryLock<'a>> {
+        RepositoryLock::acquire(repo, true)
+    }
+
+    fn acquire(repo: &'a dyn Repository, exclusive: bool) -> Result<RepositoryLock<'a>> {
+        check_conflicts(repo, exclusive, None)?;
+        let info = LockInfo {
+            exclusive,
+            host: host_name(),
+            pid: std::process::id(),
+            time: now(),
+        };
+        let name = format!("{:016x}", rand::thread_rng().next_u64());
+        repo.write_lock(&name, &serialize(&info)?, false)?;
+        let lock = RepositoryLock {
+            repo,
+            name,
+            info,
+            refreshed: Instant::now(),
+        };
+        // another process may have created a conflicting lock in the meantime.
+        // In this case both give up (the lock is removed on drop).
+        check_conflicts(repo, exclusive, Some(&lock.name))?;
+        Ok(lock)
+    }
+
+    /**
+     * Refresh the timestamp of the lock, so that it isn't considered stale.
+     * Long running operations should call this regularly. The lock is only
+     * written, if the last refresh is some time ago.
+     */
+    pub fn refresh(&mut self) -> Result<()> {
+        if self.refreshed.elapsed() < REFRESH_INTERVAL {
+            return Ok(());
+        }
+        self.info.time = now();
+        self.repo
+            .write_lock(&self.name, &serialize(&self.info)?, true)?;
+        self.refreshed = Instant::now();
+        Ok(())
+    }
+}
+
+impl Drop for RepositoryLock<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.repo.remove_lock(&self.name) {
+            log::warn!("Could not release repository lock {}: {}", self.name, e);
+        }
+    }
+}
+
+/**
+ * Read all locks currently present in the repository
+ */
+pub fn list_locks(repo: &dyn Repository) -> Result<Vec<(String, LockInfo)>> {
+    let mut locks = vec![];
+    for name in repo.list_locks()? {
+        // the lock may have been released since listing them
+        let data = match repo.read_lock(&name) {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+        let info = Deserialize::deserialize(&mut Deserializer::new(Cursor::new(&data)))
+            .or_else(|e| error("Could not read lock", Some(e.into())))?;
+        locks.push((name, info));
+    }
+    Ok(locks)
+}
+
+fn check_conflicts(repo: &dyn Repository, exclusive: bool, own_lock: Option<&str>) -> Result<()> {
+    for (name, info) in list_locks(repo)? {
+        if Some(name.as_str()) == own_lock || !info.conflicts_with(exclusive) {
+            continue;
+        }
+        if info.is_stale() {
+            log::warn!("Ignoring stale {}. Use unlock to remove it.", info);
+            continue;
+        }
+        log::error!("Repository is locked: {}", info);
+        return error("The repository is locked by another process", None);
+    }
+    Ok(())
+}
+
+fn serialize(info: &LockInfo) -> Result<Vec<u8>> {
+    let mut buffer = vec![];
+    info.serialize(&mut Serializer::new(&mut buffer))
+        .or_else(|e| error("Could not serialize lock", Some(e.into())))?;
+    Ok(buffer)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/**
+ * entry point for the unlock sub-command
+ *
+ * Removes all stale locks or, if requested, all locks regardless of their state.
+ */
+pub fn unlock(repository: &Path, key_source: &KeySource, all: bool) -> Result<()> {
+    let mut repo = BackendRepository::for_location(repository)?;
+    let key = read_key(key_source)?;
+    repo.open(key)?;
+    for (name, info) in list_locks(&repo)? {
+        if all || info.is_stale() {
+            repo.remove_lock(&name)?;
+            println!("Removed {}", info);
+        } else {
+            println!("Keeping active {}", info);
+        }
+    }
+    Ok(())
+}
@@ -1,9 +1,26 @@
+use backrub::check;
+use backrub::check::DataCheck;
+use backrub::chunker::ChunkerParams;
+use backrub::common::KeySource;
+use backrub::compression::Compression;
 use backrub::create;
+use backrub::crypto::KdfSettings;
+use backrub::crypto::KdfVariant;
 use backrub::errors::error;
 use backrub::errors::Error;
 use backrub::instances;
+use backrub::keys;
+use backrub::lock;
+use backrub::migrate;
+use backrub::os::unix::XattrFilter;
 use backrub::program;
+use backrub::prune;
+use backrub::repository::RepositoryConfig;
 use backrub::restore;
+use backrub::retention::parse_duration;
+use backrub::retention::RetentionPolicy;
+use backrub::server;
+use backrub::server::ServerConfig;
 use backrub::show;
 use directories::ProjectDirs;
 use std::fs::File;
@@ -11,6 +28,7 @@ use std::io::BufRead;
 use std::io::BufReader;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -21,6 +39,13 @@ enum Opts {
     Instances(InstancesOpts),
     Show(ShowOpts),
     Restore(RestoreOpts),
+    Forget(ForgetOpts),
+    Prune(PruneOpts),
+    Check(CheckOpts),
+    Unlock(UnlockOpts),
+    Key(KeyCommand),
+    Migrate(MigrateOpts),
+    Serve(ServeOpts),
 }
 
 #[derive(Debug, StructOpt)]
@@ -28,6 +53,25 @@ enum Opts {
 struct InitOps {
     /// The path to init as a repository
     repository: String,
+    #[structopt(long)]
+    /// The minimum size of a data block in bytes
+    chunk_min_size: Option<u32>,
+    #[structopt(long)]
+    /// The average size of a data block in bytes (must be a power of two)
+    chunk_avg_size: Option<u32>,
+    #[structopt(long)]
+    /// The maximum size of a data block in bytes
+    chunk_max_size: Option<u32>,
+    #[structopt(long, default_value = "zstd:3")]
+    /// The compression applied to data blocks (none, lz4, zstd or zstd:<level>)
+    compression: Compression,
+    #[structopt(long, default_value = "16777216")]
+    /// The size in bytes, at which blocks are written to a new pack file
+    pack_size: u64,
+    #[structopt(flatten)]
+    kdf: KdfOpts,
+    #[structopt(flatten)]
+    password: PasswordOpts,
 }
 
 #[derive(Debug, StructOpt)]
@@ -45,15 +89,38 @@ struct CreateOpts {
     #[structopt(long)]
     /// load the exclude expressions from a file
     exclude_from: Option<PathBuf>,
-    #[structopt(short, long, required = true)]
+    #[structopt(long)]
+    /// Only back up extended attributes in these namespaces or with these names (e.g. security)
+    xattr_include: Vec<String>,
+    #[structopt(long)]
+    /// Don't back up extended attributes in these namespaces or with these names (e.g. user)
+    xattr_exclude: Vec<String>,
+    #[structopt(short, long, required_unless = "stdin-name")]
     /// The path to backup
     sources: Vec<String>,
+    #[structopt(long)]
+    /// Also back up the data read from stdin as a file with this name (e.g. db.sql)
+    stdin_name: Option<String>,
     #[structopt(short, long)]
     /// The repository to write the backup to
     repository: String,
     #[structopt(short, long)]
     /// The name under which to store the backup
     name: String,
+    #[structopt(long)]
+    /// Override the compression configured for the repository (none, lz4, zstd or zstd:<level>)
+    compression: Option<Compression>,
+    #[structopt(long)]
+    /// Tags to attach to the backup instance
+    tag: Vec<String>,
+    #[structopt(long)]
+    /// Replace an existing backup instance with the same name
+    overwrite: bool,
+    #[structopt(long)]
+    /// Flush directories to disk after every write (safer, but slower)
+    sync_directories: bool,
+    #[structopt(flatten)]
+    password: PasswordOpts,
 }
 
 #[derive(Debug, StructOpt)]
@@ -65,6 +132,8 @@ struct InstancesOpts {
     #[structopt(short, long)]
     /// The repository to list the instances from
     repository: String,
+    #[structopt(flatten)]
+    password: PasswordOpts,
 }
 
 #[derive(Debug, StructOpt)]
@@ -81,6 +150,8 @@ struct ShowOpts {
     #[structopt(short, long)]
     /// The instance to retrieve
     name: String,
+    #[structopt(flatten)]
+    password: PasswordOpts,
 }
 
 #[derive(Debug, StructOpt)]
@@ -95,9 +166,12 @@ struct RestoreOpts {
     #[structopt(short, long)]
     /// The name under which the backup was stored
     name: String,
-    #[structopt(short, long)]
+    #[structopt(short, long, required_unless = "to-stdout")]
     /// The path to restore to
-    target: String,
+    target: Option<String>,
+    #[structopt(long, conflicts_with = "target")]
+    /// Write the content of a single file to stdout instead (select it with --include)
+    to_stdout: bool,
     #[structopt(short, long)]
     /// Filters for the objects to restore.
     ///
@@ -106,6 +180,314 @@ struct RestoreOpts {
     /// filter expressions will be restored to the target. If no filter
     /// is given, all objects will be restored.
     include: Option<Vec<String>>,
+    #[structopt(long)]
+    /// Restore files with unreadable blocks anyway, filling the damaged regions with zeros
+    best_effort: bool,
+    #[structopt(long)]
+    /// Write the list of damaged regions to the given file
+    damage_report: Option<String>,
+    #[structopt(flatten)]
+    password: PasswordOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "forget", about = "Remove backup instances from the repository")]
+struct ForgetOpts {
+    #[structopt(short, long)]
+    /// The repository to remove the instances from
+    repository: String,
+    #[structopt(short, long)]
+    /// The names of the instances to remove
+    name: Vec<String>,
+    #[structopt(long)]
+    /// Only show which instances would be removed
+    dry_run: bool,
+    #[structopt(long)]
+    /// Keep the given number of most recent instances
+    keep_last: Option<usize>,
+    #[structopt(long)]
+    /// Keep the most recent instance for the given number of hours
+    keep_hourly: Option<usize>,
+    #[structopt(long)]
+    /// Keep the most recent instance for the given number of days
+    keep_daily: Option<usize>,
+    #[structopt(long)]
+    /// Keep the most recent instance for the given number of weeks
+    keep_weekly: Option<usize>,
+    #[structopt(long)]
+    /// Keep the most recent instance for the given number of months
+    keep_monthly: Option<usize>,
+    #[structopt(long)]
+    /// Keep the most recent instance for the given number of years
+    keep_yearly: Option<usize>,
+    #[structopt(long)]
+    /// Keep all instances within the given duration (e.g. 7d, 2w, 1y6m) before the most recent one
+    keep_within: Option<String>,
+    #[structopt(long)]
+    /// Only apply the retention policy to instances whose name starts with this prefix
+    prefix: Option<String>,
+    #[structopt(long)]
+    /// Only apply the retention policy to instances with this tag
+    tag: Option<String>,
+    #[structopt(flatten)]
+    password: PasswordOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "prune",
+    about = "Remove data not referenced by any backup instance from the repository"
+)]
+struct PruneOpts {
+    #[structopt(short, long)]
+    /// The repository to prune
+    repository: String,
+    #[structopt(long)]
+    /// Only report how much space would be freed
+    dry_run: bool,
+    #[structopt(flatten)]
+    password: PasswordOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "check", about = "Verify the integrity of the repository")]
+struct CheckOpts {
+    #[structopt(short, long)]
+    /// The repository to check
+    repository: String,
+    #[structopt(long)]
+    /// Decrypt and verify the content of all blocks
+    read_data: bool,
+    #[structopt(long, conflicts_with = "read-data")]
+    /// Decrypt and verify the content of a random sample of the given percentage of blocks
+    read_data_percent: Option<f64>,
+    #[structopt(long, conflicts_with_all = &["read-data", "read-data-percent"])]
+    /// Decrypt and verify the content of the n-th of m subsets of blocks (given as n/m)
+    read_data_subset: Option<String>,
+    #[structopt(flatten)]
+    password: PasswordOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "unlock", about = "Remove stale locks from the repository")]
+struct UnlockOpts {
+    #[structopt(short, long)]
+    /// The repository to unlock
+    repository: String,
+    #[structopt(long)]
+    /// Remove all locks, even the ones held by running processes
+    all: bool,
+    #[structopt(flatten)]
+    password: PasswordOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "migrate",
+    about = "Upgrade the repository to the current format version"
+)]
+struct MigrateOpts {
+    #[structopt(short, long)]
+    /// The repository to upgrade
+    repository: String,
+    #[structopt(flatten)]
+    password: PasswordOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "serve",
+    about = "Give clients access to the repository over HTTP. If BACKRUB_HTTP_TOKEN is set, clients have to send the same token."
+)]
+struct ServeOpts {
+    #[structopt(short, long)]
+    /// The repository to serve
+    repository: String,
+    #[structopt(short, long, default_value = "127.0.0.1:8000")]
+    /// The address to listen on
+    listen: String,
+    #[structopt(long)]
+    /// Only allow adding data, refusing to remove or change existing files
+    append_only: bool,
+    #[structopt(long, default_value = "67108864")]
+    /// The size in bytes of the largest file clients may write
+    max_body_size: u64,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "key", about = "Manage the keys of the repository")]
+enum KeyCommand {
+    Rotate(KeyRotateOpts),
+    List(KeyListOpts),
+    Passwd(KeyPasswdOpts),
+    AddPassword(KeyPasswdOpts),
+    RemovePassword(KeyRemovePasswordOpts),
+    ListPasswords(KeyListPasswordsOpts),
+    KdfUpgrade(KeyKdfUpgradeOpts),
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    about = "Protect the current password with new key derivation parameters, which are also used for new passwords"
+)]
+struct KeyKdfUpgradeOpts {
+    #[structopt(short, long)]
+    /// The repository to change
+    repository: String,
+    #[structopt(flatten)]
+    password: PasswordOpts,
+    #[structopt(flatten)]
+    kdf: KdfOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "List the password slots of the repository")]
+struct KeyListPasswordsOpts {
+    #[structopt(short, long)]
+    /// The repository to list the password slots of
+    repository: String,
+    #[structopt(flatten)]
+    password: PasswordOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    about = "Change the repository password or add a new one. Without a new password source, the new password is read from BACKRUB_NEW_KEY or prompted for."
+)]
+struct KeyPasswdOpts {
+    #[structopt(short, long)]
+    /// The repository to change
+    repository: String,
+    #[structopt(flatten)]
+    password: PasswordOpts,
+    #[structopt(flatten)]
+    new_password: NewPasswordOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Revoke a password of the repository")]
+struct KeyRemovePasswordOpts {
+    #[structopt(short, long)]
+    /// The repository to change
+    repository: String,
+    /// The password slot to remove (see list-passwords)
+    slot: String,
+    #[structopt(flatten)]
+    password: PasswordOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "rotate",
+    about = "Generate a new data encryption key for new backups"
+)]
+struct KeyRotateOpts {
+    #[structopt(short, long)]
+    /// The repository to add the key to
+    repository: String,
+    #[structopt(flatten)]
+    password: PasswordOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "list",
+    about = "List the data encryption keys of the repository"
+)]
+struct KeyListOpts {
+    #[structopt(short, long)]
+    /// The repository to list the keys of
+    repository: String,
+    #[structopt(flatten)]
+    password: PasswordOpts,
+}
+
+#[derive(Debug, StructOpt)]
+struct KdfOpts {
+    #[structopt(long, default_value = "argon2id")]
+    /// The Argon2 variant used to derive keys from passwords (argon2d, argon2i or argon2id)
+    kdf_variant: KdfVariant,
+    #[structopt(long, default_value = "65536")]
+    /// The memory used to derive keys from passwords in KiB
+    kdf_memory: u32,
+    #[structopt(long, default_value = "4")]
+    /// The number of lanes used to derive keys from passwords
+    kdf_lanes: u32,
+    #[structopt(long)]
+    /// The number of iterations used to derive keys from passwords
+    kdf_iterations: Option<u32>,
+    #[structopt(long, conflicts_with = "kdf-iterations")]
+    /// Calibrate the number of iterations, so that unlocking takes this many milliseconds (default: 1000)
+    kdf_target_time: Option<u64>,
+}
+
+impl From<&KdfOpts> for KdfSettings {
+    fn from(opts: &KdfOpts) -> Self {
+        KdfSettings {
+            variant: opts.kdf_variant,
+            memory_kib: opts.kdf_memory,
+            lanes: opts.kdf_lanes,
+            iterations: opts.kdf_iterations,
+            target_time: opts
+                .kdf_target_time
+                .map(Duration::from_millis)
+                .unwrap_or_else(|| KdfSettings::default().target_time),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct PasswordOpts {
+    #[structopt(long)]
+    /// Read the repository password from the first line of this file
+    password_file: Option<PathBuf>,
+    #[structopt(long, conflicts_with = "password-file")]
+    /// Read the repository password from the output of this shell command
+    password_command: Option<String>,
+    #[structopt(long, conflicts_with_all = &["password-file", "password-command"])]
+    /// Read the repository password from this open file descriptor
+    password_fd: Option<i32>,
+    #[structopt(long)]
+    /// A file, whose content is needed in addition to the password to open the repository
+    keyfile: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+struct NewPasswordOpts {
+    #[structopt(long)]
+    /// Read the new password from the first line of this file
+    new_password_file: Option<PathBuf>,
+    #[structopt(long, conflicts_with = "new-password-file")]
+    /// Read the new password from the output of this shell command
+    new_password_command: Option<String>,
+    #[structopt(long, conflicts_with_all = &["new-password-file", "new-password-command"])]
+    /// Read the new password from this open file descriptor
+    new_password_fd: Option<i32>,
+    #[structopt(long)]
+    /// A file, whose content is needed in addition to the new password
+    new_keyfile: Option<PathBuf>,
+}
+
+impl From<&PasswordOpts> for KeySource {
+    fn from(opts: &PasswordOpts) -> Self {
+        KeySource {
+            password_file: opts.password_file.clone(),
+            password_command: opts.password_command.clone(),
+            password_fd: opts.password_fd,
+            keyfile: opts.keyfile.clone(),
+        }
+    }
+}
+
+impl From<&NewPasswordOpts> for KeySource {
+    fn from(opts: &NewPasswordOpts) -> Self {
+        KeySource {
+            password_file: opts.new_password_file.clone(),
+            password_command: opts.new_password_command.clone(),
+            password_fd: opts.new_password_fd,
+            keyfile: opts.new_keyfile.clone(),
+        }
+    }
 }
 
 fn main() -> backrub::errors::Result<()> {
@@ -120,23 +502,183 @@ fn main() -> backrub::errors::Result<()> {
             is_warning: false,
         })?;
     let program_result = match options {
-        Opts::Init(opts) => program::initialize_repository(&opts.repository),
+        Opts::Init(opts) => program::initialize_repository(
+            &opts.repository,
+            &KeySource::from(&opts.password),
+            &repository_config(&opts),
+        ),
         Opts::Create(opts) => create::make_backup(
             &opts.repository,
+            &KeySource::from(&opts.password),
             &opts.sources,
             &cache_dir,
             &opts.name,
             &merge_exclude(&opts.exlude, &opts.exclude_from)?,
+            &create::BackupOptions {
+                compression: opts.compression,
+                tags: opts.tag.clone(),
+                overwrite: opts.overwrite,
+                sync_directories: opts.sync_directories,
+                stdin_name: opts.stdin_name.clone(),
+                xattr_filter: XattrFilter {
+                    include: opts.xattr_include.clone(),
+                    exclude: opts.xattr_exclude.clone(),
+                },
+            },
+        ),
+        Opts::Instances(opts) => instances::instances(
+            &Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+        ),
+        Opts::Show(opts) => show::show(
+            &Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+            &opts.name,
+            opts.contents,
         ),
-        Opts::Instances(opts) => instances::instances(&Path::new(&opts.repository)),
-        Opts::Show(opts) => show::show(&Path::new(&opts.repository), &opts.name, opts.contents),
         Opts::Restore(opts) => {
-            restore::restore_backup(&opts.repository, &opts.target, &opts.include, &opts.name)
+            let options = restore::RestoreOptions {
+                best_effort: opts.best_effort,
+                damage_report: opts.damage_report.as_ref().map(PathBuf::from),
+            };
+            match (&opts.target, opts.to_stdout) {
+                (Some(target), false) => restore::restore_backup(
+                    &opts.repository,
+                    &KeySource::from(&opts.password),
+                    target,
+                    &opts.include,
+                    &opts.name,
+                    &options,
+                ),
+                _ => restore::restore_to_stdout(
+                    &opts.repository,
+                    &KeySource::from(&opts.password),
+                    &opts.include,
+                    &opts.name,
+                    &options,
+                ),
+            }
         }
+        Opts::Forget(opts) => prune::forget(
+            Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+            &opts.name,
+            retention_policy(&opts)?.as_ref(),
+            opts.dry_run,
+        ),
+        Opts::Prune(opts) => prune::prune(
+            Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+            opts.dry_run,
+        ),
+        Opts::Key(KeyCommand::Rotate(opts)) => keys::rotate(
+            Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+        ),
+        Opts::Key(KeyCommand::List(opts)) => keys::list(
+            Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+        ),
+        Opts::Key(KeyCommand::Passwd(opts)) => keys::passwd(
+            Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+            &KeySource::from(&opts.new_password),
+        ),
+        Opts::Key(KeyCommand::AddPassword(opts)) => keys::add_password(
+            Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+            &KeySource::from(&opts.new_password),
+        ),
+        Opts::Key(KeyCommand::RemovePassword(opts)) => keys::remove_password(
+            Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+            &opts.slot,
+        ),
+        Opts::Key(KeyCommand::ListPasswords(opts)) => keys::list_passwords(
+            Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+        ),
+        Opts::Key(KeyCommand::KdfUpgrade(opts)) => keys::kdf_upgrade(
+            Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+            &KdfSettings::from(&opts.kdf),
+        ),
+        Opts::Migrate(opts) => migrate::migrate(
+            Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+        ),
+        Opts::Serve(opts) => server::serve(
+            Path::new(&opts.repository),
+            &opts.listen,
+            ServerConfig {
+                append_only: opts.append_only,
+                token: std::env::var("BACKRUB_HTTP_TOKEN").ok(),
+                max_body_size: opts.max_body_size,
+            },
+        ),
+        Opts::Unlock(opts) => lock::unlock(
+            Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+            opts.all,
+        ),
+        Opts::Check(opts) => check::check(
+            Path::new(&opts.repository),
+            &KeySource::from(&opts.password),
+            &data_check(&opts)?,
+        ),
     };
     program_result
 }
 
+fn repository_config(opts: &InitOps) -> RepositoryConfig {
+    let defaults = ChunkerParams::default();
+    RepositoryConfig {
+        chunker: ChunkerParams {
+            min_size: opts.chunk_min_size.unwrap_or(defaults.min_size),
+            avg_size: opts.chunk_avg_size.unwrap_or(defaults.avg_size),
+            max_size: opts.chunk_max_size.unwrap_or(defaults.max_size),
+        },
+        compression: opts.compression,
+        kdf: KdfSettings::from(&opts.kdf),
+        pack_size: opts.pack_size,
+    }
+}
+
+fn retention_policy(opts: &ForgetOpts) -> backrub::errors::Result<Option<RetentionPolicy>> {
+    let policy = RetentionPolicy {
+        keep_last: opts.keep_last.unwrap_or(0),
+        keep_hourly: opts.keep_hourly.unwrap_or(0),
+        keep_daily: opts.keep_daily.unwrap_or(0),
+        keep_weekly: opts.keep_weekly.unwrap_or(0),
+        keep_monthly: opts.keep_monthly.unwrap_or(0),
+        keep_yearly: opts.keep_yearly.unwrap_or(0),
+        keep_within: opts
+            .keep_within
+            .as_ref()
+            .map(|d| parse_duration(d))
+            .transpose()?,
+        name_prefix: opts.prefix.clone(),
+        tag: opts.tag.clone(),
+    };
+    if policy.is_empty() && policy.name_prefix.is_none() && policy.tag.is_none() {
+        Ok(None)
+    } else {
+        Ok(Some(policy))
+    }
+}
+
+fn data_check(opts: &CheckOpts) -> backrub::errors::Result<DataCheck> {
+    if opts.read_data {
+        Ok(DataCheck::All)
+    } else if let Some(percentage) = opts.read_data_percent {
+        DataCheck::percentage(percentage)
+    } else if let Some(subset) = &opts.read_data_subset {
+        DataCheck::parse_subset(subset)
+    } else {
+        Ok(DataCheck::None)
+    }
+}
+
 fn merge_exclude(
     exclude: &Option<Vec<String>>,
     exclude_from: &Option<PathBuf>,
@@ -0,0 +1,30 @@
+use crate::backendrepository::BackendRepository;
+use crate::common::read_key;
+use crate::common::KeySource;
+use crate::errors::Result;
+use crate::lock::RepositoryLock;
+use crate::repository::Repository;
+use std::path::Path;
+
+/**
+ * entry point for the migrate sub-command
+ */
+pub fn migrate(repository: &Path, key_source: &KeySource) -> Result<()> {
+    let lock_handle = BackendRepository::for_location(repository)?;
+    let _lock = RepositoryLock::exclusive(&lock_handle)?;
+    let mut repo = BackendRepository::for_location(repository)?;
+    let key = read_key(key_source)?;
+    repo.open(key)?;
+    let from = repo.meta()?.version;
+    let added = repo.migrate()?;
+    for feature in &added {
+        println!("Added {}", feature);
+    }
+    let to = repo.meta()?.version;
+    if from == to && added.is_empty() {
+        println!("The repository is up to date (version {})", to);
+    } else {
+        println!("Migrated the repository from version {} to {}", from, to);
+    }
+    Ok(())
+}
@@ -1,17 +1,82 @@
 use crate::backup::Meta;
-use crate::errors::{error, Error, Result};
+use crate::errors::{error, warning, Error, Result};
 use crate::os::unix::Meta::UnixMeta;
-use nix::sys::stat::SFlag;
+use nix::errno::Errno;
+use nix::sys::stat::{Mode, SFlag, UtimensatFlags};
+use nix::sys::time::{TimeSpec, TimeValLike};
 use serde::{Deserialize, Serialize};
+use std::ffi::CString;
 use std::fmt::Display;
 use std::fs::Permissions;
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 
+/**
+ * A point in time with the precision of the file system
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Timestamp {
+    /**
+     * Seconds since the Unix epoch
+     */
+    pub seconds: i64,
+    pub nanoseconds: i64,
+}
+
+impl Timestamp {
+    fn to_timespec(self) -> TimeSpec {
+        TimeSpec::seconds(self.seconds) + TimeSpec::nanoseconds(self.nanoseconds)
+    }
+}
+
+/**
+ * An extended attribute, which also carries POSIX ACLs, file capabilities
+ * and SELinux labels
+ */
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ExtendedAttribute {
+    /**
+     * The full name including the namespace, e.g. "security.capability"
+     */
+    #[serde(with = "serde_bytes")]
+    pub name: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub value: Vec<u8>,
+}
+
+/**
+ * Selects extended attributes by their namespace (e.g. "user" or "security")
+ * or by their full name (e.g. "security.selinux")
+ */
+#[derive(Default, Clone)]
+pub struct XattrFilter {
+    /**
+     * Only attributes matching one of these are selected. If it is empty,
+     * all attributes are.
+     */
+    pub include: Vec<String>,
+    /**
+     * Attributes matching one of these are never selected
+     */
+    pub exclude: Vec<String>,
+}
+
+impl XattrFilter {
+    pub fn matches(&self, name: &[u8]) -> bool {
+        let matches = |filter: &String| {
+            let filter = filter.as_bytes();
+            name.starts_with(filter) && (name.len() == filter.len() || name[filter.len()] == b'.')
+        };
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
 /**
  * basic meta-data supported by most Unix objects
  */
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct UnixCommonMeta {
     /**
      * The user id of the owner of the file. Alternatively used to restore the owner of a file.
@@ -25,12 +90,28 @@ pub struct UnixCommonMeta {
      * The POSIX mode bits (with possibly some extensions)
      */
     pub mode: u32,
+    /**
+     * The time of the last modification. Entries written by older versions
+     * don't record any times.
+     */
+    #[serde(default)]
+    pub mtime: Option<Timestamp>,
+    #[serde(default)]
+    pub atime: Option<Timestamp>,
+    /**
+     * The time of the last change of the inode. It can't be set, so it is
+     * only recorded for information.
+     */
+    #[serde(default)]
+    pub ctime: Option<Timestamp>,
+    #[serde(default)]
+    pub xattrs: Vec<ExtendedAttribute>,
 }
 
 /**
  * basic meta data for files
  */
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct UnixFileMetaData {
     /**
      * Common meta data
@@ -41,24 +122,60 @@ pub struct UnixFileMetaData {
      * the size as present in the backup
      */
     pub size: i64,
+    /**
+     * The device containing the file. Together with the inode it identifies
+     * hard links to the same file.
+     */
+    #[serde(default)]
+    pub device: u64,
+    #[serde(default)]
+    pub inode: u64,
+    /**
+     * The number of hard links to the file
+     */
+    #[serde(default)]
+    pub links: u64,
 }
 
 /**
  * basic meta data for files
  */
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct UnixSymlinkMetaData {
     /**
      * Target the symlink points to
      */
     pub target: String,
+    /**
+     * Owner and times of the link itself. Entries written by older versions
+     * don't record them.
+     */
+    #[serde(default)]
+    pub common: Option<UnixCommonMeta>,
+}
+
+/**
+ * meta data for character and block devices
+ */
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UnixDeviceMetaData {
+    /**
+     * Common meta data
+     */
+    pub common: UnixCommonMeta,
+    pub major: u64,
+    pub minor: u64,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub enum UnixFsMeta {
     File(UnixFileMetaData),
     Dir(UnixCommonMeta),
     Symlink(UnixSymlinkMetaData),
+    CharDevice(UnixDeviceMetaData),
+    BlockDevice(UnixDeviceMetaData),
+    Fifo(UnixCommonMeta),
+    Socket(UnixCommonMeta),
 }
 
 impl Display for UnixFsMeta {
@@ -76,31 +193,71 @@ impl Display for UnixFsMeta {
                 write!(formatter, "Dir(permissions={:o})", permissions.mode)
             }
             UnixFsMeta::Symlink(lmeta) => write!(formatter, "Link({})", lmeta.target),
+            UnixFsMeta::CharDevice(dmeta) => write!(
+                formatter,
+                "CharDevice({}:{}, permissions={:o})",
+                dmeta.major, dmeta.minor, dmeta.common.mode
+            ),
+            UnixFsMeta::BlockDevice(dmeta) => write!(
+                formatter,
+                "BlockDevice({}:{}, permissions={:o})",
+                dmeta.major, dmeta.minor, dmeta.common.mode
+            ),
+            UnixFsMeta::Fifo(permissions) => {
+                write!(formatter, "Fifo(permissions={:o})", permissions.mode)
+            }
+            UnixFsMeta::Socket(permissions) => {
+                write!(formatter, "Socket(permissions={:o})", permissions.mode)
+            }
         }
     }
 }
 
-pub fn get_meta_data(path: &Path) -> Result<Meta> {
+pub fn get_meta_data(path: &Path, xattr_filter: &XattrFilter) -> Result<Meta> {
     log::trace!("Retrieving meta data for {}", path.display());
     let stat =
         nix::sys::stat::lstat(path).or_else(|e| error("Could not stat path", Some(e.into())))?;
-    let sflags = SFlag::from_bits_truncate(stat.st_mode);
-    if sflags.contains(SFlag::S_IFREG) {
+    // missing attributes are no reason to leave out the whole object
+    let xattrs = read_xattrs(path, xattr_filter).unwrap_or_else(|e| {
+        log::warn!("{}: {}", path.display(), e);
+        vec![]
+    });
+    // the file types share bits, so they have to be compared as a whole
+    let file_type = SFlag::from_bits_truncate(stat.st_mode & SFlag::S_IFMT.bits());
+    let common = UnixCommonMeta {
+        uid: stat.st_uid,
+        gid: stat.st_gid,
+        mode: stat.st_mode,
+        mtime: Some(Timestamp {
+            seconds: stat.st_mtime,
+            nanoseconds: stat.st_mtime_nsec,
+        }),
+        atime: Some(Timestamp {
+            seconds: stat.st_atime,
+            nanoseconds: stat.st_atime_nsec,
+        }),
+        ctime: Some(Timestamp {
+            seconds: stat.st_ctime,
+            nanoseconds: stat.st_ctime_nsec,
+        }),
+        xattrs,
+    };
+    let device = |common| UnixDeviceMetaData {
+        common,
+        major: nix::sys::stat::major(stat.st_rdev),
+        minor: nix::sys::stat::minor(stat.st_rdev),
+    };
+    if file_type == SFlag::S_IFREG {
         Ok(UnixMeta(UnixFsMeta::File(UnixFileMetaData {
-            common: UnixCommonMeta {
-                uid: stat.st_uid,
-                gid: stat.st_gid,
-                mode: stat.st_mode,
-            },
+            common,
             size: stat.st_size,
+            device: stat.st_dev as u64,
+            inode: stat.st_ino as u64,
+            links: stat.st_nlink as u64,
         })))
-    } else if sflags.contains(SFlag::S_IFDIR) {
-        Ok(UnixMeta(UnixFsMeta::Dir(UnixCommonMeta {
-            uid: stat.st_uid,
-            gid: stat.st_gid,
-            mode: stat.st_mode,
-        })))
-    } else if sflags.contains(SFlag::S_IFLNK) {
+    } else if file_type == SFlag::S_IFDIR {
+        Ok(UnixMeta(UnixFsMeta::Dir(common)))
+    } else if file_type == SFlag::S_IFLNK {
         let target = std::fs::read_link(path)
             .or_else(|e| error("Could not resolve symlink", Some(e.into())))?;
         let target_str = target.to_str().ok_or(Error {
@@ -110,9 +267,59 @@ pub fn get_meta_data(path: &Path) -> Result<Meta> {
         })?;
         Ok(UnixMeta(UnixFsMeta::Symlink(UnixSymlinkMetaData {
             target: String::from(target_str),
+            common: Some(common),
         })))
+    } else if file_type == SFlag::S_IFCHR {
+        Ok(UnixMeta(UnixFsMeta::CharDevice(device(common))))
+    } else if file_type == SFlag::S_IFBLK {
+        Ok(UnixMeta(UnixFsMeta::BlockDevice(device(common))))
+    } else if file_type == SFlag::S_IFIFO {
+        Ok(UnixMeta(UnixFsMeta::Fifo(common)))
+    } else if file_type == SFlag::S_IFSOCK {
+        Ok(UnixMeta(UnixFsMeta::Socket(common)))
     } else {
-        error("Unsupported object type", None)
+        warning("Unsupported object type", None)
+    }
+}
+
+/**
+ * Meta data for a file, that doesn't exist in the file system, e.g. data read
+ * from a pipe. The file belongs to the current user and only they may access it.
+ */
+pub fn stream_meta_data(size: u64) -> Meta {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .map(|now| Timestamp {
+            seconds: now.as_secs() as i64,
+            nanoseconds: now.subsec_nanos() as i64,
+        })
+        .ok();
+    UnixMeta(UnixFsMeta::File(UnixFileMetaData {
+        common: UnixCommonMeta {
+            uid: nix::unistd::getuid().as_raw(),
+            gid: nix::unistd::getgid().as_raw(),
+            mode: SFlag::S_IFREG.bits() | 0o600,
+            mtime: now,
+            atime: now,
+            ctime: now,
+            xattrs: vec![],
+        },
+        size: size as i64,
+        device: 0,
+        inode: 0,
+        links: 1,
+    }))
+}
+
+/**
+ * The device and inode of a file, that has more than one hard link
+ */
+pub fn hard_link_id(meta: &Meta) -> Option<(u64, u64)> {
+    match meta {
+        UnixMeta(UnixFsMeta::File(metadata)) if metadata.links > 1 => {
+            Some((metadata.device, metadata.inode))
+        }
+        _ => None,
     }
 }
 
@@ -122,21 +329,276 @@ pub fn set_meta_data(path: &Path, meta: &Meta) -> Result<()> {
     match meta {
         UnixMeta(UnixFsMeta::File(metadata)) => set_file_metadata(path, &metadata),
         UnixMeta(UnixFsMeta::Dir(metadata)) => set_common_meta(path, &metadata),
-        UnixMeta(UnixFsMeta::Symlink(_)) => Ok(()),
+        // the permissions of symlinks are meaningless, but their owner and times aren't
+        UnixMeta(UnixFsMeta::Symlink(metadata)) => match &metadata.common {
+            Some(common) => {
+                set_owner(path, common)?;
+                write_xattrs(path, &common.xattrs)?;
+                set_common_times(path, common)
+            }
+            None => Ok(()),
+        },
+        UnixMeta(UnixFsMeta::CharDevice(metadata))
+        | UnixMeta(UnixFsMeta::BlockDevice(metadata)) => set_common_meta(path, &metadata.common),
+        UnixMeta(UnixFsMeta::Fifo(metadata)) => set_common_meta(path, metadata),
+        UnixMeta(UnixFsMeta::Socket(_)) => Ok(()),
+    }
+}
+
+/**
+ * Create a character or block device. Only root is allowed to do this.
+ */
+pub fn create_device(path: &Path, block: bool, major: u64, minor: u64) -> Result<()> {
+    let kind = if block {
+        SFlag::S_IFBLK
+    } else {
+        SFlag::S_IFCHR
+    };
+    nix::sys::stat::mknod(
+        path,
+        kind,
+        Mode::S_IRUSR | Mode::S_IWUSR,
+        nix::sys::stat::makedev(major, minor),
+    )
+    .or_else(|e| error("Could not create device", Some(e.into())))
+}
+
+pub fn create_fifo(path: &Path) -> Result<()> {
+    nix::unistd::mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR)
+        .or_else(|e| error("Could not create named pipe", Some(e.into())))
+}
+
+/**
+ * Rename a file, unless the target already exists. The check and the rename
+ * are a single atomic step, but not every file system supports this.
+ */
+pub fn rename_no_replace(from: &Path, to: &Path) -> std::io::Result<()> {
+    let from = CString::new(from.as_os_str().as_bytes())?;
+    let to = CString::new(to.as_os_str().as_bytes())?;
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            from.as_ptr(),
+            libc::AT_FDCWD,
+            to.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
     }
 }
 
+/**
+ * Whether the program runs with the privileges of root
+ */
+pub fn is_root() -> bool {
+    nix::unistd::geteuid().is_root()
+}
+
 fn set_file_metadata(path: &Path, meta: &UnixFileMetaData) -> Result<()> {
     set_common_meta(path, &meta.common)
 }
 
 fn set_common_meta(path: &Path, meta: &UnixCommonMeta) -> Result<()> {
+    // changing the owner clears the setuid and setgid bits as well as file
+    // capabilities, so the attributes and permissions come afterwards. The
+    // attributes are written first, since a read-only mode would prevent it.
+    set_owner(path, meta)?;
+    write_xattrs(path, &meta.xattrs)?;
     std::fs::set_permissions(path, Permissions::from_mode(meta.mode))
         .or_else(|e| error("Could not set permissions", Some(e.into())))?;
-    nix::unistd::chown(
+    set_common_times(path, meta)
+}
+
+/**
+ * Set the owner of the object itself, even if it is a symlink
+ */
+fn set_owner(path: &Path, meta: &UnixCommonMeta) -> Result<()> {
+    nix::unistd::fchownat(
+        None,
         path,
         Some(nix::unistd::Uid::from_raw(meta.uid)),
         Some(nix::unistd::Gid::from_raw(meta.gid)),
+        nix::unistd::FchownatFlags::NoFollowSymlink,
     )
     .or_else(|e| error("Could not set file ownership", Some(e.into())))
 }
+
+fn c_path(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .or_else(|e| error("Path contains a NUL byte", Some(e.into())))
+}
+
+/**
+ * Call a function of the xattr family, that reports the required size of
+ * the buffer when called without one
+ */
+fn xattr_call(call: impl Fn(*mut u8, usize) -> isize) -> std::result::Result<Vec<u8>, Errno> {
+    loop {
+        let size = call(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(Errno::last());
+        }
+        let mut buffer = vec![0u8; size as usize];
+        let length = call(buffer.as_mut_ptr(), buffer.len());
+        if length >= 0 {
+            buffer.truncate(length as usize);
+            return Ok(buffer);
+        }
+        // the data grew between both calls
+        if Errno::last() != Errno::ERANGE {
+            return Err(Errno::last());
+        }
+    }
+}
+
+/**
+ * Read the extended attributes of the object itself, even if it is a
+ * symlink
+ */
+fn read_xattrs(path: &Path, filter: &XattrFilter) -> Result<Vec<ExtendedAttribute>> {
+    let c_path = c_path(path)?;
+    let names = match xattr_call(|buffer, size| unsafe {
+        libc::llistxattr(c_path.as_ptr(), buffer as *mut libc::c_char, size)
+    }) {
+        Ok(names) => names,
+        Err(Errno::EOPNOTSUPP) => return Ok(vec![]),
+        Err(e) => return error("Could not list extended attributes", Some(e.into())),
+    };
+    let mut attributes = vec![];
+    for name in names.split(|c| *c == 0).filter(|name| !name.is_empty()) {
+        if !filter.matches(name) {
+            continue;
+        }
+        // the name was delimited by NUL bytes, so it can't contain one
+        let c_name = CString::new(name).unwrap();
+        match xattr_call(|buffer, size| unsafe {
+            libc::lgetxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                buffer as *mut libc::c_void,
+                size,
+            )
+        }) {
+            Ok(value) => attributes.push(ExtendedAttribute {
+                name: name.to_vec(),
+                value,
+            }),
+            // the attribute was removed in the meantime
+            Err(Errno::ENODATA) => {}
+            Err(e) => return error("Could not read extended attribute", Some(e.into())),
+        }
+    }
+    attributes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(attributes)
+}
+
+/**
+ * Set the extended attributes of the object itself, even if it is a symlink.
+ * Attributes, that the file system doesn't support or that need privileges
+ * the program doesn't have, are skipped with a warning.
+ */
+fn write_xattrs(path: &Path, attributes: &[ExtendedAttribute]) -> Result<()> {
+    if attributes.is_empty() {
+        return Ok(());
+    }
+    let c_path = c_path(path)?;
+    for attribute in attributes {
+        let c_name = CString::new(attribute.name.clone())
+            .or_else(|e| error("Invalid name of extended attribute", Some(e.into())))?;
+        let result = unsafe {
+            libc::lsetxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                attribute.value.as_ptr() as *const libc::c_void,
+                attribute.value.len(),
+                0,
+            )
+        };
+        if result == 0 {
+            continue;
+        }
+        // ENOTSUP is the same as EOPNOTSUPP on Linux
+        match Errno::last() {
+            errno @ Errno::EOPNOTSUPP | errno @ Errno::EPERM | errno @ Errno::EACCES => log::warn!(
+                "Could not set extended attribute {} of {}: {}",
+                String::from_utf8_lossy(&attribute.name),
+                path.display(),
+                errno
+            ),
+            errno => return error("Could not set extended attribute", Some(errno.into())),
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Set the access and modification times of the object itself, even if it is
+ * a symlink. The change time is always set by the system.
+ */
+fn set_common_times(path: &Path, meta: &UnixCommonMeta) -> Result<()> {
+    let (mtime, atime) = match (meta.mtime, meta.atime) {
+        (Some(mtime), Some(atime)) => (mtime, atime),
+        (Some(mtime), None) => (mtime, mtime),
+        _ => return Ok(()),
+    };
+    nix::sys::stat::utimensat(
+        None,
+        path,
+        &atime.to_timespec(),
+        &mtime.to_timespec(),
+        UtimensatFlags::NoFollowSymlink,
+    )
+    .or_else(|e| error("Could not set file times", Some(e.into())))
+}
+
+fn common_meta(meta: &mut Meta) -> Option<&mut UnixCommonMeta> {
+    match meta {
+        UnixMeta(UnixFsMeta::File(metadata)) => Some(&mut metadata.common),
+        UnixMeta(UnixFsMeta::Dir(metadata)) => Some(metadata),
+        UnixMeta(UnixFsMeta::Symlink(metadata)) => metadata.common.as_mut(),
+        UnixMeta(UnixFsMeta::CharDevice(metadata))
+        | UnixMeta(UnixFsMeta::BlockDevice(metadata)) => Some(&mut metadata.common),
+        UnixMeta(UnixFsMeta::Fifo(metadata)) | UnixMeta(UnixFsMeta::Socket(metadata)) => {
+            Some(metadata)
+        }
+    }
+}
+
+/**
+ * The meta data without the access time, which changes whenever the object
+ * is read, e.g. by the previous backup
+ */
+pub fn without_access_time(meta: &Meta) -> Meta {
+    let mut meta = meta.clone();
+    if let Some(common) = common_meta(&mut meta) {
+        common.atime = None;
+    }
+    meta
+}
+
+/**
+ * The name of the machine the program is running on
+ */
+pub fn host_name() -> String {
+    let mut buffer = [0u8; 256];
+    match nix::unistd::gethostname(&mut buffer) {
+        Ok(name) => name.to_string_lossy().into_owned(),
+        Err(_) => String::from("unknown"),
+    }
+}
+
+/**
+ * Check whether a process with the given ID is running on this machine
+ */
+pub fn process_exists(pid: u32) -> bool {
+    match nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid as i32), None) {
+        Ok(_) => true,
+        // the process exists, but belongs to another user
+        Err(nix::Error::Sys(nix::errno::Errno::EPERM)) => true,
+        Err(_) => false,
+    }
+}
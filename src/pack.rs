@@ -0,0 +1,81 @@
+use crate::repository::BackupBlockId;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::collections::HashMap;
+
+/**
+ * The default size, at which a pack file is finished and written to the repository
+ */
+pub const DEFAULT_PACK_SIZE: u64 = 16 * 1024 * 1024;
+
+/**
+ * The position of an (encrypted) block inside of a pack file
+ */
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PackedBlock {
+    pub id: BackupBlockId,
+    pub offset: u64,
+    pub length: u64,
+}
+
+/**
+ * The list of blocks contained in a pack file. It is stored encrypted
+ * under the name of the pack file in the index directory of the repository.
+ */
+#[derive(Serialize, Deserialize)]
+pub struct PackIndex {
+    pub blocks: Vec<PackedBlock>,
+}
+
+/**
+ * Collects encrypted blocks in memory, until there are enough of them to
+ * write a pack file
+ */
+#[derive(Default)]
+pub struct PackBuilder {
+    data: Vec<u8>,
+    blocks: Vec<PackedBlock>,
+    positions: HashMap<BackupBlockId, usize>,
+}
+
+impl PackBuilder {
+    pub fn add(&mut self, id: &BackupBlockId, block: &[u8]) {
+        self.positions.insert(id.clone(), self.blocks.len());
+        self.blocks.push(PackedBlock {
+            id: id.clone(),
+            offset: self.data.len() as u64,
+            length: block.len() as u64,
+        });
+        self.data.extend_from_slice(block);
+    }
+
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn get(&self, id: &BackupBlockId) -> Option<&[u8]> {
+        self.positions.get(id).map(|position| {
+            let block = &self.blocks[*position];
+            &self.data[block.offset as usize..(block.offset + block.length) as usize]
+        })
+    }
+
+    /**
+     * Finish the pack. Returns the name of the pack, which is the hash of its
+     * content, the content itself and the index of the contained blocks.
+     */
+    pub fn finish(self) -> (String, Vec<u8>, PackIndex) {
+        let name = hex::encode(Sha3_256::digest(&self.data));
+        (
+            name,
+            self.data,
+            PackIndex {
+                blocks: self.blocks,
+            },
+        )
+    }
+}
@@ -1,12 +1,18 @@
+use super::backendrepository::BackendRepository;
 use super::errors::Result;
-use super::fsrepository::FsRepository;
 use super::repository::Repository;
+use super::repository::RepositoryConfig;
 use crate::common::read_key;
+use crate::common::KeySource;
 use std::path::Path;
 
-pub fn initialize_repository(repository: &str) -> Result<()> {
-    let repo = FsRepository::new(&Path::new(&repository));
-    let user_key = read_key()?;
-    repo.initialize(user_key)?;
+pub fn initialize_repository(
+    repository: &str,
+    key_source: &KeySource,
+    config: &RepositoryConfig,
+) -> Result<()> {
+    let repo = BackendRepository::for_location(Path::new(repository))?;
+    let user_key = read_key(key_source)?;
+    repo.initialize(user_key, config)?;
     Ok(())
 }
@@ -0,0 +1,170 @@
+use crate::backendrepository::BackendRepository;
+use crate::backup::EntryType;
+use crate::common::read_key;
+use crate::common::ByteSize;
+use crate::common::KeySource;
+use crate::errors::{error, Result};
+use crate::lock::RepositoryLock;
+use crate::repository::BackupBlockId;
+use crate::repository::Repository;
+use crate::retention::RetentionPolicy;
+use std::collections::HashSet;
+use std::path::Path;
+
+/**
+ * Summary of a prune run
+ */
+pub struct PruneResult {
+    /**
+     * The number of blocks not referenced by any instance
+     */
+    pub unreferenced_blocks: usize,
+    /**
+     * The storage space used by the unreferenced blocks
+     */
+    pub unreferenced_size: usize,
+}
+
+/**
+ * entry point for the forget sub-command
+ *
+ * Removes the explicitly named instances as well as all instances not kept
+ * by the retention policy, if one is given.
+ */
+pub fn forget(
+    repository: &Path,
+    key_source: &KeySource,
+    names: &[String],
+    policy: Option<&RetentionPolicy>,
+    dry_run: bool,
+) -> Result<()> {
+    let mut repo = BackendRepository::for_location(repository)?;
+    let key = read_key(key_source)?;
+    repo.open(key)?;
+    let _lock = if dry_run {
+        RepositoryLock::shared(&repo)?
+    } else {
+        RepositoryLock::exclusive(&repo)?
+    };
+    let mut to_remove = vec![];
+    for name in names {
+        repo.open_instance(name)?;
+        to_remove.push(name.clone());
+    }
+    if let Some(policy) = policy {
+        if policy.is_empty() {
+            return error(
+                "The retention policy doesn't keep any instance. Refusing to remove all of them.",
+                None,
+            );
+        }
+        let instances = repo.list_instances()?;
+        for decision in policy.apply(&instances) {
+            if decision.keep() {
+                println!(
+                    "keep   {} ({})",
+                    decision.instance.name,
+                    decision.reasons.join(", ")
+                );
+            } else if !to_remove.contains(&decision.instance.name) {
+                println!("remove {}", decision.instance.name);
+                to_remove.push(decision.instance.name.clone());
+            }
+        }
+    }
+    for name in &to_remove {
+        if dry_run {
+            log::info!("Would remove instance {}", name);
+        } else {
+            repo.remove_instance(name)?;
+            log::info!("Removed instance {}", name);
+        }
+    }
+    if !dry_run && !to_remove.is_empty() {
+        log::info!("Run prune to free the space used by the removed instances.");
+    }
+    Ok(())
+}
+
+/**
+ * entry point for the prune sub-command
+ */
+pub fn prune(repository: &Path, key_source: &KeySource, dry_run: bool) -> Result<()> {
+    let mut repo = BackendRepository::for_location(repository)?;
+    let key = read_key(key_source)?;
+    repo.open(key)?;
+    let _lock = RepositoryLock::exclusive(&repo)?;
+    let result = prune_repository(&repo, dry_run)?;
+    if dry_run {
+        println!(
+            "Would remove {} unreferenced blocks, freeing {}",
+            result.unreferenced_blocks,
+            ByteSize(result.unreferenced_size)
+        );
+    } else {
+        println!(
+            "Removed {} unreferenced blocks, freed {}",
+            result.unreferenced_blocks,
+            ByteSize(result.unreferenced_size)
+        );
+    }
+    Ok(())
+}
+
+/**
+ * Remove all blocks from the repository, that are not reachable from any
+ * backup instance. In dry-run mode only the summary is calculated.
+ */
+pub fn prune_repository(repo: &dyn Repository, dry_run: bool) -> Result<PruneResult> {
+    log::info!("Marking referenced blocks");
+    let reachable = referenced_blocks(repo)?;
+    log::info!("{} blocks are referenced by instances", reachable.len());
+    let mut result = PruneResult {
+        unreferenced_blocks: 0,
+        unreferenced_size: 0,
+    };
+    let mut unreferenced = vec![];
+    for (id, size) in repo.list_blocks()? {
+        if !reachable.contains(&id) {
+            log::debug!("{} is not referenced", id);
+            result.unreferenced_blocks += 1;
+            result.unreferenced_size += size;
+            unreferenced.push(id);
+        }
+    }
+    // removing all blocks at once rewrites every affected pack only once
+    if !dry_run {
+        repo.remove_blocks(&unreferenced)?;
+    }
+    Ok(result)
+}
+
+/**
+ * Collect the IDs of all blocks referenced by the instances in the repository.
+ *
+ * This fails if any instance cannot be read completely, as the blocks
+ * referenced by it would otherwise be considered garbage.
+ */
+fn referenced_blocks(repo: &dyn Repository) -> Result<HashSet<BackupBlockId>> {
+    let mut reachable = HashSet::new();
+    for name in repo.list_instance_names()? {
+        log::debug!("Marking blocks of instance {}", name);
+        let instance = repo.open_instance(&name).or_else(|e| {
+            error(
+                "Could not read instance. Refusing to remove any blocks.",
+                Some(e.into()),
+            )
+        })?;
+        let entries = repo.load_entry_list(&instance.entry_list_id)?;
+        reachable.insert(instance.entry_list_id);
+        for entry in entries.0 {
+            if let EntryType::File(file_data) = entry.entry_type {
+                if reachable.insert(file_data.block_list_id.clone()) {
+                    let object = repo.open_object(&file_data.block_list_id)?;
+                    reachable.extend(object.blocks);
+                }
+            }
+        }
+    }
+    Ok(reachable)
+}
@@ -3,12 +3,46 @@ use super::backupobject::BackupObjectReader;
 use super::errors::Result;
 use crate::backup::EntryList;
 use crate::backupobject::BackupObject;
+use crate::chunker::ChunkerParams;
+use crate::compression::Compression;
 use crate::crypto::DataEncryptionKey;
 use crate::crypto::InputKey;
+use crate::crypto::KdfParams;
+use crate::crypto::KdfSettings;
 use crate::crypto::KeySet;
 use crate::errors::error;
+use crate::pack::DEFAULT_PACK_SIZE;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
+
+/**
+ * The repository format version written by this version of backrub. Older
+ * repositories can only be read until they are migrated.
+ */
+pub const CURRENT_VERSION: u32 = 3;
+
+/**
+ * Blocks are identified by a keyed hash of their content
+ */
+pub const FEATURE_KEYED_BLOCK_IDS: &str = "keyed-block-ids";
+/**
+ * The data keys are wrapped by a random key stored in password slots
+ */
+pub const FEATURE_PASSWORD_SLOTS: &str = "password-slots";
+/**
+ * Blocks are stored in pack files with an encrypted index
+ */
+pub const FEATURE_PACK_FILES: &str = "pack-files";
+
+/**
+ * The format features this version of backrub understands
+ */
+pub const KNOWN_FEATURES: &[&str] = &[
+    FEATURE_KEYED_BLOCK_IDS,
+    FEATURE_PASSWORD_SLOTS,
+    FEATURE_PACK_FILES,
+];
+
 /**
  * Meta information of a repository
  */
@@ -16,15 +50,129 @@ use std::fmt::Display;
 pub struct BackrubRepositoryMeta {
     pub version: u32,
     pub title: String,
+    /**
+     * The key derivation salt of repositories without password slots
+     */
     pub salt: Vec<u8>,
+    /**
+     * The key derivation iterations of repositories without password slots
+     */
     pub iterations: u16,
     pub id: String,
+    /**
+     * The parameters used to cut data into blocks
+     */
+    #[serde(default)]
+    pub chunker: ChunkerParams,
+    /**
+     * The default compression applied to new blocks
+     */
+    #[serde(default)]
+    pub compression: Compression,
+    /**
+     * The key derivation parameters for new passwords. Every password slot
+     * stores the parameters it was created with.
+     */
+    #[serde(default)]
+    pub kdf: Option<KdfParams>,
+    /**
+     * The format features used by the repository. A repository using features
+     * unknown to this version of backrub can't be opened.
+     */
+    #[serde(default)]
+    pub features: Vec<String>,
+    /**
+     * The size in bytes, at which pack files are finished
+     */
+    #[serde(default = "default_pack_size")]
+    pub pack_size: u64,
+}
+
+fn default_pack_size() -> u64 {
+    DEFAULT_PACK_SIZE
+}
+
+impl BackrubRepositoryMeta {
+    /**
+     * The key derivation parameters new passwords should use
+     */
+    pub fn kdf(&self) -> KdfParams {
+        match &self.kdf {
+            Some(kdf) => kdf.clone(),
+            None => KdfParams::legacy(&self.salt, self.iterations as u32),
+        }
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /**
+     * Make sure this version of backrub understands the repository format
+     */
+    pub fn check_readable(&self) -> Result<()> {
+        if let Some(feature) = self
+            .features
+            .iter()
+            .find(|f| !KNOWN_FEATURES.contains(&f.as_str()))
+        {
+            log::error!("Unknown repository feature {}", feature);
+            return error(
+                "The repository uses features unknown to this version of backrub",
+                None,
+            );
+        }
+        Ok(())
+    }
+
+    /**
+     * Make sure this version of backrub may modify the repository. Older
+     * repositories have to be migrated first, while newer ones must only be
+     * modified by newer versions of backrub.
+     */
+    pub fn check_writable(&self) -> Result<()> {
+        self.check_readable()?;
+        if self.version < CURRENT_VERSION {
+            error(
+                "The repository has an older format and is read-only. Run backrub migrate to upgrade it.",
+                None,
+            )
+        } else if self.version > CURRENT_VERSION {
+            error(
+                "The repository was written by a newer version of backrub and is read-only",
+                None,
+            )
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/**
+ * Settings applied when initializing a new repository
+ */
+pub struct RepositoryConfig {
+    pub chunker: ChunkerParams,
+    pub compression: Compression,
+    pub kdf: KdfSettings,
+    pub pack_size: u64,
+}
+
+impl Default for RepositoryConfig {
+    fn default() -> Self {
+        RepositoryConfig {
+            chunker: ChunkerParams::default(),
+            compression: Compression::Zstd(3),
+            kdf: KdfSettings::default(),
+            pack_size: DEFAULT_PACK_SIZE,
+        }
+    }
 }
 
 /**
  * ID type used for block identifiers
  */
-#[derive(Serialize, Deserialize, Eq)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Hash)]
 pub struct BackupBlockId(#[serde(with = "serde_bytes")] Vec<u8>);
 
 impl BackupBlockId {
@@ -36,9 +184,17 @@ impl BackupBlockId {
             Ok(Self(id))
         }
     }
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
     pub fn to_str(&self) -> String {
         hex::encode(&self.0)
     }
+    pub fn from_hex(id: &str) -> Result<Self> {
+        let bytes =
+            hex::decode(id).or_else(|e| error("Could not decode block ID", Some(e.into())))?;
+        Self::from_bytes(&bytes)
+    }
 }
 
 impl Display for BackupBlockId {
@@ -47,12 +203,6 @@ impl Display for BackupBlockId {
     }
 }
 
-impl PartialEq for BackupBlockId {
-    fn eq(&self, rhs: &Self) -> bool {
-        self.0 == rhs.0
-    }
-}
-
 pub trait Repository {
     /**
      * Return the meta information of this repository
@@ -62,7 +212,7 @@ pub trait Repository {
      * Initialize the given backup repository, i.e. check whether anything needs to be set up in the
      * directory
      */
-    fn initialize(&self, input_key: InputKey) -> Result<()>;
+    fn initialize(&self, input_key: InputKey, config: &RepositoryConfig) -> Result<()>;
     /**
      * open this repository
      */
@@ -77,11 +227,111 @@ pub trait Repository {
      */
     fn current_key(&self) -> Result<&(u64, DataEncryptionKey)>;
     /**
-     * Add a new block to the block store. This will return the
-     * block's ID, if successful or an error description, if not
+     * Generate a new data encryption key, which becomes the current key.
+     * Blocks encrypted with older keys stay readable. Returns the index of
+     * the new key.
+     */
+    fn add_key(&mut self) -> Result<u64>;
+    /**
+     * Replace the password the repository was opened with. The data keys are
+     * not changed, so no blocks have to be re-encrypted.
+     */
+    fn change_password(&mut self, new_key: InputKey) -> Result<()>;
+    /**
+     * Add another password, that opens the repository independently of the
+     * existing ones. Returns the name of the new password slot.
+     */
+    fn add_password(&mut self, new_key: InputKey) -> Result<String>;
+    /**
+     * Protect the password the repository was opened with by new key derivation
+     * parameters, which are also used for all passwords added from now on
+     */
+    fn upgrade_kdf(&mut self, key: InputKey, kdf: KdfParams) -> Result<()>;
+    /**
+     * Upgrade the repository to the current format version. Every step is
+     * recorded in the repository, once it is finished, so an interrupted
+     * migration can simply be started again. Returns the features added.
+     */
+    fn migrate(&mut self) -> Result<Vec<&'static str>>;
+    /**
+     * Revoke the password stored in the given slot
+     */
+    fn remove_password(&self, slot: &str) -> Result<()>;
+    /**
+     * List the names of the password slots together with their creation time
+     */
+    fn list_passwords(&self) -> Result<Vec<(String, u64)>>;
+    /**
+     * Override the compression applied to blocks added from now on.
+     * By default the compression configured for the repository is used.
+     */
+    fn set_compression(&mut self, compression: Compression);
+    /**
+     * Add a new block of plain data to the block store. The block is encrypted
+     * with the current key and identified by a keyed hash of its content, so
+     * a block already present in the repository is not stored again.
+     * This will return the block's ID and the number of bytes actually written,
+     * if successful or an error description, if not
      */
     fn add_block(&self, data: &[u8]) -> Result<(BackupBlockId, usize)>;
 
+    /**
+     * Calculate the ID a block with the given plain data has in this repository
+     */
+    fn block_id(&self, data: &[u8]) -> Result<BackupBlockId>;
+
+    /**
+     * Check whether a block with the given ID is present in the block store
+     */
+    fn has_block(&self, id: &BackupBlockId) -> Result<bool>;
+
+    /**
+     * Read the raw, encrypted content of a block
+     */
+    fn read_block(&self, id: &BackupBlockId) -> Result<Vec<u8>>;
+
+    /**
+     * Read the plain data of a block. The data is verified against the block
+     * ID while decoding it.
+     */
+    fn read_block_data(&self, id: &BackupBlockId) -> Result<Vec<u8>>;
+
+    /**
+     * Tell the index of the key a block is encrypted with. Only the header of
+     * the block is read, it is neither decrypted nor verified against the ID.
+     */
+    fn read_block_key_index(&self, id: &BackupBlockId) -> Result<u64>;
+
+    /**
+     * List all blocks in the block store together with their stored size
+     */
+    fn list_blocks(&self) -> Result<Vec<(BackupBlockId, usize)>>;
+
+    /**
+     * List the files skipped when opening the repository, because they
+     * couldn't be read, together with the reason
+     */
+    fn list_unreadable_files(&self) -> Vec<(String, String)>;
+
+    /**
+     * Remove a block from the block store
+     */
+    fn remove_block(&self, id: &BackupBlockId) -> Result<()> {
+        self.remove_blocks(std::slice::from_ref(id))
+    }
+
+    /**
+     * Remove the given blocks from the block store. Pack files, that still
+     * contain other blocks, are rewritten without the removed ones.
+     */
+    fn remove_blocks(&self, ids: &[BackupBlockId]) -> Result<()>;
+
+    /**
+     * Write the blocks added so far, but still buffered in memory, to the
+     * repository. Finishing a backup does this implicitly.
+     */
+    fn flush(&self) -> Result<()>;
+
     /**
      * Store the list of entries in a backup instance in the block store
      */
@@ -93,9 +343,10 @@ pub trait Repository {
     fn load_entry_list(&self, list_id: &BackupBlockId) -> Result<EntryList>;
 
     /**
-     * Finish the given backup by writing it to the repository
+     * Finish the given backup by writing it to the repository. An existing
+     * instance with the same name is only replaced, if overwrite is set.
      */
-    fn finish_backup(&self, backup: BackupInstance) -> Result<()>;
+    fn finish_backup(&self, backup: BackupInstance, overwrite: bool) -> Result<()>;
 
     /**
      * Open an object based on its ID
@@ -112,8 +363,41 @@ pub trait Repository {
      */
     fn list_instances(&self) -> Result<Vec<BackupInstance>>;
 
+    /**
+     * List the names of all stored backup instances, including the ones that
+     * cannot be loaded
+     */
+    fn list_instance_names(&self) -> Result<Vec<String>>;
+
     /**
      * Load a instance with the given name
      */
     fn open_instance(&self, name: &str) -> Result<BackupInstance>;
+
+    /**
+     * Remove the instance with the given name from the repository. The blocks
+     * referenced by the instance stay in the repository until they are pruned.
+     */
+    fn remove_instance(&self, name: &str) -> Result<()>;
+
+    /**
+     * Store a lock file with the given name. An existing lock is only
+     * replaced, if replace is set.
+     */
+    fn write_lock(&self, name: &str, data: &[u8], replace: bool) -> Result<()>;
+
+    /**
+     * Read the content of the lock file with the given name
+     */
+    fn read_lock(&self, name: &str) -> Result<Vec<u8>>;
+
+    /**
+     * List the names of all lock files in the repository
+     */
+    fn list_locks(&self) -> Result<Vec<String>>;
+
+    /**
+     * Remove the lock file with the given name
+     */
+    fn remove_lock(&self, name: &str) -> Result<()>;
 }
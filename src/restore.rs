@@ -1,20 +1,53 @@
+use super::backendrepository::BackendRepository;
 use super::common::read_key;
+use super::common::KeySource;
 use super::errors::{error, Result};
-use super::fsrepository::FsRepository;
 use super::repository::Repository;
 use crate::backup::LinkData;
-use crate::backup::{BackupEntry, EntryType, FileEntryData};
-use crate::crypto::decode_keyed_block;
-use crate::os::unix::set_meta_data;
+use crate::backup::{BackupEntry, EntryList, EntryType, FileEntryData, HardLinkData};
+use crate::lock::RepositoryLock;
+use crate::os::unix::{create_device, create_fifo, is_root, set_meta_data};
 use crate::regexfilter::regex_string_filter;
-use std::io::{Cursor, Write};
+use std::collections::HashMap;
+use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
+
+/**
+ * Options controlling how a backup instance is restored
+ */
+#[derive(Default)]
+pub struct RestoreOptions {
+    /**
+     * Restore files with unreadable blocks anyway and fill the missing
+     * regions with zeros instead of failing
+     */
+    pub best_effort: bool,
+    /**
+     * The file to write the list of damaged regions to
+     */
+    pub damage_report: Option<PathBuf>,
+}
+
+/**
+ * A region of a restored file, that could not be read from the repository
+ */
+pub struct DamagedRegion {
+    pub offset: u64,
+    /**
+     * The length of the region. Objects written by older versions don't
+     * record block sizes, so the length may be unknown.
+     */
+    pub length: Option<u64>,
+}
 
 pub fn restore_backup(
     repository: &str,
+    key_source: &KeySource,
     path: &str,
     include: &Option<Vec<String>>,
     name: &str,
+    options: &RestoreOptions,
 ) -> Result<()> {
     log::info!(
         "Restoring {} from repository {} to {}",
@@ -22,53 +55,262 @@ pub fn restore_backup(
         repository,
         path
     );
-    let mut repository = FsRepository::new(&Path::new(repository));
-    let key = read_key()?;
+    let mut repository = BackendRepository::for_location(Path::new(repository))?;
+    let key = read_key(key_source)?;
     repository.open(key)?;
+    let mut lock = RepositoryLock::shared(&repository)?;
     let instance = repository.open_instance(name)?;
     let entries = repository.load_entry_list(&instance.entry_list_id)?;
     let mut errors = vec![];
-    let filter: Option<Box<dyn Fn(&str) -> bool>> = include
-        .as_ref()
-        .map(|e| regex_string_filter(&e))
-        .transpose()?;
+    let mut damaged_files = vec![];
+    let filter = include_filter(include)?;
+    // the restored file holding the data of each linked file
+    let mut link_targets: HashMap<&str, &str> = HashMap::new();
+    let mut directories = vec![];
     for entry in entries
         .0
         .iter()
         .filter(|entry| filter.is_none() || filter.as_ref().unwrap()(&entry.name))
     {
-        let restore_result = restore_entry(&repository, &entry, path);
+        lock.refresh()?;
+        let restore_result = match &entry.entry_type {
+            EntryType::HardLink(link_data) => restore_hard_link(
+                &repository,
+                entry,
+                link_data,
+                &entries,
+                &mut link_targets,
+                path,
+                &mut lock,
+                options,
+            ),
+            _ => restore_entry(&repository, entry, path, &mut lock, options),
+        };
+        match (&entry.entry_type, &restore_result) {
+            (EntryType::File(_), Ok(_)) => {
+                link_targets.insert(&entry.name, &entry.name);
+            }
+            (EntryType::Dir, Ok(_)) => directories.push(entry),
+            _ => {}
+        }
         match restore_result {
-            Ok(_) => log::debug!("Successfully restored object"),
-            Err(e) => errors.push(e),
+            Ok(regions) if regions.is_empty() => log::debug!("Successfully restored object"),
+            Ok(regions) => damaged_files.push((entry.name.clone(), regions)),
+            Err(e) => errors.push((entry.name.clone(), e)),
+        }
+    }
+    // Read-only directories would prevent restoring their contents and
+    // restoring the contents changes the modification times, so the meta data
+    // of directories is set last. Subdirectories come before their parents,
+    // which may be read-only as well.
+    directories.sort_by_key(|entry| std::cmp::Reverse(Path::new(&entry.name).components().count()));
+    for entry in directories {
+        let dir_name: PathBuf = [path, &entry.name].iter().collect();
+        if let Err(e) = set_meta_data(&dir_name, &entry.meta) {
+            errors.push((entry.name.clone(), e));
+        }
+    }
+    if !damaged_files.is_empty() {
+        let report = damage_report(&damaged_files);
+        print!("{}", report);
+        if let Some(report_path) = &options.damage_report {
+            std::fs::write(report_path, report)
+                .or_else(|e| error("Could not write damage report", Some(e.into())))?;
         }
     }
     if errors.len() != 0 {
         log::error!("{} error(s) occured during restore", errors.len());
-        for error in errors {
-            log::error!("{}", &error);
-            println!("{}", &error);
+        for (name, error) in errors {
+            log::error!("{}: {}", name, &error);
+            println!("{}: {}", name, &error);
         }
         error("Restore unsuccessful", None)
+    } else if !damaged_files.is_empty() {
+        error("Restored files are damaged", None)
     } else {
         Ok(())
     }
 }
 
-fn restore_entry(repo: &FsRepository, entry: &BackupEntry, base_path: &str) -> Result<()> {
+type EntryFilter = Box<dyn Fn(&str) -> bool>;
+
+fn include_filter(include: &Option<Vec<String>>) -> Result<Option<EntryFilter>> {
+    include.as_ref().map(|e| regex_string_filter(e)).transpose()
+}
+
+/**
+ * Write the content of a single file of the instance to stdout. The file is
+ * selected by the include filters, which may be left out, if the instance
+ * contains only one file (e.g. one created from stdin).
+ */
+pub fn restore_to_stdout(
+    repository: &str,
+    key_source: &KeySource,
+    include: &Option<Vec<String>>,
+    name: &str,
+    options: &RestoreOptions,
+) -> Result<()> {
+    let mut repository = BackendRepository::for_location(Path::new(repository))?;
+    let key = read_key(key_source)?;
+    repository.open(key)?;
+    let mut lock = RepositoryLock::shared(&repository)?;
+    let instance = repository.open_instance(name)?;
+    let entries = repository.load_entry_list(&instance.entry_list_id)?;
+    let filter = include_filter(include)?;
+    let files: Vec<(&BackupEntry, &FileEntryData)> = entries
+        .0
+        .iter()
+        .filter(|entry| filter.is_none() || filter.as_ref().unwrap()(&entry.name))
+        .filter_map(|entry| match &entry.entry_type {
+            EntryType::File(file_data) => Some((entry, file_data)),
+            EntryType::HardLink(link_data) => {
+                linked_file(&entries, link_data).map(|file_data| (entry, file_data))
+            }
+            _ => None,
+        })
+        .collect();
+    let (entry, file_data) = match files.as_slice() {
+        [file] => *file,
+        [] => return error("No file in the instance matches", None),
+        _ => {
+            return error(
+                "More than one file matches. Select a single file with --include",
+                None,
+            )
+        }
+    };
+    log::info!("Writing {} to stdout", entry.name);
+    let stdout = std::io::stdout();
+    let mut output = stdout.lock();
+    let damaged = write_object(
+        &repository,
+        entry,
+        file_data,
+        &mut output,
+        &mut lock,
+        options,
+    )?;
+    output
+        .flush()
+        .or_else(|e| error("Could not write to stdout", Some(e.into())))?;
+    if damaged.is_empty() {
+        return Ok(());
+    }
+    // stdout carries the data, so the report goes elsewhere
+    let report = damage_report(&[(entry.name.clone(), damaged)]);
+    eprint!("{}", report);
+    if let Some(report_path) = &options.damage_report {
+        std::fs::write(report_path, report)
+            .or_else(|e| error("Could not write damage report", Some(e.into())))?;
+    }
+    error("Restored file is damaged", None)
+}
+
+fn damage_report(damaged_files: &[(String, Vec<DamagedRegion>)]) -> String {
+    let mut report = String::new();
+    for (name, regions) in damaged_files {
+        report.push_str(&format!("{}\n", name));
+        for region in regions {
+            match region.length {
+                Some(length) => report.push_str(&format!(
+                    "  damaged at offset {}, {} bytes filled with zeros\n",
+                    region.offset, length
+                )),
+                None => report.push_str(&format!(
+                    "  damaged at offset {}, unknown length\n",
+                    region.offset
+                )),
+            }
+        }
+    }
+    report
+}
+
+fn restore_entry(
+    repo: &BackendRepository,
+    entry: &BackupEntry,
+    base_path: &str,
+    lock: &mut RepositoryLock,
+    options: &RestoreOptions,
+) -> Result<Vec<DamagedRegion>> {
     match &entry.entry_type {
-        EntryType::File(file_data) => restore_file(repo, entry, &file_data, base_path),
-        EntryType::Dir => restore_dir(entry, base_path),
-        EntryType::Link(link_data) => restore_link(entry, &link_data, base_path),
+        EntryType::File(file_data) => {
+            restore_file(repo, entry, file_data, base_path, lock, options)
+        }
+        EntryType::Dir => restore_dir(entry, base_path).map(|_| vec![]),
+        EntryType::Link(link_data) => restore_link(entry, link_data, base_path).map(|_| vec![]),
+        EntryType::HardLink(_) => error("Hard links are restored with their targets", None),
+        EntryType::CharDevice(_) | EntryType::BlockDevice(_) | EntryType::Fifo => {
+            restore_special(entry, base_path).map(|_| vec![])
+        }
+        EntryType::Socket => {
+            log::info!("Skipping socket {}", &entry.name);
+            Ok(vec![])
+        }
     }
 }
 
+/**
+ * The data of the file a hard link refers to
+ */
+fn linked_file<'a>(entries: &'a EntryList, link_data: &HardLinkData) -> Option<&'a FileEntryData> {
+    entries.0.iter().find_map(|entry| match &entry.entry_type {
+        EntryType::File(file_data) if entry.name == link_data.target => Some(file_data),
+        _ => None,
+    })
+}
+
+/**
+ * Link the entry to the restored file holding the data of its target. If
+ * there is none, e.g. because the target was left out by the include filters,
+ * the entry is restored as a file and takes the place of the target for
+ * further links.
+ */
+#[allow(clippy::too_many_arguments)]
+fn restore_hard_link<'a>(
+    repo: &BackendRepository,
+    entry: &'a BackupEntry,
+    link_data: &'a HardLinkData,
+    entries: &EntryList,
+    link_targets: &mut HashMap<&'a str, &'a str>,
+    base_path: &str,
+    lock: &mut RepositoryLock,
+    options: &RestoreOptions,
+) -> Result<Vec<DamagedRegion>> {
+    if let Some(target) = link_targets.get(link_data.target.as_str()) {
+        let target_path: PathBuf = [base_path, target].iter().collect();
+        let restore_path: PathBuf = [base_path, &entry.name].iter().collect();
+        log::debug!("Linking {} to {}", &entry.name, target);
+        if let Some(parent_path) = restore_path.parent() {
+            std::fs::create_dir_all(parent_path)
+                .or_else(|e| error("Could not create parent path", Some(e.into())))?;
+        }
+        std::fs::hard_link(&target_path, &restore_path)
+            .or_else(|e| error("Could not create hard link", Some(e.into())))?;
+        return Ok(vec![]);
+    }
+    let file_data = match linked_file(entries, link_data) {
+        Some(file_data) => file_data,
+        None => {
+            return error(
+                "The target of the hard link is missing from the instance",
+                None,
+            )
+        }
+    };
+    let damaged = restore_file(repo, entry, file_data, base_path, lock, options)?;
+    link_targets.insert(&link_data.target, &entry.name);
+    Ok(damaged)
+}
+
 fn restore_file(
-    repo: &FsRepository,
+    repo: &BackendRepository,
     entry: &BackupEntry,
     entry_data: &FileEntryData,
     base_path: &str,
-) -> Result<()> {
+    lock: &mut RepositoryLock,
+    options: &RestoreOptions,
+) -> Result<Vec<DamagedRegion>> {
     let restore_path: std::path::PathBuf = [base_path, &entry.name].iter().collect();
     let parent_path = restore_path.parent().ok_or(super::errors::Error {
         message: "Object has no parent directory",
@@ -84,25 +326,111 @@ fn restore_file(
         .or_else(|e| error("Could not create parent path", Some(e.into())))?;
     let mut file = std::fs::File::create(&restore_path)
         .or_else(|e| error("Could not create output file", Some(e.into())))?;
+    match write_object(repo, entry, entry_data, &mut file, lock, options) {
+        Ok(damaged) => {
+            set_meta_data(&restore_path, &entry.meta)?;
+            Ok(damaged)
+        }
+        Err(e) => {
+            // never leave a truncated file behind, that looks like a successful restore
+            drop(file);
+            std::fs::remove_file(&restore_path).ok();
+            Err(e)
+        }
+    }
+}
+
+/**
+ * Write the content of a file entry to the output. In best effort mode,
+ * damaged blocks are replaced with zeros and reported, otherwise they are
+ * an error.
+ */
+fn write_object(
+    repo: &BackendRepository,
+    entry: &BackupEntry,
+    entry_data: &FileEntryData,
+    output: &mut dyn Write,
+    lock: &mut RepositoryLock,
+    options: &RestoreOptions,
+) -> Result<Vec<DamagedRegion>> {
     let object = repo.open_object(&entry_data.block_list_id)?;
+    let sizes = object.sizes.clone();
     let object_reader = repo.open_object_reader(object)?;
-    let keyset = repo.keys()?;
-    for block in object_reader.blocks() {
-        log::debug!("Decoding serialized data block of size {}", block.len());
-        let data_block = decode_keyed_block(Cursor::new(block), &keyset)?;
-        log::debug!("Contained block of size {}", data_block.len());
-        file.write(&data_block)
-            .or_else(|e| error("Could not write to output file", Some(e.into())))?;
+    let mut offset = 0;
+    let mut damaged = vec![];
+    for (index, data_block) in object_reader.blocks().enumerate() {
+        lock.refresh()?;
+        match data_block {
+            Ok(data_block) => {
+                log::debug!("Contained block of size {}", data_block.len());
+                output
+                    .write_all(&data_block)
+                    .or_else(|e| error("Could not write to output file", Some(e.into())))?;
+                offset += data_block.len() as u64;
+            }
+            Err(e) if options.best_effort => {
+                log::warn!("Block {} of {} is damaged: {}", index, &entry.name, e);
+                let length = sizes.get(index).copied();
+                if let Some(length) = length {
+                    output
+                        .write_all(&vec![0; length as usize])
+                        .or_else(|e| error("Could not write to output file", Some(e.into())))?;
+                }
+                damaged.push(DamagedRegion { offset, length });
+                offset += length.unwrap_or(0);
+            }
+            Err(e) => return Err(e),
+        }
     }
-    set_meta_data(&restore_path, &entry.meta)?;
-    Ok(())
+    Ok(damaged)
 }
 
+/**
+ * Create the directory. Its meta data is set after its contents have been
+ * restored.
+ */
 fn restore_dir(entry: &BackupEntry, base_path: &str) -> Result<()> {
     let dir_name: std::path::PathBuf = [base_path, &entry.name].iter().collect();
     std::fs::create_dir_all(&dir_name)
+        .or_else(|e| error("Could not create parent path", Some(e.into())))
+}
+
+/**
+ * Recreate a device or named pipe. Devices can only be created by root, so
+ * they are skipped with a warning otherwise.
+ */
+fn restore_special(entry: &BackupEntry, base_path: &str) -> Result<()> {
+    let restore_path: std::path::PathBuf = [base_path, &entry.name].iter().collect();
+    let parent_path = restore_path.parent().ok_or(super::errors::Error {
+        message: "Object has no parent directory",
+        cause: None,
+        is_warning: false,
+    })?;
+    log::debug!(
+        "Restoring {} to {}",
+        &entry.name,
+        restore_path.as_path().to_str().unwrap()
+    );
+    std::fs::create_dir_all(parent_path)
         .or_else(|e| error("Could not create parent path", Some(e.into())))?;
-    set_meta_data(&dir_name, &entry.meta)
+    match &entry.entry_type {
+        EntryType::CharDevice(_) | EntryType::BlockDevice(_) if !is_root() => {
+            log::warn!(
+                "Skipping device {}, only root can create devices",
+                &entry.name
+            );
+            return Ok(());
+        }
+        EntryType::CharDevice(device) => {
+            create_device(&restore_path, false, device.major, device.minor)?
+        }
+        EntryType::BlockDevice(device) => {
+            create_device(&restore_path, true, device.major, device.minor)?
+        }
+        EntryType::Fifo => create_fifo(&restore_path)?,
+        _ => return error("Not a special file", None),
+    }
+    set_meta_data(&restore_path, &entry.meta)
 }
 
 fn restore_link(entry: &BackupEntry, link_data: &LinkData, base_path: &str) -> Result<()> {
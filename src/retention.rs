@@ -0,0 +1,194 @@
+use crate::backup::BackupInstance;
+use crate::errors::{error, Result};
+use chrono::DateTime;
+use chrono::Local;
+use std::ops::Add;
+use std::time::Duration;
+
+/**
+ * Declarative description of the backup instances to keep
+ */
+#[derive(Default, Debug)]
+pub struct RetentionPolicy {
+    /**
+     * Keep the given number of most recent instances
+     */
+    pub keep_last: usize,
+    /**
+     * Keep the most recent instance of each of the given number of hours
+     */
+    pub keep_hourly: usize,
+    /**
+     * Keep the most recent instance of each of the given number of days
+     */
+    pub keep_daily: usize,
+    /**
+     * Keep the most recent instance of each of the given number of weeks
+     */
+    pub keep_weekly: usize,
+    /**
+     * Keep the most recent instance of each of the given number of months
+     */
+    pub keep_monthly: usize,
+    /**
+     * Keep the most recent instance of each of the given number of years
+     */
+    pub keep_yearly: usize,
+    /**
+     * Keep all instances created within the given duration before the most
+     * recent instance
+     */
+    pub keep_within: Option<Duration>,
+    /**
+     * Only apply the policy to instances whose name starts with the given prefix
+     */
+    pub name_prefix: Option<String>,
+    /**
+     * Only apply the policy to instances carrying the given tag
+     */
+    pub tag: Option<String>,
+}
+
+/**
+ * The result of applying a retention policy to a single instance
+ */
+pub struct RetentionDecision<'a> {
+    pub instance: &'a BackupInstance,
+    /**
+     * The rules the instance is kept for. Instances without reasons are removed.
+     */
+    pub reasons: Vec<String>,
+}
+
+impl RetentionDecision<'_> {
+    pub fn keep(&self) -> bool {
+        !self.reasons.is_empty()
+    }
+}
+
+impl RetentionPolicy {
+    /**
+     * Check whether the policy would keep any instance at all
+     */
+    pub fn is_empty(&self) -> bool {
+        self.keep_last == 0
+            && self.keep_hourly == 0
+            && self.keep_daily == 0
+            && self.keep_weekly == 0
+            && self.keep_monthly == 0
+            && self.keep_yearly == 0
+            && self.keep_within.is_none()
+    }
+
+    fn applies_to(&self, instance: &BackupInstance) -> bool {
+        self.name_prefix
+            .as_ref()
+            .is_none_or(|prefix| instance.name.starts_with(prefix))
+            && self
+                .tag
+                .as_ref()
+                .is_none_or(|tag| instance.tags.contains(tag))
+    }
+
+    /**
+     * Decide which of the given instances to keep.
+     *
+     * Instances outside the scope of the policy are not part of the result.
+     * The decisions are ordered from the most recent to the oldest instance.
+     */
+    pub fn apply<'a>(&self, instances: &'a [BackupInstance]) -> Vec<RetentionDecision<'a>> {
+        let mut decisions: Vec<RetentionDecision> = instances
+            .iter()
+            .filter(|instance| self.applies_to(instance))
+            .map(|instance| RetentionDecision {
+                instance,
+                reasons: vec![],
+            })
+            .collect();
+        decisions.sort_by_key(|d| std::cmp::Reverse(d.instance.time));
+        for (index, decision) in decisions.iter_mut().enumerate() {
+            if index < self.keep_last {
+                decision.reasons.push(String::from("last"));
+            }
+        }
+        let buckets: [(usize, &str, &str); 5] = [
+            (self.keep_hourly, "hourly", "%Y-%m-%d %H"),
+            (self.keep_daily, "daily", "%Y-%m-%d"),
+            (self.keep_weekly, "weekly", "%G-%V"),
+            (self.keep_monthly, "monthly", "%Y-%m"),
+            (self.keep_yearly, "yearly", "%Y"),
+        ];
+        for (count, reason, format) in &buckets {
+            let mut kept = 0;
+            let mut last_bucket = None;
+            for decision in decisions.iter_mut() {
+                if kept >= *count {
+                    break;
+                }
+                let bucket = local_time(decision.instance.time)
+                    .format(format)
+                    .to_string();
+                if last_bucket.as_ref() != Some(&bucket) {
+                    decision.reasons.push(String::from(*reason));
+                    last_bucket = Some(bucket);
+                    kept += 1;
+                }
+            }
+        }
+        if let (Some(within), Some(newest)) = (self.keep_within, decisions.first()) {
+            let limit = newest.instance.time.saturating_sub(within.as_secs());
+            for decision in decisions.iter_mut() {
+                if decision.instance.time >= limit {
+                    decision.reasons.push(String::from("within"));
+                }
+            }
+        }
+        decisions
+    }
+}
+
+fn local_time(timestamp: u64) -> DateTime<Local> {
+    std::time::SystemTime::UNIX_EPOCH
+        .add(Duration::from_secs(timestamp))
+        .into()
+}
+
+/**
+ * Parse a duration given as a sequence of numbers with units, e.g. "1y6m" or "36h".
+ *
+ * Supported units are h (hours), d (days), w (weeks), m (months of 30 days)
+ * and y (years of 365 days).
+ */
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in duration.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let hours = match c {
+            'h' => 1,
+            'd' => 24,
+            'w' => 7 * 24,
+            'm' => 30 * 24,
+            'y' => 365 * 24,
+            _ => return error("Unknown unit in duration", None),
+        };
+        let value = number
+            .parse::<u64>()
+            .or_else(|e| error("Could not parse duration", Some(e.into())))?;
+        total = match value
+            .checked_mul(hours * 3600)
+            .and_then(|seconds| total.checked_add(seconds))
+        {
+            Some(total) => total,
+            None => return error("Duration is too long", None),
+        };
+        number.clear();
+    }
+    if !number.is_empty() || total == 0 {
+        return error("Durations must be given with a unit, e.g. 7d", None);
+    }
+    Ok(Duration::from_secs(total))
+}
@@ -0,0 +1,623 @@
+use crate::backend::Backend;
+use crate::errors::{error, Result};
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::time::Duration;
+
+/**
+ * Objects larger than this are uploaded in several parts
+ */
+pub const MULTIPART_THRESHOLD: usize = 8 * 1024 * 1024;
+/**
+ * The size of the parts of a multipart upload. S3 requires at least 5 MiB.
+ */
+pub const PART_SIZE: usize = 8 * 1024 * 1024;
+/**
+ * How often a request is sent, before a temporary failure is reported
+ */
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/**
+ * Access keys for an S3 compatible object store
+ */
+#[derive(Clone)]
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+    pub session_token: Option<String>,
+}
+
+/**
+ * Connection settings of an S3 compatible object store
+ */
+#[derive(Clone)]
+pub struct S3Config {
+    /**
+     * The base URL of the service, e.g. http://localhost:9000 for a local MinIO
+     */
+    pub endpoint: String,
+    pub region: String,
+    pub credentials: Credentials,
+}
+
+impl S3Config {
+    /**
+     * Read the settings from the environment. The credentials are taken from
+     * AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and AWS_SESSION_TOKEN, the region
+     * from AWS_REGION or AWS_DEFAULT_REGION. BACKRUB_S3_ENDPOINT selects a
+     * service other than AWS.
+     */
+    pub fn from_env() -> Result<S3Config> {
+        let region = std::env::var("AWS_REGION")
+            .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| String::from("us-east-1"));
+        let endpoint = std::env::var("BACKRUB_S3_ENDPOINT")
+            .unwrap_or_else(|_| format!("https://s3.{}.amazonaws.com", region));
+        let (access_key, secret_key) = match (
+            std::env::var("AWS_ACCESS_KEY_ID"),
+            std::env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            (Ok(access_key), Ok(secret_key)) => (access_key, secret_key),
+            _ => {
+                return error(
+                    "S3 repositories require AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY",
+                    None,
+                )
+            }
+        };
+        Ok(S3Config {
+            endpoint,
+            region,
+            credentials: Credentials {
+                access_key,
+                secret_key,
+                session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+            },
+        })
+    }
+}
+
+/**
+ * A repository stored in a bucket of an S3 compatible object store. Every
+ * file of the repository is stored as an object under the prefix of the
+ * repository. Objects are addressed path-style, which is supported by AWS
+ * as well as by self-hosted services like MinIO.
+ */
+pub struct S3Backend {
+    agent: ureq::Agent,
+    config: S3Config,
+    host: String,
+    bucket: String,
+    prefix: String,
+}
+
+/**
+ * The parts of a response, that are needed by the backend
+ */
+struct S3Response {
+    status: u16,
+    etag: Option<String>,
+    length: Option<u64>,
+    body: Vec<u8>,
+}
+
+impl S3Backend {
+    /**
+     * Create a backend for a location of the form s3://bucket/prefix
+     */
+    pub fn new(location: &str, config: S3Config) -> Result<S3Backend> {
+        let path = match location.strip_prefix("s3://") {
+            Some(path) => path,
+            None => return error("S3 locations must start with s3://", None),
+        };
+        let mut parts = path.splitn(2, '/');
+        let bucket = parts.next().unwrap_or_default().to_string();
+        if bucket.is_empty() {
+            return error("The S3 location doesn't name a bucket", None);
+        }
+        let prefix = parts
+            .next()
+            .unwrap_or_default()
+            .trim_matches('/')
+            .to_string();
+        let host = match config.endpoint.split_once("://") {
+            Some((_, host)) => host.trim_end_matches('/').to_string(),
+            None => return error("The S3 endpoint must be an http or https URL", None),
+        };
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(30))
+            .timeout_read(Duration::from_secs(300))
+            .timeout_write(Duration::from_secs(300))
+            .build();
+        Ok(S3Backend {
+            agent,
+            config,
+            host,
+            bucket,
+            prefix,
+        })
+    }
+
+    fn key(&self, path: &str) -> String {
+        if self.prefix.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.prefix, path)
+        }
+    }
+
+    /**
+     * Send a request, retrying temporary failures (connection problems,
+     * server errors and throttling) with an increasing delay
+     */
+    fn request(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+        body: &[u8],
+    ) -> std::result::Result<S3Response, Box<ureq::Error>> {
+        self.request_retried(method, key, query, headers, body).0
+    }
+
+    /**
+     * Send a request like request() and also tell, whether it was retried.
+     * A failed attempt may still have been carried out by the server.
+     */
+    fn request_retried(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+        body: &[u8],
+    ) -> (std::result::Result<S3Response, Box<ureq::Error>>, bool) {
+        let mut attempt = 0;
+        loop {
+            match self.send(method, key, query, headers, body) {
+                Err(e) if is_temporary(&e) && attempt + 1 < MAX_ATTEMPTS => {
+                    log::warn!("S3 request failed, retrying: {}", e);
+                    std::thread::sleep(RETRY_DELAY * 2u32.pow(attempt));
+                    attempt += 1;
+                }
+                result => return (result, attempt > 0),
+            }
+        }
+    }
+
+    /**
+     * Check whether a conditional write, that was rejected after being retried,
+     * has been stored by one of the earlier attempts
+     */
+    fn is_stored(&self, key: &str, data: &[u8]) -> bool {
+        match self.request("GET", key, &[], &[], &[]) {
+            Ok(response) => response.body == data,
+            Err(_) => false,
+        }
+    }
+
+    fn send(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+        body: &[u8],
+    ) -> std::result::Result<S3Response, Box<ureq::Error>> {
+        let uri = if key.is_empty() {
+            format!("/{}", uri_encode(&self.bucket, false))
+        } else {
+            format!(
+                "/{}/{}",
+                uri_encode(&self.bucket, false),
+                uri_encode(key, false)
+            )
+        };
+        let query = canonical_query(query);
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = hex::encode(Sha256::digest(body));
+        let mut signed_headers = vec![
+            (String::from("host"), self.host.clone()),
+            (String::from("x-amz-content-sha256"), payload_hash.clone()),
+            (String::from("x-amz-date"), amz_date.clone()),
+        ];
+        if let Some(token) = &self.config.credentials.session_token {
+            signed_headers.push((String::from("x-amz-security-token"), token.clone()));
+        }
+        for (name, value) in headers {
+            signed_headers.push((name.to_lowercase(), value.clone()));
+        }
+        let authorization = authorization(
+            &self.config.credentials,
+            &self.config.region,
+            method,
+            &uri,
+            &query,
+            &signed_headers,
+            &payload_hash,
+            &amz_date,
+        );
+        let mut url = format!("{}{}", self.config.endpoint.trim_end_matches('/'), uri);
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query);
+        }
+        let mut request = self
+            .agent
+            .request(method, &url)
+            .set("Authorization", &authorization);
+        for (name, value) in &signed_headers {
+            if name != "host" {
+                request = request.set(name, value);
+            }
+        }
+        let response = if body.is_empty() && method != "PUT" && method != "POST" {
+            request.call()?
+        } else {
+            request.send_bytes(body)?
+        };
+        let status = response.status();
+        let etag = response.header("ETag").map(String::from);
+        let length = response
+            .header("Content-Length")
+            .and_then(|l| l.parse::<u64>().ok());
+        let mut data = vec![];
+        response
+            .into_reader()
+            .read_to_end(&mut data)
+            .map_err(|e| Box::new(e.into()))?;
+        Ok(S3Response {
+            status,
+            etag,
+            length,
+            body: data,
+        })
+    }
+
+    fn put(&self, key: &str, data: &[u8], overwrite: bool) -> Result<()> {
+        let headers = conditional_headers(overwrite);
+        match self.request_retried("PUT", key, &[], &headers, data) {
+            (Ok(_), _) => Ok(()),
+            (Err(e), true) if status(&e) == Some(412) && self.is_stored(key, data) => Ok(()),
+            (Err(e), _) if status(&e) == Some(412) => error("The file already exists", None),
+            (Err(e), _) => error("Could not write file to S3", Some(e.into())),
+        }
+    }
+
+    /**
+     * Upload a large object in several parts. An upload, that can't be
+     * finished, is aborted, so the object store doesn't keep the parts.
+     */
+    fn put_multipart(&self, key: &str, data: &[u8], overwrite: bool) -> Result<()> {
+        let response = self
+            .request("POST", key, &[("uploads", "")], &[], &[])
+            .or_else(|e| error("Could not start multipart upload", Some(e.into())))?;
+        let body = String::from_utf8_lossy(&response.body);
+        let upload_id = match xml_values(&body, "UploadId").first() {
+            Some(upload_id) => xml_unescape(upload_id),
+            None => return error("S3 didn't return an upload ID", None),
+        };
+        let result = self.upload_parts(key, &upload_id, data, overwrite);
+        if result.is_err() {
+            if let Err(e) = self.request("DELETE", key, &[("uploadId", &upload_id)], &[], &[]) {
+                log::warn!("Could not abort multipart upload {}: {}", upload_id, e);
+            }
+        }
+        result
+    }
+
+    fn upload_parts(&self, key: &str, upload_id: &str, data: &[u8], overwrite: bool) -> Result<()> {
+        let mut completion = String::from("<CompleteMultipartUpload>");
+        for (index, part) in data.chunks(PART_SIZE).enumerate() {
+            let part_number = (index + 1).to_string();
+            let response = self
+                .request(
+                    "PUT",
+                    key,
+                    &[("partNumber", &part_number), ("uploadId", upload_id)],
+                    &[],
+                    part,
+                )
+                .or_else(|e| error("Could not upload part", Some(e.into())))?;
+            let etag = match response.etag {
+                Some(etag) => etag,
+                None => return error("S3 didn't return an ETag for the part", None),
+            };
+            completion.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                part_number,
+                xml_escape(&etag)
+            ));
+        }
+        completion.push_str("</CompleteMultipartUpload>");
+        let headers = conditional_headers(overwrite);
+        match self.request_retried(
+            "POST",
+            key,
+            &[("uploadId", upload_id)],
+            &headers,
+            completion.as_bytes(),
+        ) {
+            // completing the upload may fail after the response has started
+            (Ok(response), _) if String::from_utf8_lossy(&response.body).contains("<Error>") => {
+                error("Could not complete multipart upload", None)
+            }
+            (Ok(_), _) => Ok(()),
+            (Err(e), true) if status(&e) == Some(412) && self.is_stored(key, data) => Ok(()),
+            (Err(e), _) if status(&e) == Some(412) => error("The file already exists", None),
+            (Err(e), _) => error("Could not complete multipart upload", Some(e.into())),
+        }
+    }
+}
+
+impl Backend for S3Backend {
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        match self.request("GET", &self.key(path), &[], &[], &[]) {
+            Ok(response) => Ok(response.body),
+            Err(e) => error("Could not read file from S3", Some(e.into())),
+        }
+    }
+    fn read_range(&self, path: &str, offset: u64, length: u64) -> Result<Vec<u8>> {
+        if length == 0 {
+            return Ok(vec![]);
+        }
+        let last = match offset.checked_add(length - 1) {
+            Some(last) => last,
+            None => return error("The range lies outside of the file", None),
+        };
+        let range = format!("bytes={}-{}", offset, last);
+        let response = self
+            .request("GET", &self.key(path), &[], &[("Range", range)], &[])
+            .or_else(|e| error("Could not read file from S3", Some(e.into())))?;
+        if response.status != 206 || response.body.len() as u64 != length {
+            return error("S3 returned an unexpected range of the file", None);
+        }
+        Ok(response.body)
+    }
+    fn write(&self, path: &str, data: &[u8], overwrite: bool) -> Result<()> {
+        if data.len() > MULTIPART_THRESHOLD {
+            self.put_multipart(&self.key(path), data, overwrite)
+        } else {
+            self.put(&self.key(path), data, overwrite)
+        }
+    }
+    fn exists(&self, path: &str) -> Result<bool> {
+        match self.request("HEAD", &self.key(path), &[], &[], &[]) {
+            Ok(_) => Ok(true),
+            Err(e) if status(&e) == Some(404) => Ok(false),
+            Err(e) => error("Could not query S3", Some(e.into())),
+        }
+    }
+    fn size(&self, path: &str) -> Result<u64> {
+        match self.request("HEAD", &self.key(path), &[], &[], &[]) {
+            Ok(S3Response {
+                length: Some(length),
+                ..
+            }) => Ok(length),
+            Ok(_) => error("S3 didn't report the file size", None),
+            Err(e) => error("Could not query S3", Some(e.into())),
+        }
+    }
+    fn list(&self, dir: &str) -> Result<Vec<(String, u64)>> {
+        let prefix = if !dir.is_empty() {
+            format!("{}/", self.key(dir))
+        } else if !self.prefix.is_empty() {
+            format!("{}/", self.prefix)
+        } else {
+            // the repository fills the whole bucket
+            String::new()
+        };
+        let mut files = vec![];
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix.as_str())];
+            if let Some(token) = &continuation {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let response = self
+                .request("GET", "", &query, &[], &[])
+                .or_else(|e| error("Could not list files in S3", Some(e.into())))?;
+            let body = String::from_utf8_lossy(&response.body);
+            for object in xml_values(&body, "Contents") {
+                let key = xml_values(object, "Key").first().map(|k| xml_unescape(k));
+                let size = xml_values(object, "Size")
+                    .first()
+                    .and_then(|s| s.parse::<u64>().ok());
+                match (key, size) {
+                    (Some(key), Some(size)) => {
+                        if let Some(name) = key.strip_prefix(&prefix) {
+                            files.push((name.to_string(), size));
+                        }
+                    }
+                    _ => return error("Could not parse S3 object listing", None),
+                }
+            }
+            continuation = match xml_values(&body, "NextContinuationToken").first() {
+                Some(token) if xml_values(&body, "IsTruncated").first() == Some(&"true") => {
+                    Some(xml_unescape(token))
+                }
+                _ => break,
+            };
+        }
+        Ok(files)
+    }
+    fn remove(&self, path: &str) -> Result<()> {
+        self.request("DELETE", &self.key(path), &[], &[], &[])
+            .map(|_| ())
+            .or_else(|e| error("Could not remove file from S3", Some(e.into())))
+    }
+    fn create_dir(&self, _dir: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/**
+ * The HTTP status of a failed request, if the server answered at all
+ */
+fn status(e: &ureq::Error) -> Option<u16> {
+    match e {
+        ureq::Error::Status(status, _) => Some(*status),
+        ureq::Error::Transport(_) => None,
+    }
+}
+
+/**
+ * Connection problems, server errors and throttling are worth another try
+ */
+fn is_temporary(e: &ureq::Error) -> bool {
+    match status(e) {
+        Some(status) => status >= 500 || status == 429,
+        None => true,
+    }
+}
+
+/**
+ * Headers, that make a write fail, if the object already exists
+ */
+fn conditional_headers(overwrite: bool) -> Vec<(&'static str, String)> {
+    if overwrite {
+        vec![]
+    } else {
+        vec![("If-None-Match", String::from("*"))]
+    }
+}
+
+/**
+ * Compute the Authorization header of a request according to AWS signature
+ * version 4. The headers must contain at least host, x-amz-content-sha256 and
+ * x-amz-date. All given headers are signed.
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn authorization(
+    credentials: &Credentials,
+    region: &str,
+    method: &str,
+    canonical_uri: &str,
+    canonical_query: &str,
+    headers: &[(String, String)],
+    payload_hash: &str,
+    amz_date: &str,
+) -> String {
+    let mut headers: Vec<(String, String)> = headers
+        .iter()
+        .map(|(name, value)| (name.to_lowercase(), value.trim().to_string()))
+        .collect();
+    headers.sort();
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<&str>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method, canonical_uri, canonical_query, canonical_headers, signed_headers, payload_hash
+    );
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut key = hmac_sha256(
+        format!("AWS4{}", credentials.secret_key).as_bytes(),
+        date.as_bytes(),
+    );
+    for part in &[region, "s3", "aws4_request"] {
+        key = hmac_sha256(&key, part.as_bytes());
+    }
+    let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{},SignedHeaders={},Signature={}",
+        credentials.access_key, scope, signed_headers, signature
+    )
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/**
+ * Build the canonical (sorted and encoded) query string of a request
+ */
+pub fn canonical_query(query: &[(&str, &str)]) -> String {
+    let mut parameters: Vec<(String, String)> = query
+        .iter()
+        .map(|(name, value)| (uri_encode(name, true), uri_encode(value, true)))
+        .collect();
+    parameters.sort();
+    parameters
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+/**
+ * Percent-encode everything except the unreserved characters. Slashes are
+ * kept in paths, but encoded in query parameters.
+ */
+pub fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/**
+ * The contents of all elements with the given name. This is sufficient for
+ * the flat responses of S3 and avoids pulling in a complete XML parser.
+ */
+fn xml_values<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut values = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        match rest.find(&close) {
+            Some(end) => {
+                values.push(&rest[..end]);
+                rest = &rest[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    values
+}
+
+fn xml_unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,404 @@
+use crate::backend::Backend;
+use crate::backendrepository::backend_for_location;
+use crate::errors::{error, Result};
+use crate::pack::DEFAULT_PACK_SIZE;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::Path;
+use tiny_http::{Header, Method, Request, Response};
+
+/**
+ * The top level entries of a repository, that may be accessed over HTTP
+ */
+const SERVED_ENTRIES: &[&str] = &[
+    "backrub",
+    "keys",
+    "blocks",
+    "packs",
+    "index",
+    "instances",
+    "locks",
+];
+
+/**
+ * The header, in which clients send a random ID, which stays the same as long
+ * as they access the repository
+ */
+pub const CLIENT_HEADER: &str = "X-Backrub-Client";
+
+/**
+ * The default limit for the size of written files: packs grow beyond the pack
+ * size by at most one block, which leaves plenty of headroom
+ */
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 4 * DEFAULT_PACK_SIZE;
+
+/**
+ * Settings of a RepositoryServer
+ */
+#[derive(Clone)]
+pub struct ServerConfig {
+    /**
+     * In append-only mode, files can be added, but neither removed nor
+     * changed, so a client can't destroy existing backups. Locks can only be
+     * changed and removed by the client, that created them.
+     */
+    pub append_only: bool,
+    /**
+     * The token clients have to send as "Authorization: Bearer <token>".
+     * Without a token, everybody able to connect can read and write the
+     * repository.
+     */
+    pub token: Option<String>,
+    /**
+     * Writing larger files is refused, so a client can't make the server
+     * run out of memory
+     */
+    pub max_body_size: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            append_only: false,
+            token: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+}
+
+/**
+ * A small HTTP server giving access to the files of a repository. The server
+ * only sees encrypted data, so it doesn't need the password of the
+ * repository.
+ *
+ * GET returns a file (honoring a Range header), HEAD checks whether it exists,
+ * PUT writes it (replacing an existing file only with ?overwrite=true) and
+ * DELETE removes it. Paths ending in a slash refer to directories: GET lists
+ * the files below them as lines of "<size> <name>", PUT creates them.
+ */
+pub struct RepositoryServer {
+    server: tiny_http::Server,
+    config: ServerConfig,
+    /**
+     * The client, that created each lock, identified by the CLIENT_HEADER it
+     * sent. In append-only mode, only this client may change or remove it.
+     */
+    lock_owners: RefCell<HashMap<String, String>>,
+}
+
+impl RepositoryServer {
+    /**
+     * Listen on the given address
+     */
+    pub fn bind(address: &str, config: ServerConfig) -> Result<RepositoryServer> {
+        let server = tiny_http::Server::http(address)
+            .or_else(|e| error("Could not listen on the given address", Some(e)))?;
+        Ok(RepositoryServer {
+            server,
+            config,
+            lock_owners: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /**
+     * The address the server is listening on
+     */
+    pub fn address(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /**
+     * Answer requests until the process is stopped. Requests are handled one
+     * after another, which also keeps the checks of append-only mode free of
+     * races.
+     */
+    pub fn run(&self, backend: &dyn Backend) -> Result<()> {
+        for mut request in self.server.incoming_requests() {
+            let response = match self.handle(backend, &mut request) {
+                Ok(response) => response,
+                Err(e) => {
+                    log::error!("{} {}: {}", request.method(), request.url(), e);
+                    Response::from_string(e.to_string()).with_status_code(500)
+                }
+            };
+            log::debug!(
+                "{} {} -> {}",
+                request.method(),
+                request.url(),
+                response.status_code().0
+            );
+            if let Err(e) = request.respond(response) {
+                log::warn!("Could not send response: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(
+        &self,
+        backend: &dyn Backend,
+        request: &mut Request,
+    ) -> Result<Response<std::io::Cursor<Vec<u8>>>> {
+        if !self.is_authorized(request) {
+            return Ok(status(401).with_header(header("WWW-Authenticate", "Bearer")));
+        }
+        let (path, query) = match request.url().split_once('?') {
+            Some((path, query)) => (path.to_string(), query.to_string()),
+            None => (request.url().to_string(), String::new()),
+        };
+        let path = match percent_decode(&path) {
+            Some(path) => path,
+            None => return Ok(status(400)),
+        };
+        let path = path.trim_start_matches('/');
+        if !is_served(path) {
+            return Ok(status(404));
+        }
+        let overwrite = query.split('&').any(|p| p == "overwrite=true");
+        let is_dir = path.is_empty() || path.ends_with('/');
+        let path = path.trim_end_matches('/');
+        match (request.method(), is_dir) {
+            (Method::Get, true) => {
+                let mut listing = String::new();
+                for (name, size) in backend.list(path)? {
+                    listing.push_str(&format!("{} {}\n", size, name));
+                }
+                Ok(Response::from_string(listing))
+            }
+            (Method::Put, true) => {
+                backend.create_dir(path)?;
+                Ok(status(204))
+            }
+            (Method::Head, false) => {
+                if !backend.exists(path)? {
+                    return Ok(status(404));
+                }
+                // no body is sent in reply to HEAD, but the Content-Length
+                // tells the size of the file
+                let size = backend.size(path)?;
+                Ok(Response::new(
+                    200.into(),
+                    vec![],
+                    std::io::Cursor::new(vec![]),
+                    usize::try_from(size).ok(),
+                    None,
+                ))
+            }
+            (Method::Get, false) => {
+                if !backend.exists(path)? {
+                    return Ok(status(404));
+                }
+                match range(request) {
+                    Some((start, end)) => {
+                        // the range is clamped to the file, so a request
+                        // can't make the server allocate more than the file
+                        let size = backend.size(path)?;
+                        if start >= size {
+                            return Ok(status(416).with_header(header(
+                                "Content-Range",
+                                &format!("bytes */{}", size),
+                            )));
+                        }
+                        let end = std::cmp::min(end, size - 1);
+                        let data = backend.read_range(path, start, end - start + 1)?;
+                        let content_range = format!("bytes {}-{}/{}", start, end, size);
+                        Ok(Response::from_data(data)
+                            .with_status_code(206)
+                            .with_header(header("Content-Range", &content_range)))
+                    }
+                    None => Ok(Response::from_data(backend.read(path)?)),
+                }
+            }
+            (Method::Put, false) => {
+                let max_body_size = self.config.max_body_size;
+                if matches!(request.body_length(), Some(length) if length as u64 > max_body_size) {
+                    return Ok(status(413));
+                }
+                let mut data = vec![];
+                request
+                    .as_reader()
+                    .take(max_body_size.saturating_add(1))
+                    .read_to_end(&mut data)
+                    .or_else(|e| error("Could not read request", Some(e.into())))?;
+                if data.len() as u64 > max_body_size {
+                    return Ok(status(413));
+                }
+                if !backend.exists(path)? {
+                    backend.write(path, &data, false)?;
+                    if let (true, Some(client)) = (is_lock(path), client(request)) {
+                        self.lock_owners
+                            .borrow_mut()
+                            .insert(path.to_string(), client);
+                    }
+                    return Ok(status(201));
+                }
+                if !overwrite {
+                    return Ok(status(409));
+                }
+                if self.config.append_only && is_lock(path) && !self.owns_lock(request, path) {
+                    return Ok(status(403));
+                }
+                if self.config.append_only && !is_lock(path) {
+                    // writing the same content again changes nothing, which
+                    // happens e.g. for pack files named by their content
+                    if backend.read(path)? == data {
+                        return Ok(status(204));
+                    }
+                    return Ok(status(403));
+                }
+                backend.write(path, &data, true)?;
+                Ok(status(204))
+            }
+            (Method::Delete, false) => {
+                if self.config.append_only && (!is_lock(path) || !self.owns_lock(request, path)) {
+                    return Ok(status(403));
+                }
+                if !backend.exists(path)? {
+                    return Ok(status(404));
+                }
+                backend.remove(path)?;
+                self.lock_owners.borrow_mut().remove(path);
+                Ok(status(204))
+            }
+            _ => Ok(status(405)),
+        }
+    }
+
+    /**
+     * Whether the client sending the request created the lock. The owners of
+     * locks created before the server was started are unknown.
+     */
+    fn owns_lock(&self, request: &Request, path: &str) -> bool {
+        match (self.lock_owners.borrow().get(path), client(request)) {
+            (Some(owner), Some(client)) => *owner == client,
+            _ => false,
+        }
+    }
+
+    fn is_authorized(&self, request: &Request) -> bool {
+        let token = match &self.config.token {
+            Some(token) => token,
+            None => return true,
+        };
+        let expected = format!("Bearer {}", token);
+        request
+            .headers()
+            .iter()
+            .filter(|h| h.field.equiv("Authorization"))
+            .any(|h| equal_in_constant_time(h.value.as_str().as_bytes(), expected.as_bytes()))
+    }
+}
+
+/**
+ * Serve the repository at the given location until the process is stopped
+ */
+pub fn serve(repository: &Path, address: &str, config: ServerConfig) -> Result<()> {
+    let backend = backend_for_location(repository)?;
+    if config.token.is_none() {
+        log::warn!(
+            "No access token is set, everybody able to connect can read and change the repository"
+        );
+    }
+    let append_only = config.append_only;
+    let server = RepositoryServer::bind(address, config)?;
+    match server.address() {
+        Some(address) if append_only => {
+            log::info!(
+                "Serving {} on {} (append-only)",
+                repository.display(),
+                address
+            )
+        }
+        Some(address) => log::info!("Serving {} on {}", repository.display(), address),
+        None => {}
+    }
+    server.run(&*backend)
+}
+
+fn status(code: u16) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_data(vec![]).with_status_code(code)
+}
+
+fn header(name: &str, value: &str) -> Header {
+    // both are ASCII, so creating the header can't fail
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+/**
+ * Only the entries of the repository may be accessed, and nothing outside of
+ * it
+ */
+fn is_served(path: &str) -> bool {
+    if path.is_empty() {
+        return true;
+    }
+    let components: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    SERVED_ENTRIES.contains(&components[0])
+        && components
+            .iter()
+            .all(|c| !c.is_empty() && *c != "." && *c != "..")
+}
+
+/**
+ * Compare the token of a request without revealing by the time taken, how
+ * much of it matched
+ */
+fn equal_in_constant_time(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn is_lock(path: &str) -> bool {
+    path.starts_with("locks/")
+}
+
+fn client(request: &Request) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(CLIENT_HEADER))
+        .map(|h| h.value.as_str().to_string())
+}
+
+/**
+ * The requested range as first and last byte. Only single ranges with a start
+ * and an end are supported, which is what the client sends.
+ */
+fn range(request: &Request) -> Option<(u64, u64)> {
+    let value = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Range"))?
+        .value
+        .as_str();
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    let start = start.parse::<u64>().ok()?;
+    let end = end.parse::<u64>().ok()?;
+    if end < start {
+        return None;
+    }
+    Some((start, end))
+}
+
+/**
+ * Decode the %XX escapes of a URL path. Invalid escapes and paths, that don't
+ * decode to UTF-8, are rejected.
+ */
+pub fn percent_decode(value: &str) -> Option<String> {
+    let bytes = value.as_bytes();
+    let mut decoded = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = value.get(index + 1..index + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
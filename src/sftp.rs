@@ -0,0 +1,422 @@
+use crate::backend::{Backend, TEMP_PREFIX};
+use crate::errors::{error, Result};
+use rand::RngCore;
+use ssh2::{
+    CheckResult, ErrorCode, KnownHostFileKind, OpenFlags, OpenType, RenameFlags, Session, Sftp,
+};
+use std::cell::Cell;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+
+/**
+ * Timeout for single operations on the server in milliseconds
+ */
+const TIMEOUT: u32 = 5 * 60 * 1000;
+/**
+ * Renaming without OVERWRITE must fail, if the new name exists
+ */
+const NO_REPLACE: RenameFlags =
+    RenameFlags::from_bits_truncate(RenameFlags::ATOMIC.bits() | RenameFlags::NATIVE.bits());
+// status codes of the SFTP protocol
+const LIBSSH2_FX_NO_SUCH_FILE: i32 = 2;
+const LIBSSH2_FX_NO_SUCH_PATH: i32 = 10;
+
+/**
+ * The parts of a location of the form sftp://[user@]host[:port]/path. The
+ * path is absolute, unless it starts with /~/.
+ */
+#[derive(Debug, PartialEq)]
+pub struct SftpLocation {
+    pub user: String,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl SftpLocation {
+    /**
+     * Parse a location. Without a user name, the name of the local user is used.
+     */
+    pub fn parse(location: &str) -> Result<SftpLocation> {
+        let rest = match location.strip_prefix("sftp://") {
+            Some(rest) => rest,
+            None => return error("SFTP locations must start with sftp://", None),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => return error("The SFTP location doesn't contain a path", None),
+        };
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (user.to_string(), host_port),
+            None => (
+                std::env::var("USER").unwrap_or_else(|_| String::from("root")),
+                authority,
+            ),
+        };
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) => match port.parse::<u16>() {
+                Ok(port) => (host, port),
+                Err(e) => return error("Invalid port in SFTP location", Some(e.into())),
+            },
+            None => (host_port, 22),
+        };
+        if host.is_empty() {
+            return error("The SFTP location doesn't name a host", None);
+        }
+        // paths starting with ~ are relative to the home directory of the user
+        let path = match path.strip_prefix("/~/") {
+            Some(relative) => relative,
+            None => path,
+        };
+        Ok(SftpLocation {
+            user,
+            host: host.to_string(),
+            port,
+            path: path.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+/**
+ * A repository in a directory on a server reachable over SSH. The directory
+ * has the same layout as a repository on a local file system, so the same
+ * repository can be used both ways.
+ */
+pub struct SftpBackend {
+    // the session must outlive the SFTP channel
+    _session: Session,
+    sftp: Sftp,
+    root: PathBuf,
+    /**
+     * Whether renaming refuses to replace existing files, once it is known
+     */
+    exclusive_rename: Cell<Option<bool>>,
+}
+
+impl SftpBackend {
+    /**
+     * Connect to the server of the location. The host key must be listed in
+     * ~/.ssh/known_hosts. The user is authenticated with the SSH agent or with
+     * the key in BACKRUB_SSH_KEY, ~/.ssh/id_ed25519, ~/.ssh/id_ecdsa or
+     * ~/.ssh/id_rsa.
+     */
+    pub fn connect(location: &str) -> Result<SftpBackend> {
+        let location = SftpLocation::parse(location)?;
+        let stream = TcpStream::connect((location.host.as_str(), location.port))
+            .or_else(|e| error("Could not connect to the SFTP server", Some(e.into())))?;
+        let mut session =
+            Session::new().or_else(|e| error("Could not create SSH session", Some(e.into())))?;
+        session.set_tcp_stream(stream);
+        session.set_timeout(TIMEOUT);
+        session
+            .handshake()
+            .or_else(|e| error("SSH handshake failed", Some(e.into())))?;
+        verify_host_key(&session, &location)?;
+        authenticate(&session, &location.user)?;
+        let sftp = session
+            .sftp()
+            .or_else(|e| error("Could not start SFTP", Some(e.into())))?;
+        Ok(SftpBackend {
+            _session: session,
+            sftp,
+            root: PathBuf::from(location.path),
+            exclusive_rename: Cell::new(None),
+        })
+    }
+
+    /**
+     * Whether renaming fails, if the new name exists. This is what the SFTP
+     * protocol demands and what OpenSSH implements by hard linking the file,
+     * just like its hardlink@openssh.com extension, but some servers replace
+     * the existing file instead. The ssh2 crate can't send the extensions of
+     * OpenSSH, so the behaviour is tried once with two temporary files.
+     */
+    fn has_exclusive_rename(&self) -> bool {
+        if let Some(exclusive) = self.exclusive_rename.get() {
+            return exclusive;
+        }
+        let first = temp_path(&self.root);
+        let second = temp_path(&self.root);
+        if self.create_file(&first, b"first").is_err() {
+            // the repository doesn't exist yet, try again later
+            return false;
+        }
+        let exclusive = self.create_file(&second, b"second").is_ok()
+            && self.sftp.rename(&first, &second, Some(NO_REPLACE)).is_err()
+            && self.sftp.stat(&first).is_ok()
+            && self.read_file(&second).ok().as_deref() == Some(b"second" as &[u8]);
+        self.sftp.unlink(&first).ok();
+        self.sftp.unlink(&second).ok();
+        if !exclusive {
+            log::warn!(
+                "The SFTP server replaces files when renaming, new files are written in place"
+            );
+        }
+        self.exclusive_rename.set(Some(exclusive));
+        exclusive
+    }
+
+    fn read_file(&self, path: &Path) -> Result<Vec<u8>> {
+        let mut file = self
+            .sftp
+            .open(path)
+            .or_else(|e| error("Could not open file", Some(e.into())))?;
+        let mut data = vec![];
+        file.read_to_end(&mut data)
+            .or_else(|e| error("Could not read file", Some(e.into())))?;
+        Ok(data)
+    }
+
+    fn create_parent(&self, path: &Path) -> Result<()> {
+        match path.parent() {
+            Some(parent) => self.create_dirs(parent),
+            None => Ok(()),
+        }
+    }
+
+    fn create_dirs(&self, path: &Path) -> Result<()> {
+        if self.sftp.stat(path).is_ok() {
+            return Ok(());
+        }
+        self.create_parent(path)?;
+        match self.sftp.mkdir(path, 0o755) {
+            Ok(()) => Ok(()),
+            // another client may have created the directory concurrently
+            Err(_) if self.sftp.stat(path).is_ok() => Ok(()),
+            Err(e) => error("Could not create directory", Some(e.into())),
+        }
+    }
+
+    /**
+     * Create a file, that must not exist yet, and its directory if necessary
+     */
+    fn create_file_in_dir(&self, path: &Path, data: &[u8]) -> Result<()> {
+        if self.create_file(path, data).is_err() {
+            // the directory may not exist yet
+            self.create_parent(path)?;
+            self.create_file(path, data)?;
+        }
+        Ok(())
+    }
+
+    fn create_file(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let mut file = self
+            .sftp
+            .open_mode(
+                path,
+                OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE,
+                0o644,
+                OpenType::File,
+            )
+            .or_else(|e| error("Could not create file", Some(e.into())))?;
+        if let Err(e) = file.write_all(data) {
+            self.sftp.unlink(path).ok();
+            return error("Could not write file", Some(e.into()));
+        }
+        // not every server supports flushing files to disk
+        if let Err(e) = file.fsync() {
+            log::debug!("Could not flush {} to disk: {}", path.display(), e);
+        }
+        Ok(())
+    }
+
+    fn list_dir(&self, dir: &Path, prefix: &str, files: &mut Vec<(String, u64)>) -> Result<()> {
+        let entries = self
+            .sftp
+            .readdir(dir)
+            .or_else(|e| error("Could not read directory", Some(e.into())))?;
+        for (path, stat) in entries {
+            let name = match path.file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => continue,
+            };
+            if name.starts_with(TEMP_PREFIX) {
+                continue;
+            }
+            if stat.is_dir() {
+                self.list_dir(&path, &format!("{}{}/", prefix, name), files)?;
+            } else if stat.is_file() {
+                files.push((format!("{}{}", prefix, name), stat.size.unwrap_or(0)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Backend for SftpBackend {
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        self.read_file(&self.root.join(path))
+    }
+    fn read_range(&self, path: &str, offset: u64, length: u64) -> Result<Vec<u8>> {
+        let mut file = self
+            .sftp
+            .open(self.root.join(path))
+            .or_else(|e| error("Could not open file", Some(e.into())))?;
+        let size = file
+            .stat()
+            .or_else(|e| error("Could not read file size", Some(e.into())))?
+            .size
+            .unwrap_or(0);
+        match offset.checked_add(length) {
+            Some(end) if end <= size => {}
+            _ => return error("The range lies outside of the file", None),
+        }
+        file.seek(SeekFrom::Start(offset))
+            .or_else(|e| error("Could not read file", Some(e.into())))?;
+        let mut data = vec![0; length as usize];
+        file.read_exact(&mut data)
+            .or_else(|e| error("Could not read file", Some(e.into())))?;
+        Ok(data)
+    }
+    /**
+     * Files are written under a temporary name first. New files are renamed
+     * into place, if the server refuses to replace existing files when
+     * renaming. Otherwise they are created exclusively under their final name
+     * and may be seen, before they are complete. Replacing a file removes the
+     * old one right before renaming, so there is a short moment, in which
+     * neither version exists.
+     */
+    fn write(&self, path: &str, data: &[u8], overwrite: bool) -> Result<()> {
+        let file_path = self.root.join(path);
+        if !overwrite && !self.has_exclusive_rename() {
+            return self.create_file_in_dir(&file_path, data);
+        }
+        let temp_path = match file_path.parent() {
+            Some(parent) => temp_path(parent),
+            None => return error("File has no parent directory", None),
+        };
+        self.create_file_in_dir(&temp_path, data)?;
+        if !overwrite {
+            if let Err(e) = self.sftp.rename(&temp_path, &file_path, Some(NO_REPLACE)) {
+                self.sftp.unlink(&temp_path).ok();
+                return error("Could not move file into place", Some(e.into()));
+            }
+            return Ok(());
+        }
+        let mut result = self.sftp.rename(&temp_path, &file_path, None);
+        if result.is_err() && self.sftp.stat(&file_path).is_ok() {
+            self.sftp.unlink(&file_path).ok();
+            result = self.sftp.rename(&temp_path, &file_path, None);
+        }
+        if let Err(e) = result {
+            self.sftp.unlink(&temp_path).ok();
+            return error("Could not move file into place", Some(e.into()));
+        }
+        Ok(())
+    }
+    fn exists(&self, path: &str) -> Result<bool> {
+        match self.sftp.stat(&self.root.join(path)) {
+            Ok(_) => Ok(true),
+            Err(e) if is_not_found(&e) => Ok(false),
+            Err(e) => error("Could not query file", Some(e.into())),
+        }
+    }
+    fn size(&self, path: &str) -> Result<u64> {
+        match self.sftp.stat(&self.root.join(path)) {
+            Ok(stat) => stat
+                .size
+                .map_or_else(|| error("The server didn't report the file size", None), Ok),
+            Err(e) => error("Could not query file", Some(e.into())),
+        }
+    }
+    fn list(&self, dir: &str) -> Result<Vec<(String, u64)>> {
+        let dir_path = self.root.join(dir);
+        let mut files = vec![];
+        match self.sftp.stat(&dir_path) {
+            Ok(stat) if stat.is_dir() => self.list_dir(&dir_path, "", &mut files)?,
+            Ok(_) => {}
+            Err(e) if is_not_found(&e) => {}
+            Err(e) => return error("Could not read directory", Some(e.into())),
+        }
+        Ok(files)
+    }
+    fn remove(&self, path: &str) -> Result<()> {
+        self.sftp
+            .unlink(&self.root.join(path))
+            .or_else(|e| error("Could not remove file", Some(e.into())))
+    }
+    fn create_dir(&self, dir: &str) -> Result<()> {
+        self.create_dirs(&self.root.join(dir))
+    }
+}
+
+/**
+ * A new name for a temporary file in the directory
+ */
+fn temp_path(dir: &Path) -> PathBuf {
+    dir.join(format!(
+        "{}{:016x}",
+        TEMP_PREFIX,
+        rand::thread_rng().next_u64()
+    ))
+}
+
+/**
+ * Whether the file or one of its parent directories doesn't exist
+ */
+fn is_not_found(e: &ssh2::Error) -> bool {
+    matches!(
+        e.code(),
+        ErrorCode::SFTP(LIBSSH2_FX_NO_SUCH_FILE) | ErrorCode::SFTP(LIBSSH2_FX_NO_SUCH_PATH)
+    )
+}
+
+fn ssh_dir() -> Result<PathBuf> {
+    match std::env::var("HOME") {
+        Ok(home) => Ok(Path::new(&home).join(".ssh")),
+        Err(e) => error("Could not determine the home directory", Some(e.into())),
+    }
+}
+
+/**
+ * Make sure the server is the one known from earlier connections
+ */
+fn verify_host_key(session: &Session, location: &SftpLocation) -> Result<()> {
+    let mut known_hosts = session
+        .known_hosts()
+        .or_else(|e| error("Could not read known hosts", Some(e.into())))?;
+    let known_hosts_file = ssh_dir()?.join("known_hosts");
+    if known_hosts_file.exists() {
+        known_hosts
+            .read_file(&known_hosts_file, KnownHostFileKind::OpenSSH)
+            .or_else(|e| error("Could not read known hosts", Some(e.into())))?;
+    }
+    let key = match session.host_key() {
+        Some((key, _)) => key,
+        None => return error("The SFTP server didn't send a host key", None),
+    };
+    match known_hosts.check_port(&location.host, location.port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => error(
+            "The host key of the SFTP server doesn't match the known host key",
+            None,
+        ),
+        CheckResult::NotFound => error(
+            "The SFTP server is unknown. Connect to it with ssh once to add its host key to known_hosts",
+            None,
+        ),
+        CheckResult::Failure => error("Could not verify the host key of the SFTP server", None),
+    }
+}
+
+fn authenticate(session: &Session, user: &str) -> Result<()> {
+    if session.userauth_agent(user).is_ok() && session.authenticated() {
+        return Ok(());
+    }
+    let mut keys = vec![];
+    if let Ok(key) = std::env::var("BACKRUB_SSH_KEY") {
+        keys.push(PathBuf::from(key));
+    }
+    let ssh_dir = ssh_dir()?;
+    for name in &["id_ed25519", "id_ecdsa", "id_rsa"] {
+        keys.push(ssh_dir.join(name));
+    }
+    for key in keys.iter().filter(|key| key.exists()) {
+        if session.userauth_pubkey_file(user, None, key, None).is_ok() && session.authenticated() {
+            log::debug!("Authenticated with key {}", key.display());
+            return Ok(());
+        }
+    }
+    error("Could not authenticate at the SFTP server", None)
+}
@@ -1,12 +1,18 @@
+use crate::backendrepository::BackendRepository;
 use crate::common::read_key;
+use crate::common::KeySource;
 use crate::errors::Result;
-use crate::fsrepository::FsRepository;
 use crate::repository::Repository;
 use std::path::Path;
 
-pub fn show(repository: &Path, name: &String, contents: bool) -> Result<()> {
-    let mut repo = FsRepository::new(&repository);
-    let key = read_key()?;
+pub fn show(
+    repository: &Path,
+    key_source: &KeySource,
+    name: &String,
+    contents: bool,
+) -> Result<()> {
+    let mut repo = BackendRepository::for_location(repository)?;
+    let key = read_key(key_source)?;
     repo.open(key)?;
     let instance = repo.open_instance(&name)?;
     println!("-----\n{}\n-----", instance);
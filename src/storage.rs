@@ -0,0 +1,160 @@
+use crate::backend::Backend;
+use crate::errors::{error, Result};
+use std::rc::Rc;
+
+/**
+ * The kinds of objects stored in a repository
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ObjectKind {
+    /**
+     * The unencrypted meta data describing the repository
+     */
+    Meta,
+    /**
+     * Encrypted keys and password slots
+     */
+    Key,
+    /**
+     * Blocks stored on their own by older versions, named by their ID
+     */
+    Block,
+    Pack,
+    /**
+     * The encrypted index of the pack with the same name
+     */
+    PackIndex,
+    Instance,
+    Lock,
+}
+
+/**
+ * Storage for the objects of a repository.
+ *
+ * Objects are opaque byte strings identified by their kind and name. Storage
+ * knows nothing about encryption or the encoding of the objects, which is
+ * layered on top by the repository, so new kinds of storage only implement
+ * these few operations.
+ */
+pub trait Storage {
+    /**
+     * Prepare the storage for a new repository
+     */
+    fn create(&self) -> Result<()>;
+    /**
+     * Store an object, so that it either exists completely or not at all.
+     * Unless overwrite is set, an existing object is never replaced and
+     * storing it fails.
+     */
+    fn put(&self, kind: ObjectKind, name: &str, data: &[u8], overwrite: bool) -> Result<()>;
+    fn get(&self, kind: ObjectKind, name: &str) -> Result<Vec<u8>>;
+    /**
+     * Read length bytes starting at offset from an object
+     */
+    fn get_range(&self, kind: ObjectKind, name: &str, offset: u64, length: u64) -> Result<Vec<u8>>;
+    /**
+     * The names and sizes of all objects of the given kind
+     */
+    fn list(&self, kind: ObjectKind) -> Result<Vec<(String, u64)>>;
+    fn delete(&self, kind: ObjectKind, name: &str) -> Result<()>;
+    fn exists(&self, kind: ObjectKind, name: &str) -> Result<bool>;
+    /**
+     * Also flush the parent directory to disk after writing a file. Storage,
+     * that doesn't write to a local file system, ignores this.
+     */
+    fn set_sync_directories(&self, _sync_directories: bool) {}
+}
+
+/**
+ * Storage keeping every object in a file of a backend. Each kind of object
+ * has its own directory. Blocks and packs are spread over subdirectories
+ * named by the first two characters of their names, so that no directory
+ * grows too large.
+ */
+pub struct FileStorage {
+    backend: Rc<dyn Backend>,
+}
+
+impl FileStorage {
+    pub fn new(backend: Rc<dyn Backend>) -> FileStorage {
+        FileStorage { backend }
+    }
+}
+
+fn directory(kind: ObjectKind) -> &'static str {
+    match kind {
+        ObjectKind::Meta => "",
+        ObjectKind::Key => "keys",
+        ObjectKind::Block => "blocks",
+        ObjectKind::Pack => "packs",
+        ObjectKind::PackIndex => "index",
+        ObjectKind::Instance => "instances",
+        ObjectKind::Lock => "locks",
+    }
+}
+
+fn is_spread(kind: ObjectKind) -> bool {
+    kind == ObjectKind::Block || kind == ObjectKind::Pack
+}
+
+fn path(kind: ObjectKind, name: &str) -> Result<String> {
+    if name.is_empty() || name.split('/').any(|c| c.is_empty() || c == "..") {
+        return error("Invalid object name", None);
+    }
+    Ok(match kind {
+        ObjectKind::Meta => name.to_string(),
+        _ if is_spread(kind) && name.len() > 2 => {
+            format!("{}/{}/{}", directory(kind), &name[..2], &name[2..])
+        }
+        _ => format!("{}/{}", directory(kind), name),
+    })
+}
+
+impl Storage for FileStorage {
+    fn create(&self) -> Result<()> {
+        self.backend.create_dir("")?;
+        for kind in &[
+            ObjectKind::Pack,
+            ObjectKind::PackIndex,
+            ObjectKind::Instance,
+            ObjectKind::Key,
+            ObjectKind::Lock,
+        ] {
+            self.backend.create_dir(directory(*kind))?;
+        }
+        Ok(())
+    }
+    fn put(&self, kind: ObjectKind, name: &str, data: &[u8], overwrite: bool) -> Result<()> {
+        self.backend.write(&path(kind, name)?, data, overwrite)
+    }
+    fn get(&self, kind: ObjectKind, name: &str) -> Result<Vec<u8>> {
+        self.backend.read(&path(kind, name)?)
+    }
+    fn get_range(&self, kind: ObjectKind, name: &str, offset: u64, length: u64) -> Result<Vec<u8>> {
+        self.backend.read_range(&path(kind, name)?, offset, length)
+    }
+    fn list(&self, kind: ObjectKind) -> Result<Vec<(String, u64)>> {
+        let files = self.backend.list(directory(kind))?;
+        Ok(match kind {
+            // the meta data lives next to the directories of the other kinds
+            ObjectKind::Meta => files
+                .into_iter()
+                .filter(|(name, _)| !name.contains('/'))
+                .collect(),
+            _ if is_spread(kind) => files
+                .into_iter()
+                .map(|(name, size)| (name.replacen('/', "", 1), size))
+                .collect(),
+            _ => files,
+        })
+    }
+    fn delete(&self, kind: ObjectKind, name: &str) -> Result<()> {
+        self.backend.remove(&path(kind, name)?)
+    }
+    fn exists(&self, kind: ObjectKind, name: &str) -> Result<bool> {
+        self.backend.exists(&path(kind, name)?)
+    }
+    fn set_sync_directories(&self, sync_directories: bool) {
+        self.backend.set_sync_directories(sync_directories);
+    }
+}
@@ -0,0 +1,183 @@
+#[cfg(test)]
+mod checktest {
+    use backrub::backendrepository::BackendRepository;
+    use backrub::backup::{BackupEntry, BackupInstance, EntryList, EntryType, FileEntryData};
+    use backrub::backupobject::BackupObject;
+    use backrub::check::{check_repository, DataCheck};
+    use backrub::crypto::InputKey;
+    use backrub::errors::Result;
+    use backrub::os::unix::{get_meta_data, XattrFilter};
+    use backrub::repository::{BackupBlockId, Repository, RepositoryConfig};
+    use rmp_serde::Serializer;
+    use serde::Serialize;
+
+    fn open_test_repository(path: &std::path::Path) -> Result<BackendRepository> {
+        let mut repo = BackendRepository::new(path);
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        Ok(repo)
+    }
+
+    fn store_instance(
+        repo: &BackendRepository,
+        name: &str,
+        blocks: Vec<BackupBlockId>,
+        meta_source: &std::path::Path,
+    ) -> Result<()> {
+        let mut object_buffer = vec![];
+        BackupObject {
+            blocks,
+            sizes: vec![],
+        }
+        .serialize(&mut Serializer::new(&mut object_buffer))
+        .unwrap();
+        let (object_id, _) = repo.add_block(&object_buffer)?;
+        let entries = EntryList::from(vec![BackupEntry {
+            name: String::from("file"),
+            entry_type: EntryType::File(FileEntryData {
+                block_list_id: object_id,
+            }),
+            meta: get_meta_data(meta_source, &XattrFilter::default())?,
+        }]);
+        let (entry_list_id, _) = repo.store_entry_list(&entries)?;
+        repo.finish_backup(
+            BackupInstance {
+                name: String::from(name),
+                time: 0,
+                entry_list_id,
+                tags: vec![],
+            },
+            false,
+        )
+    }
+
+    fn pack_files(repo_path: &std::path::Path) -> Vec<std::path::PathBuf> {
+        walkdir::WalkDir::new(repo_path.join("packs"))
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect()
+    }
+
+    #[test]
+    fn healthy_repository_passes_the_check() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let meta_source = assert_fs::NamedTempFile::new("meta").unwrap();
+        std::fs::write(meta_source.path(), b"").unwrap();
+        let repo = open_test_repository(temp.path())?;
+        let (data, _) = repo.add_block(b"some data")?;
+        store_instance(&repo, "instance", vec![data], meta_source.path())?;
+
+        let report = check_repository(&repo, &DataCheck::All)?;
+
+        assert2::assert!(report.is_healthy());
+        assert2::assert!(report.checked_instances == 1);
+        assert2::assert!(report.checked_blocks == repo.list_blocks()?.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn missing_blocks_are_reported_for_the_affected_files() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let meta_source = assert_fs::NamedTempFile::new("meta").unwrap();
+        std::fs::write(meta_source.path(), b"").unwrap();
+        let repo = open_test_repository(temp.path())?;
+        let (data, _) = repo.add_block(b"some data")?;
+        store_instance(&repo, "instance", vec![data.clone()], meta_source.path())?;
+        repo.remove_block(&data)?;
+
+        let report = check_repository(&repo, &DataCheck::None)?;
+
+        assert2::assert!(!report.is_healthy());
+        assert2::assert!(report.damaged_instances.len() == 1);
+        assert2::assert!(report.damaged_instances[0].name == "instance");
+        assert2::assert!(report.damaged_instances[0].files.len() == 1);
+        assert2::assert!(report.damaged_instances[0].files[0].name == "file");
+
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted_blocks_are_only_found_when_reading_data() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let meta_source = assert_fs::NamedTempFile::new("meta").unwrap();
+        std::fs::write(meta_source.path(), b"").unwrap();
+        let repo = open_test_repository(temp.path())?;
+        let (data, _) = repo.add_block(b"some data")?;
+        // write the data block to its own pack, which is then damaged
+        repo.flush()?;
+        let packs = pack_files(temp.path());
+        assert2::assert!(packs.len() == 1);
+        std::fs::write(&packs[0], b"garbage").unwrap();
+        store_instance(&repo, "instance", vec![data.clone()], meta_source.path())?;
+
+        assert2::assert!(check_repository(&repo, &DataCheck::None)?.is_healthy());
+
+        let report = check_repository(&repo, &DataCheck::All)?;
+        assert2::assert!(report.damaged_blocks.len() == 1);
+        assert2::assert!(report.damaged_blocks[0].0 == data);
+        assert2::assert!(report.damaged_instances[0].files.len() == 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unreadable_key_and_index_files_are_skipped_and_reported() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let repo = open_test_repository(temp.path())?;
+        let (data, _) = repo.add_block(b"some data")?;
+        repo.flush()?;
+        assert2::assert!(check_repository(&repo, &DataCheck::All)?.is_healthy());
+        // files another client could add to an append-only repository
+        for file in &["keys/forged.slot", "keys/1234.dek", "index/forged"] {
+            std::fs::write(temp.path().join(file), b"garbage").unwrap();
+        }
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(repo.has_block(&data)?);
+        let report = check_repository(&repo, &DataCheck::All)?;
+        assert2::assert!(!report.is_healthy());
+        let mut files: Vec<String> = report.unreadable_files.into_iter().map(|f| f.0).collect();
+        files.sort();
+        assert2::assert!(
+            files == vec!["key 1234.dek", "pack index forged", "password slot forged"]
+        );
+
+        // a wrong password still fails
+        let mut repo = BackendRepository::new(temp.path());
+        assert2::assert!(repo.open(InputKey::from(b"WrongKey" as &[u8])).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn subsets_cover_all_blocks_exactly_once() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let repo = open_test_repository(temp.path())?;
+        for i in 0..20u8 {
+            repo.add_block(&[i; 16])?;
+        }
+        repo.flush()?;
+
+        let mut checked = 0;
+        for n in 1..=3 {
+            checked += check_repository(&repo, &DataCheck::Subset(n, 3))?.checked_blocks;
+        }
+
+        assert2::assert!(checked == 20);
+        assert2::assert!(DataCheck::parse_subset("0/3").is_err());
+        assert2::assert!(DataCheck::parse_subset("4/3").is_err());
+        assert2::assert!(DataCheck::percentage(100.0).is_ok());
+        assert2::assert!(DataCheck::percentage(150.0).is_err());
+        assert2::assert!(DataCheck::percentage(-1.0).is_err());
+        assert2::assert!(DataCheck::percentage(f64::NAN).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+#[cfg(test)]
+mod chunkertest {
+    use backrub::chunker::Chunker;
+    use backrub::chunker::ChunkerParams;
+    use rand::prelude::*;
+    use std::io::{Cursor, Read};
+
+    fn test_params() -> ChunkerParams {
+        ChunkerParams {
+            min_size: 256,
+            avg_size: 1024,
+            max_size: 4096,
+        }
+    }
+
+    /// pseudo-random data, seeded to keep the chunk boundaries reproducible
+    fn random_data(size: usize) -> Vec<u8> {
+        let mut data = vec![0; size];
+        StdRng::seed_from_u64(0x6261_636b_7275_6221).fill_bytes(&mut data);
+        data
+    }
+
+    fn chunk(data: &[u8], params: &ChunkerParams) -> Vec<Vec<u8>> {
+        Chunker::new(Cursor::new(data), params)
+            .collect::<backrub::errors::Result<Vec<Vec<u8>>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn chunks_reassemble_to_the_input() {
+        let params = test_params();
+        let data = random_data(256 * 1024);
+        let chunks = chunk(&data, &params);
+
+        assert2::assert!(chunks.concat() == data);
+        let (last, others) = chunks.split_last().unwrap();
+        assert2::assert!(others.iter().all(|c| c.len() >= params.min_size as usize));
+        assert2::assert!(chunks.iter().all(|c| c.len() <= params.max_size as usize));
+        assert2::assert!(!last.is_empty());
+    }
+
+    #[test]
+    fn inserting_data_only_changes_chunks_locally() {
+        let params = test_params();
+        let data = random_data(256 * 1024);
+        let mut modified = data.clone();
+        modified.insert(100, 42);
+
+        let original_chunks = chunk(&data, &params);
+        let modified_chunks = chunk(&modified, &params);
+        let shared = modified_chunks
+            .iter()
+            .filter(|c| original_chunks.contains(c))
+            .count();
+
+        assert2::assert!(shared >= original_chunks.len() - 2);
+    }
+
+    /// reader returning the data in pieces of varying, small sizes
+    struct TricklingReader {
+        data: Vec<u8>,
+        position: usize,
+        reads: usize,
+    }
+
+    impl Read for TricklingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.reads += 1;
+            let size = [1, 7, 100, 1000, 5000][self.reads % 5];
+            let end = std::cmp::min(self.data.len(), self.position + size);
+            let bytes = std::cmp::min(buf.len(), end - self.position);
+            buf[..bytes].copy_from_slice(&self.data[self.position..self.position + bytes]);
+            self.position += bytes;
+            Ok(bytes)
+        }
+    }
+
+    #[test]
+    fn chunks_do_not_depend_on_the_read_sizes() {
+        let params = test_params();
+        let data = random_data(256 * 1024);
+        let reader = TricklingReader {
+            data: data.clone(),
+            position: 0,
+            reads: 0,
+        };
+        let chunks = Chunker::new(reader, &params)
+            .collect::<backrub::errors::Result<Vec<Vec<u8>>>>()
+            .unwrap();
+
+        assert2::assert!(chunks == chunk(&data, &params));
+    }
+
+    #[test]
+    fn empty_input_yields_no_chunks() {
+        assert2::assert!(chunk(&[], &test_params()).is_empty());
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        let params = ChunkerParams {
+            min_size: 256,
+            avg_size: 1000,
+            max_size: 4096,
+        };
+        assert2::assert!(params.validate().is_err());
+        assert2::assert!(ChunkerParams::default().validate().is_ok());
+    }
+}
@@ -1,12 +1,53 @@
 #[cfg(test)]
 mod fsrepotest {
+    use backrub::compression::Codec;
+    use backrub::compression::Compression;
     use backrub::crypto::decode_keyed_block;
     use backrub::crypto::encode_keyed_block;
+    use backrub::crypto::keyed_block_key_index;
     use backrub::crypto::DataEncryptionKey;
+    use backrub::crypto::InputKey;
+    use backrub::crypto::KdfParams;
+    use backrub::crypto::KdfVariant;
     use backrub::crypto::KeySet;
+    use backrub::crypto::KeyedCryptoBlock;
+    use backrub::crypto::MasterKey;
+    use backrub::crypto::KEYED_BLOCK_HEADER_SIZE;
     use rand::prelude::*;
+    use serde::{Deserialize, Serialize};
     use std::io::Cursor;
 
+    fn test_key_set() -> KeySet {
+        let mut test_key_set = KeySet::new();
+        test_key_set.insert(
+            1,
+            DataEncryptionKey {
+                created_at: 0,
+                value: Vec::from(b"0123456789ABCDEF0123456789ABCDEF" as &[u8]),
+            },
+        );
+        test_key_set
+    }
+
+    fn encode(data: &[u8], key_set: &KeySet, compression: Compression) -> Vec<u8> {
+        let mut encoded = vec![];
+        encode_keyed_block(
+            &mut encoded,
+            data,
+            &(1, key_set.get(&1).unwrap().clone()),
+            compression,
+        )
+        .unwrap();
+        encoded
+    }
+
+    fn codec_of(encoded: &[u8]) -> Codec {
+        let block: KeyedCryptoBlock =
+            Deserialize::deserialize(&mut rmp_serde::Deserializer::new(Cursor::new(encoded)))
+                .unwrap();
+        block.codec
+    }
+
     #[test]
     fn keyed_block_roundtrip_results_in_original() {
         let mut data = vec![0; 65535];
@@ -27,10 +68,114 @@ mod fsrepotest {
             &mut encoded,
             &data,
             &(1, test_key_set.get(&1).unwrap().clone()),
+            Compression::None,
         )
         .unwrap();
         let decoded = decode_keyed_block(&mut Cursor::new(encoded), &test_key_set).unwrap();
 
         assert2::assert!(data == decoded);
     }
+
+    #[test]
+    fn key_index_is_read_from_the_header_alone() {
+        let key = DataEncryptionKey {
+            created_at: 0,
+            value: Vec::from(b"0123456789ABCDEF0123456789ABCDEF" as &[u8]),
+        };
+        for key_index in [0, 127, 128, 255, 256, 70000, 1 << 40, u64::MAX] {
+            let mut encoded = vec![];
+            encode_keyed_block(
+                &mut encoded,
+                b"some data",
+                &(key_index, key.clone()),
+                Compression::None,
+            )
+            .unwrap();
+            let header = &encoded[..KEYED_BLOCK_HEADER_SIZE as usize];
+            assert2::assert!(keyed_block_key_index(Cursor::new(header)).unwrap() == key_index);
+        }
+        assert2::assert!(keyed_block_key_index(Cursor::new(b"garbage")).is_err());
+    }
+
+    #[test]
+    fn compressed_block_roundtrip_results_in_original() {
+        let data = b"All work and no play makes Jack a dull boy. ".repeat(1000);
+        let key_set = test_key_set();
+
+        for (compression, codec) in &[
+            (Compression::Lz4, Codec::Lz4),
+            (Compression::Zstd(3), Codec::Zstd),
+        ] {
+            let encoded = encode(&data, &key_set, *compression);
+            assert2::assert!(encoded.len() < data.len());
+            assert2::assert!(codec_of(&encoded) == *codec);
+            let decoded = decode_keyed_block(&mut Cursor::new(encoded), &key_set).unwrap();
+            assert2::assert!(data == decoded);
+        }
+    }
+
+    #[test]
+    fn incompressible_block_is_stored_uncompressed() {
+        let mut data = vec![0; 65535];
+        rand::thread_rng().fill_bytes(&mut data);
+        let key_set = test_key_set();
+
+        let encoded = encode(&data, &key_set, Compression::Zstd(3));
+        assert2::assert!(codec_of(&encoded) == Codec::None);
+        let decoded = decode_keyed_block(&mut Cursor::new(encoded), &key_set).unwrap();
+        assert2::assert!(data == decoded);
+    }
+
+    #[test]
+    fn compression_settings_are_parsed() {
+        assert2::assert!("none".parse::<Compression>().unwrap() == Compression::None);
+        assert2::assert!("lz4".parse::<Compression>().unwrap() == Compression::Lz4);
+        assert2::assert!("zstd:7".parse::<Compression>().unwrap() == Compression::Zstd(7));
+        assert2::assert!("gzip".parse::<Compression>().is_err());
+    }
+
+    #[test]
+    fn kdf_parameters_of_old_password_slots_default_to_legacy_values() {
+        let mut encoded = vec![];
+        (serde_bytes::Bytes::new(b"0123456789ABCDEF"), 3u32)
+            .serialize(&mut rmp_serde::Serializer::new(&mut encoded))
+            .unwrap();
+        let mut deserializer = rmp_serde::Deserializer::new(Cursor::new(encoded));
+        let params: KdfParams = Deserialize::deserialize(&mut deserializer).unwrap();
+        assert2::assert!(params == KdfParams::legacy(b"0123456789ABCDEF", 3));
+        assert2::assert!(params.variant == KdfVariant::Argon2id);
+        assert2::assert!(params.memory_kib == 4096);
+        assert2::assert!(params.lanes == 1);
+    }
+
+    #[test]
+    fn kdf_parameters_change_the_derived_key() {
+        let password = InputKey::from(b"MyTestKey" as &[u8]);
+        let params = KdfParams::legacy(b"0123456789ABCDEF", 2);
+        let key = params.derive(&password).unwrap();
+        let wrapped = key.wrap(&key).unwrap();
+        let same = params.derive(&password).unwrap();
+        assert2::assert!(MasterKey::unwrap(&wrapped, &same).is_ok());
+        for other in [
+            KdfParams {
+                memory_kib: 2048,
+                ..params.clone()
+            },
+            KdfParams {
+                lanes: 2,
+                ..params.clone()
+            },
+            KdfParams {
+                variant: KdfVariant::Argon2i,
+                ..params.clone()
+            },
+            KdfParams {
+                iterations: 3,
+                ..params.clone()
+            },
+        ] {
+            let other_key = other.derive(&password).unwrap();
+            assert2::assert!(MasterKey::unwrap(&wrapped, &other_key).is_err());
+        }
+    }
 }
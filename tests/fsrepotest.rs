@@ -2,34 +2,60 @@
 mod fsrepotest {
     use assert2;
     use assert_fs::prelude::*;
-    use backrub::create::make_backup;
-    use backrub::crypto::InputKey;
+    use backrub::backend::LocalBackend;
+    use backrub::backendrepository::BackendRepository;
+    use backrub::backup::{BackupInstance, EntryList};
+    use backrub::common::KeySource;
+    use backrub::compression::Compression;
+    use backrub::create::{make_backup, BackupOptions};
+    use backrub::crypto::{decode_keyed_block, encode_keyed_block, InputKey};
     use backrub::errors::Result;
-    use backrub::fsrepository::FsRepository;
+    use backrub::pack::PackBuilder;
     use backrub::repository::Repository;
+    use backrub::repository::RepositoryConfig;
     use backrub::restore::restore_backup;
+    use backrub::restore::RestoreOptions;
+    use backrub::storage::{FileStorage, ObjectKind, Storage};
     use rand::prelude::*;
     use rand_distr::Exp;
+    use rmp_serde::Serializer;
+    use serde::Serialize;
     use std::fs;
+    use std::io::Cursor;
     use std::path::Path;
+    use std::rc::Rc;
 
     #[test]
     fn initialize_creates_repo_structure() -> Result<()> {
         let temp = assert_fs::TempDir::new().unwrap();
-        let repo = FsRepository::new(temp.path());
-        repo.initialize(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let repo = BackendRepository::new(temp.path());
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
 
         assert2::assert!(Path::is_file(temp.child("backrub").path()));
-        assert2::assert!(Path::is_dir(temp.child("blocks").path()));
+        assert2::assert!(Path::is_dir(temp.child("packs").path()));
+        assert2::assert!(Path::is_dir(temp.child("index").path()));
         assert2::assert!(Path::is_dir(temp.child("instances").path()));
         assert2::assert!(Path::is_dir(temp.child("keys").path()));
         assert2::assert!(
             fs::read_dir(temp.child("keys").path())
                 .unwrap()
-                .collect::<Vec<std::io::Result<fs::DirEntry>>>()
-                .len()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().extension() == Some(std::ffi::OsStr::new("dek")))
+                .count()
                 == 1
         );
+        assert2::assert!(
+            fs::read_dir(temp.child("keys").path())
+                .unwrap()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().extension() == Some(std::ffi::OsStr::new("slot")))
+                .count()
+                == 1
+        );
+        assert2::assert!(Path::is_file(temp.child("keys/block_id.mac").path()));
 
         Ok(())
     }
@@ -37,21 +63,169 @@ mod fsrepotest {
     #[test]
     fn block_is_stored_in_repository() -> Result<()> {
         let temp = assert_fs::TempDir::new().unwrap();
-        let repo = FsRepository::new(temp.path());
-        repo.initialize(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let mut repo = BackendRepository::new(temp.path());
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
         let string = "This is a test";
-        repo.add_block(string.as_bytes()).unwrap();
+        let (id, size) = repo.add_block(string.as_bytes()).unwrap();
+        repo.flush()?;
 
-        assert2::assert!(Path::is_file(
-            temp.child("blocks/3c/3b66edcfe51f5b15bf372f61e25710ffc1ad3c0e3c60d832b42053a96772cf")
-                .path()
-        ));
-        let block_content = fs::read(
-            temp.child("blocks/3c/3b66edcfe51f5b15bf372f61e25710ffc1ad3c0e3c60d832b42053a96772cf")
-                .path(),
-        )
-        .unwrap();
-        assert2::assert!(block_content == string.as_bytes());
+        let packs: Vec<_> = walkdir::WalkDir::new(temp.child("packs").path())
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .collect();
+        assert2::assert!(packs.len() == 1);
+        assert2::assert!(fs::read_dir(temp.child("index").path()).unwrap().count() == 1);
+        let block_content = repo.read_block(&id)?;
+        assert2::assert!(block_content.len() == size);
+        assert2::assert!(block_content != string.as_bytes());
+        let pack_content = fs::read(packs[0].path()).unwrap();
+        assert2::assert!(pack_content == block_content);
+
+        Ok(())
+    }
+
+    #[test]
+    fn blocks_are_aggregated_into_packs() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let mut repo = BackendRepository::new(temp.path());
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig {
+                compression: Compression::None,
+                pack_size: 4096,
+                ..Default::default()
+            },
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let ids = (0..10u8)
+            .map(|i| repo.add_block(&[i; 1024]).map(|(id, _)| id))
+            .collect::<Result<Vec<_>>>()?;
+        // the packs are written, as soon as they are full
+        assert2::assert!(fs::read_dir(temp.child("index").path()).unwrap().count() == 2);
+        drop(repo);
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(fs::read_dir(temp.child("index").path()).unwrap().count() == 3);
+        assert2::assert!(repo.list_blocks()?.len() == 10);
+        for (i, id) in ids.iter().enumerate() {
+            let data = decode_keyed_block(Cursor::new(repo.read_block(id)?), repo.keys()?)?;
+            assert2::assert!(data == vec![i as u8; 1024]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn blocks_are_only_read_from_locations_matching_their_id() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let mut repo = BackendRepository::new(temp.path());
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let storage = FileStorage::new(Rc::new(LocalBackend::new(temp.path())));
+        let (real, _) = repo.add_block(b"real data")?;
+        repo.flush()?;
+        let (genuine, _) = storage.list(ObjectKind::Pack)?.remove(0);
+        let (fake, _) = repo.add_block(b"fake data")?;
+        repo.flush()?;
+
+        // another client adds a pack claiming to contain the first block
+        let mut builder = PackBuilder::default();
+        builder.add(&fake, &repo.read_block(&fake)?);
+        builder.add(&real, &repo.read_block(&fake)?);
+        let (name, data, index) = builder.finish();
+        let mut index_buffer = vec![];
+        index
+            .serialize(&mut Serializer::new(&mut index_buffer))
+            .unwrap();
+        let mut encoded_index = vec![];
+        encode_keyed_block(
+            &mut encoded_index,
+            &index_buffer,
+            repo.current_key()?,
+            Compression::None,
+        )?;
+        storage.put(ObjectKind::Pack, &name, &data, false)?;
+        storage.put(ObjectKind::PackIndex, &name, &encoded_index, false)?;
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let block = decode_keyed_block(Cursor::new(repo.read_block(&real)?), repo.keys()?)?;
+        assert2::assert!(block == b"real data");
+
+        // without the genuine pack, the block can't be read at all
+        storage.delete(ObjectKind::PackIndex, &genuine)?;
+        storage.delete(ObjectKind::Pack, &genuine)?;
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(repo.read_block(&real).is_err());
+        assert2::assert!(repo.open_object(&real).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn identical_blocks_are_deduplicated_across_sessions() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let first_id = {
+            let mut repo = BackendRepository::new(temp.path());
+            repo.initialize(
+                InputKey::from(b"MyTestKey" as &[u8]),
+                &RepositoryConfig::default(),
+            )?;
+            repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+            repo.add_block(b"This is a test")?.0
+        };
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let (second_id, size) = repo.add_block(b"This is a test")?;
+
+        assert2::assert!(first_id == second_id);
+        assert2::assert!(size == 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn existing_instances_are_only_replaced_on_request() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let mut repo = BackendRepository::new(temp.path());
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let (entry_list_id, _) = repo.store_entry_list(&EntryList::from(vec![]))?;
+        let instance = |time| BackupInstance {
+            name: String::from("instance"),
+            time,
+            entry_list_id: entry_list_id.clone(),
+            tags: vec![],
+        };
+
+        repo.finish_backup(instance(1), false)?;
+        assert2::assert!(repo.finish_backup(instance(2), false).is_err());
+        assert2::assert!(repo.open_instance("instance")?.time == 1);
+        repo.finish_backup(instance(3), true)?;
+        assert2::assert!(repo.open_instance("instance")?.time == 3);
+
+        // no temporary files are left behind
+        assert2::assert!(
+            fs::read_dir(temp.child("instances").path())
+                .unwrap()
+                .count()
+                == 1
+        );
+        assert2::assert!(repo.list_instance_names()? == vec!["instance"]);
 
         Ok(())
     }
@@ -60,7 +234,7 @@ mod fsrepotest {
     // fn object_is_represented_by_correct_block() -> Result<()> {
     //     // let temp = assert_fs::TempDir::new().unwrap();
     //     // let test_path = temp.path().to_str().unwrap();
-    //     // let repo: FsRepository = Repository::new(test_path);
+    //     // let repo: BackendRepository = Repository::new(test_path);
     //     // repo.initialize(InputKey::from(b"MyTestKey" as &[u8]))?;
     //     // let object =
     //     // let string = "This is a test";
@@ -102,7 +276,7 @@ mod fsrepotest {
     //     // let test_path = temp.path().to_str().unwrap();
     //     // let object_id: String;
     //     // {
-    //     //     let repo: FsRepository = Repository::new(test_path);
+    //     //     let repo: BackendRepository = Repository::new(test_path);
     //     //     repo.initialize(InputKey::from(b"MyTestKey" as &[u8]))?;
     //     //     let mut object = BackupObject { blocks: vec![] };
     //     //     //let mut object = repo.start_object("test").unwrap();
@@ -121,7 +295,7 @@ mod fsrepotest {
     //     //     object_id = repo.finish_object(object).unwrap();
     //     // };
     //     // // close everything and re-initialize it
-    //     // let mut repo: FsRepository = Repository::new(test_path);
+    //     // let mut repo: BackendRepository = Repository::new(test_path);
     //     // repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
     //     // let object = repo.open_object(&object_id).unwrap();
     //     // let object_reader = repo.open_object_reader(object)?;
@@ -142,15 +316,20 @@ mod fsrepotest {
         println!("Starting backup process...");
         let repo_temp = assert_fs::TempDir::new().unwrap();
 
-        let repo = FsRepository::new(repo_temp.path());
-        repo.initialize(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let repo = BackendRepository::new(repo_temp.path());
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
         std::env::set_var("BACKRUB_KEY", "MyTestKey");
         make_backup(
             repo_temp.path().to_str().unwrap(),
+            &KeySource::default(),
             &vec![String::from(source_dir.path().to_str().unwrap())],
             temp_cache.path(),
             "ThisRandomBackup",
             &None,
+            &BackupOptions::default(),
         )?;
 
         let restore_dir = assert_fs::TempDir::new().unwrap();
@@ -158,9 +337,11 @@ mod fsrepotest {
         println!("Restoring backup...");
         restore_backup(
             repo_temp.path().to_str().unwrap(),
+            &KeySource::default(),
             restore_path,
             &None,
             "ThisRandomBackup",
+            &RestoreOptions::default(),
         )?;
 
         println!("Comparing source and restored path...");
@@ -229,11 +410,14 @@ mod fsrepotest {
     fn stored_keys_are_loaded_by_the_repo() -> Result<()> {
         let repo_dir = assert_fs::TempDir::new().unwrap();
         {
-            let repo = FsRepository::new(repo_dir.path());
-            repo.initialize(InputKey::from(b"ThisIsATest" as &[u8]))?;
+            let repo = BackendRepository::new(repo_dir.path());
+            repo.initialize(
+                InputKey::from(b"ThisIsATest" as &[u8]),
+                &RepositoryConfig::default(),
+            )?;
         }
 
-        let mut repo = FsRepository::new(repo_dir.path());
+        let mut repo = BackendRepository::new(repo_dir.path());
         repo.open(InputKey::from(b"ThisIsATest" as &[u8]))?;
 
         assert2::assert!(repo.keys()?.len() == 1);
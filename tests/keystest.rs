@@ -0,0 +1,209 @@
+#[cfg(test)]
+mod keystest {
+    use assert_fs::prelude::*;
+    use backrub::backendrepository::BackendRepository;
+    use backrub::common::{read_key, KeySource};
+    use backrub::crypto::{decode_keyed_block, InputKey, KdfSettings};
+    use backrub::errors::Result;
+    use backrub::keys::key_usage;
+    use backrub::repository::{Repository, RepositoryConfig};
+    use std::fs::File;
+    use std::io::Cursor;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn rotated_key_is_used_for_new_blocks() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let (old_key, new_key, old_block, new_block) = {
+            let mut repo = BackendRepository::new(temp.path());
+            repo.initialize(
+                InputKey::from(b"MyTestKey" as &[u8]),
+                &RepositoryConfig::default(),
+            )?;
+            repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+            let old_key = repo.current_key()?.0;
+            let (old_block, _) = repo.add_block(b"encrypted with the old key")?;
+            let new_key = repo.add_key()?;
+            assert2::assert!(repo.current_key()?.0 == new_key);
+            let (new_block, _) = repo.add_block(b"encrypted with the new key")?;
+            (old_key, new_key, old_block, new_block)
+        };
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+
+        assert2::assert!(old_key != new_key);
+        assert2::assert!(repo.keys()?.len() == 2);
+        assert2::assert!(repo.current_key()?.0 == new_key);
+        let usage = key_usage(&repo)?;
+        assert2::assert!(usage.get(&old_key) == Some(&1));
+        assert2::assert!(usage.get(&new_key) == Some(&1));
+        let old_data = decode_keyed_block(Cursor::new(repo.read_block(&old_block)?), repo.keys()?)?;
+        let new_data = decode_keyed_block(Cursor::new(repo.read_block(&new_block)?), repo.keys()?)?;
+        assert2::assert!(old_data == b"encrypted with the old key");
+        assert2::assert!(new_data == b"encrypted with the new key");
+
+        Ok(())
+    }
+
+    #[test]
+    fn changed_password_replaces_the_old_one() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let block = {
+            let mut repo = BackendRepository::new(temp.path());
+            repo.initialize(
+                InputKey::from(b"OldPassword" as &[u8]),
+                &RepositoryConfig::default(),
+            )?;
+            repo.open(InputKey::from(b"OldPassword" as &[u8]))?;
+            let (block, _) = repo.add_block(b"some data")?;
+            repo.change_password(InputKey::from(b"NewPassword" as &[u8]))?;
+            block
+        };
+
+        let mut repo = BackendRepository::new(temp.path());
+        assert2::assert!(repo.open(InputKey::from(b"OldPassword" as &[u8])).is_err());
+        repo.open(InputKey::from(b"NewPassword" as &[u8]))?;
+        let data = decode_keyed_block(Cursor::new(repo.read_block(&block)?), repo.keys()?)?;
+        assert2::assert!(data == b"some data");
+        assert2::assert!(repo.list_passwords()?.len() == 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn passwords_can_be_added_and_revoked() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let (first, second) = {
+            let mut repo = BackendRepository::new(temp.path());
+            repo.initialize(
+                InputKey::from(b"Ops" as &[u8]),
+                &RepositoryConfig::default(),
+            )?;
+            repo.open(InputKey::from(b"Ops" as &[u8]))?;
+            let first = String::from(repo.key_slot().unwrap());
+            let second = repo.add_password(InputKey::from(b"OnCall" as &[u8]))?;
+            (first, second)
+        };
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"OnCall" as &[u8]))?;
+        assert2::assert!(repo.key_slot() == Some(second.as_str()));
+        let current_key = repo.current_key()?.0;
+        repo.open(InputKey::from(b"Ops" as &[u8]))?;
+        assert2::assert!(repo.key_slot() == Some(first.as_str()));
+        assert2::assert!(repo.current_key()?.0 == current_key);
+
+        repo.remove_password(&second)?;
+        assert2::assert!(repo.open(InputKey::from(b"OnCall" as &[u8])).is_err());
+        assert2::assert!(repo.remove_password(&first).is_err());
+        repo.open(InputKey::from(b"Ops" as &[u8]))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn keyfile_is_required_in_addition_to_the_password() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let repo_dir = temp.child("repo");
+        let password_file = temp.child("password");
+        password_file.write_str("MyTestKey\n").unwrap();
+        let keyfile = temp.child("keyfile");
+        keyfile.write_binary(&[0x42; 64]).unwrap();
+        let source = KeySource {
+            password_file: Some(password_file.path().to_path_buf()),
+            keyfile: Some(keyfile.path().to_path_buf()),
+            ..Default::default()
+        };
+        BackendRepository::new(repo_dir.path())
+            .initialize(read_key(&source)?, &RepositoryConfig::default())?;
+
+        let mut repo = BackendRepository::new(repo_dir.path());
+        assert2::assert!(repo.open(InputKey::from(b"MyTestKey" as &[u8])).is_err());
+        let command_source = KeySource {
+            password_command: Some(String::from("echo MyTestKey")),
+            keyfile: Some(keyfile.path().to_path_buf()),
+            ..Default::default()
+        };
+        repo.open(read_key(&command_source)?)?;
+        repo.open(read_key(&source)?)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn password_fd_is_validated_and_left_open() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let password_file = temp.child("password");
+        password_file.write_str("MyTestKey\n").unwrap();
+        let file = File::open(password_file.path()).unwrap();
+        let source = KeySource {
+            password_fd: Some(file.as_raw_fd()),
+            ..Default::default()
+        };
+        let repo_dir = temp.child("repo");
+        BackendRepository::new(repo_dir.path())
+            .initialize(read_key(&source)?, &RepositoryConfig::default())?;
+        BackendRepository::new(repo_dir.path()).open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        // the descriptor is still usable by its owner
+        assert2::assert!(file.metadata().is_ok());
+
+        for fd in &[0, 1, 2, 4711] {
+            let source = KeySource {
+                password_fd: Some(*fd),
+                ..Default::default()
+            };
+            assert2::assert!(read_key(&source).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn kdf_parameters_are_stored_and_can_be_upgraded() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let weak = KdfSettings {
+            memory_kib: 1024,
+            lanes: 1,
+            iterations: Some(2),
+            ..Default::default()
+        };
+        BackendRepository::new(temp.path()).initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig {
+                kdf: weak,
+                ..Default::default()
+            },
+        )?;
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let initial = repo.current_kdf()?;
+        assert2::assert!(initial.memory_kib == 1024);
+        assert2::assert!(initial.lanes == 1);
+        assert2::assert!(initial.iterations == 2);
+        assert2::assert!(repo.meta()?.kdf.as_ref() == Some(&initial));
+
+        let strong = KdfSettings {
+            memory_kib: 2048,
+            lanes: 2,
+            iterations: Some(3),
+            ..Default::default()
+        }
+        .params()?;
+        repo.upgrade_kdf(InputKey::from(b"MyTestKey" as &[u8]), strong.clone())?;
+        let slot = repo.add_password(InputKey::from(b"OnCall" as &[u8]))?;
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(repo.current_kdf()? == strong);
+        assert2::assert!(repo.meta()?.kdf.as_ref() == Some(&strong));
+        repo.open(InputKey::from(b"OnCall" as &[u8]))?;
+        assert2::assert!(repo.key_slot() == Some(slot.as_str()));
+        let added = repo.current_kdf()?;
+        assert2::assert!(added.memory_kib == 2048);
+        assert2::assert!(added.iterations == 3);
+        assert2::assert!(added.salt != strong.salt);
+
+        Ok(())
+    }
+}
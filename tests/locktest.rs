@@ -0,0 +1,104 @@
+#[cfg(test)]
+mod locktest {
+    use backrub::backendrepository::BackendRepository;
+    use backrub::crypto::InputKey;
+    use backrub::errors::Result;
+    use backrub::lock::{list_locks, LockInfo, RepositoryLock, STALE_AFTER};
+    use backrub::os::unix::host_name;
+    use backrub::repository::{Repository, RepositoryConfig};
+    use rmp_serde::Serializer;
+    use serde::Serialize;
+
+    fn open_test_repository(path: &std::path::Path) -> Result<BackendRepository> {
+        let mut repo = BackendRepository::new(path);
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        Ok(repo)
+    }
+
+    fn write_foreign_lock(repo: &BackendRepository, exclusive: bool, time: u64) -> Result<()> {
+        let info = LockInfo {
+            exclusive,
+            host: String::from("some-other-host"),
+            pid: 1,
+            time,
+        };
+        let mut buffer = vec![];
+        info.serialize(&mut Serializer::new(&mut buffer)).unwrap();
+        repo.write_lock("foreign", &buffer, false)
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn shared_locks_can_be_held_concurrently() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let repo = open_test_repository(temp.path())?;
+
+        let first = RepositoryLock::shared(&repo)?;
+        let second = RepositoryLock::shared(&repo)?;
+        assert2::assert!(list_locks(&repo)?.len() == 2);
+        assert2::assert!(RepositoryLock::exclusive(&repo).is_err());
+        assert2::assert!(list_locks(&repo)?.len() == 2);
+
+        drop(first);
+        drop(second);
+        assert2::assert!(repo.list_locks()?.is_empty());
+        let _exclusive = RepositoryLock::exclusive(&repo)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn exclusive_lock_blocks_all_other_locks() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let repo = open_test_repository(temp.path())?;
+
+        let lock = RepositoryLock::exclusive(&repo)?;
+        assert2::assert!(RepositoryLock::shared(&repo).is_err());
+        assert2::assert!(RepositoryLock::exclusive(&repo).is_err());
+
+        let locks = list_locks(&repo)?;
+        assert2::assert!(locks.len() == 1);
+        assert2::assert!(locks[0].1.exclusive);
+        assert2::assert!(locks[0].1.host == host_name());
+        assert2::assert!(locks[0].1.pid == std::process::id());
+        drop(lock);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stale_locks_are_ignored() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let repo = open_test_repository(temp.path())?;
+        write_foreign_lock(&repo, true, now() - STALE_AFTER.as_secs() - 60)?;
+
+        assert2::assert!(list_locks(&repo)?[0].1.is_stale());
+        let _lock = RepositoryLock::exclusive(&repo)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn active_foreign_locks_are_respected() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let repo = open_test_repository(temp.path())?;
+        write_foreign_lock(&repo, true, now())?;
+
+        assert2::assert!(!list_locks(&repo)?[0].1.is_stale());
+        assert2::assert!(RepositoryLock::shared(&repo).is_err());
+        // the failed attempt doesn't leave a lock behind
+        assert2::assert!(repo.list_locks()? == vec!["foreign"]);
+
+        Ok(())
+    }
+}
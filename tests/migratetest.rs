@@ -0,0 +1,414 @@
+#[cfg(test)]
+mod migratetest {
+    use backrub::backendrepository::BackendRepository;
+    use backrub::check::{check_repository, DataCheck};
+    use backrub::common::KeySource;
+    use backrub::crypto::{Cipher, DataEncryptionKey, InputKey, KdfParams};
+    use backrub::errors::Result;
+    use backrub::repository::{
+        BackrubRepositoryMeta, BackupBlockId, Repository, RepositoryConfig, CURRENT_VERSION,
+        FEATURE_PACK_FILES, KNOWN_FEATURES,
+    };
+    use backrub::restore::{restore_backup, RestoreOptions};
+    use rmp_serde::{Deserializer, Serializer};
+    use serde::{Deserialize, Serialize};
+    use sha3::{Digest, Sha3_256};
+    use std::fs;
+    use std::path::Path;
+
+    /**
+     * The formats written by the first version of backrub. The current types
+     * gained fields since, so they can't be used to write the old format.
+     */
+    mod v1 {
+        use backrub::crypto::CryptoBlock;
+        use backrub::repository::BackupBlockId;
+        use serde::Serialize;
+
+        #[derive(Serialize)]
+        pub struct RepositoryMeta {
+            pub version: u32,
+            pub title: String,
+            pub salt: Vec<u8>,
+            pub iterations: u16,
+            pub id: String,
+        }
+
+        #[derive(Serialize)]
+        pub struct EncryptedDataEncryptionKey {
+            pub created_at: u64,
+            pub key_block: CryptoBlock,
+        }
+
+        #[derive(Serialize)]
+        pub struct KeyedCryptoBlock {
+            pub key_index: u64,
+            pub block: CryptoBlock,
+        }
+
+        #[derive(Serialize)]
+        pub struct BackupInstance {
+            pub name: String,
+            pub time: u64,
+            pub entry_list_id: BackupBlockId,
+        }
+
+        #[derive(Serialize)]
+        pub struct BackupObject {
+            pub blocks: Vec<BackupBlockId>,
+        }
+
+        #[derive(Serialize)]
+        pub struct BackupEntry {
+            pub name: String,
+            pub entry_type: EntryType,
+            pub meta: Meta,
+        }
+
+        #[derive(Serialize)]
+        pub enum EntryType {
+            File(FileEntryData),
+            Dir,
+        }
+
+        #[derive(Serialize)]
+        pub struct FileEntryData {
+            pub block_list_id: BackupBlockId,
+        }
+
+        #[derive(Serialize)]
+        pub enum Meta {
+            UnixMeta(UnixFsMeta),
+        }
+
+        #[derive(Serialize)]
+        pub enum UnixFsMeta {
+            File(UnixFileMetaData),
+            Dir(UnixCommonMeta),
+        }
+
+        #[derive(Serialize)]
+        pub struct UnixFileMetaData {
+            pub common: UnixCommonMeta,
+            pub size: i64,
+        }
+
+        #[derive(Serialize)]
+        pub struct UnixCommonMeta {
+            pub uid: u32,
+            pub gid: u32,
+            pub mode: u32,
+        }
+    }
+
+    fn serialize(value: &impl Serialize) -> Vec<u8> {
+        let mut buffer = vec![];
+        value.serialize(&mut Serializer::new(&mut buffer)).unwrap();
+        buffer
+    }
+
+    /**
+     * Store a block the way the first version did: encrypted, in its own
+     * file and named after the hash of the encrypted block
+     */
+    fn write_v1_block(path: &Path, key: &(u64, DataEncryptionKey), data: &[u8]) -> BackupBlockId {
+        let block = serialize(&v1::KeyedCryptoBlock {
+            key_index: key.0,
+            block: Cipher::new(&key.1).encrypt_block(data).unwrap(),
+        });
+        let id = BackupBlockId::from_bytes(&Sha3_256::digest(&block)).unwrap();
+        let name = id.to_str();
+        let dir = path.join("blocks").join(&name[..2]);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(&name[2..]), block).unwrap();
+        id
+    }
+
+    /**
+     * Write a repository of the first format version containing the instance
+     * "first" with the directory "data" and the file "data/file", which
+     * consists of the given chunks
+     */
+    fn write_v1_repo(path: &Path, password: &[u8], chunks: &[&[u8]]) {
+        let salt = vec![7; 16];
+        let iterations = 1;
+        fs::write(
+            path.join("backrub"),
+            serialize(&v1::RepositoryMeta {
+                version: 1,
+                title: String::from("backrub backup repository."),
+                salt: salt.clone(),
+                iterations,
+                id: String::from("00000000000000ff"),
+            }),
+        )
+        .unwrap();
+        for dir in &["blocks", "instances", "keys"] {
+            fs::create_dir_all(path.join(dir)).unwrap();
+        }
+
+        // the data encryption key is encrypted with the password itself
+        let master_key = KdfParams::legacy(&salt, iterations as u32)
+            .derive(&InputKey::from(password))
+            .unwrap();
+        let key = (
+            0x1234u64,
+            DataEncryptionKey {
+                created_at: 1_600_000_000,
+                value: vec![42; 32],
+            },
+        );
+        let key_block = Cipher::new(&DataEncryptionKey::from(&master_key))
+            .encrypt_block(&key.1.value)
+            .unwrap();
+        fs::write(
+            path.join("keys").join(format!("{:016x}.key", key.0)),
+            serialize(&v1::EncryptedDataEncryptionKey {
+                created_at: key.1.created_at,
+                key_block,
+            }),
+        )
+        .unwrap();
+
+        let blocks = chunks
+            .iter()
+            .map(|chunk| write_v1_block(path, &key, chunk))
+            .collect();
+        let object = write_v1_block(path, &key, &serialize(&v1::BackupObject { blocks }));
+        let uid = nix::unistd::getuid().as_raw();
+        let gid = nix::unistd::getgid().as_raw();
+        let entries = vec![
+            v1::BackupEntry {
+                name: String::from("data"),
+                entry_type: v1::EntryType::Dir,
+                meta: v1::Meta::UnixMeta(v1::UnixFsMeta::Dir(v1::UnixCommonMeta {
+                    uid,
+                    gid,
+                    mode: 0o40755,
+                })),
+            },
+            v1::BackupEntry {
+                name: String::from("data/file"),
+                entry_type: v1::EntryType::File(v1::FileEntryData {
+                    block_list_id: object,
+                }),
+                meta: v1::Meta::UnixMeta(v1::UnixFsMeta::File(v1::UnixFileMetaData {
+                    common: v1::UnixCommonMeta {
+                        uid,
+                        gid,
+                        mode: 0o100644,
+                    },
+                    size: chunks.iter().map(|chunk| chunk.len() as i64).sum(),
+                })),
+            },
+        ];
+        let entry_list_id = write_v1_block(path, &key, &serialize(&entries));
+        fs::write(
+            path.join("instances").join("first"),
+            serialize(&v1::BackupInstance {
+                name: String::from("first"),
+                time: 1_600_000_000,
+                entry_list_id,
+            }),
+        )
+        .unwrap();
+    }
+
+    fn change_meta(path: &Path, change: impl Fn(&mut BackrubRepositoryMeta)) {
+        let meta_path = path.join("backrub");
+        let mut meta: BackrubRepositoryMeta =
+            Deserialize::deserialize(&mut Deserializer::new(fs::File::open(&meta_path).unwrap()))
+                .unwrap();
+        change(&mut meta);
+        let mut buffer = vec![];
+        meta.serialize(&mut Serializer::new(&mut buffer)).unwrap();
+        fs::write(&meta_path, buffer).unwrap();
+    }
+
+    /**
+     * Create a repository containing a single block and return the block's ID
+     */
+    fn init_repo(path: &Path) -> Result<BackupBlockId> {
+        let mut repo = BackendRepository::new(path);
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let (block, _) = repo.add_block(b"some data")?;
+        Ok(block)
+    }
+
+    #[test]
+    fn repositories_of_the_first_version_are_migrated() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let chunks: &[&[u8]] = &[b"The first chunk, ", b"the second chunk"];
+        write_v1_repo(temp.path(), b"MyTestKey", chunks);
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(repo.meta()?.version == 1);
+        assert2::assert!(check_repository(&repo, &DataCheck::All)?.is_healthy());
+        assert2::assert!(repo.migrate()? == KNOWN_FEATURES);
+        assert2::assert!(fs::read_dir(temp.path().join("blocks"))
+            .unwrap()
+            .all(|dir| fs::read_dir(dir.unwrap().path()).unwrap().next().is_none()));
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(repo.meta()?.version == CURRENT_VERSION);
+        let report = check_repository(&repo, &DataCheck::All)?;
+        assert2::assert!(report.is_healthy());
+        assert2::assert!(report.checked_blocks == 4);
+
+        let restore_dir = assert_fs::TempDir::new().unwrap();
+        std::env::set_var("BACKRUB_KEY", "MyTestKey");
+        restore_backup(
+            temp.path().to_str().unwrap(),
+            &KeySource::default(),
+            restore_dir.path().to_str().unwrap(),
+            &None,
+            "first",
+            &RestoreOptions::default(),
+        )?;
+        assert2::assert!(
+            fs::read(restore_dir.path().join("data").join("file")).unwrap() == chunks.concat()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn older_repositories_are_read_only_until_migrated() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let block = init_repo(temp.path())?;
+        change_meta(temp.path(), |meta| {
+            meta.version = 1;
+            meta.features = vec![];
+        });
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(repo.read_block(&block).is_ok());
+        assert2::assert!(repo.add_block(b"other data").is_err());
+        assert2::assert!(repo.remove_block(&block).is_err());
+
+        let added = repo.migrate()?;
+        assert2::assert!(added == KNOWN_FEATURES);
+        assert2::assert!(repo.meta()?.version == CURRENT_VERSION);
+        assert2::assert!(repo.add_block(b"other data").is_ok());
+        assert2::assert!(repo.migrate()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn block_id_key_is_only_created_by_migration() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let block = init_repo(temp.path())?;
+        // repositories of the first format version have no block ID key
+        let key_path = temp.path().join("keys").join("block_id.mac");
+        fs::remove_file(&key_path).unwrap();
+        change_meta(temp.path(), |meta| {
+            meta.version = 1;
+            meta.features = vec![];
+        });
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(repo.has_block(&block)?);
+        assert2::assert!(repo.block_id(b"some data").is_err());
+        assert2::assert!(!key_path.exists());
+
+        repo.migrate()?;
+        assert2::assert!(key_path.exists());
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(repo.add_block(b"other data").is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn interrupted_migration_is_resumed() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        init_repo(temp.path())?;
+        // the first step was finished, before the migration was interrupted
+        change_meta(temp.path(), |meta| {
+            meta.version = 1;
+            meta.features.truncate(1);
+        });
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let added = repo.migrate()?;
+        assert2::assert!(added == &KNOWN_FEATURES[1..]);
+        assert2::assert!(repo.meta()?.version == CURRENT_VERSION);
+        assert2::assert!(repo.meta()?.features.len() == KNOWN_FEATURES.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn newer_repositories_are_not_written() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let block = init_repo(temp.path())?;
+        change_meta(temp.path(), |meta| meta.version = CURRENT_VERSION + 1);
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(repo.read_block(&block).is_ok());
+        assert2::assert!(repo.add_block(b"other data").is_err());
+        assert2::assert!(repo.migrate().is_err());
+
+        change_meta(temp.path(), |meta| {
+            meta.features.push(String::from("some-future-feature"))
+        });
+        assert2::assert!(repo.open(InputKey::from(b"MyTestKey" as &[u8])).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn loose_blocks_are_moved_into_packs() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let block = init_repo(temp.path())?;
+        // recreate the layout of older versions storing every block in its own file
+        let raw_block = {
+            let mut repo = BackendRepository::new(temp.path());
+            repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+            repo.read_block(&block)?
+        };
+        let name = block.to_str();
+        fs::create_dir_all(temp.path().join("blocks").join(&name[..2])).unwrap();
+        fs::write(
+            temp.path().join("blocks").join(&name[..2]).join(&name[2..]),
+            &raw_block,
+        )
+        .unwrap();
+        fs::remove_dir_all(temp.path().join("packs")).unwrap();
+        fs::remove_dir_all(temp.path().join("index")).unwrap();
+        change_meta(temp.path(), |meta| {
+            meta.version = 2;
+            meta.features.retain(|f| f != FEATURE_PACK_FILES);
+        });
+
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(repo.read_block(&block)? == raw_block);
+        assert2::assert!(repo.migrate()? == vec![FEATURE_PACK_FILES]);
+
+        assert2::assert!(!temp
+            .path()
+            .join("blocks")
+            .join(&name[..2])
+            .join(&name[2..])
+            .exists());
+        let mut repo = BackendRepository::new(temp.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(repo.read_block(&block)? == raw_block);
+        assert2::assert!(repo.list_blocks()?.len() == 1);
+
+        Ok(())
+    }
+}
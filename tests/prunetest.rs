@@ -0,0 +1,107 @@
+#[cfg(test)]
+mod prunetest {
+    use backrub::backendrepository::BackendRepository;
+    use backrub::backup::{BackupEntry, BackupInstance, EntryList, EntryType, FileEntryData};
+    use backrub::backupobject::BackupObject;
+    use backrub::crypto::{decode_keyed_block, InputKey};
+    use backrub::errors::Result;
+    use backrub::os::unix::{get_meta_data, XattrFilter};
+    use backrub::prune::prune_repository;
+    use backrub::repository::{BackupBlockId, Repository, RepositoryConfig};
+    use rmp_serde::Serializer;
+    use serde::Serialize;
+    use std::io::Cursor;
+
+    fn open_test_repository(path: &std::path::Path) -> Result<BackendRepository> {
+        let mut repo = BackendRepository::new(path);
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        Ok(repo)
+    }
+
+    fn store_instance(
+        repo: &BackendRepository,
+        name: &str,
+        blocks: Vec<BackupBlockId>,
+        meta_source: &std::path::Path,
+    ) -> Result<()> {
+        let mut object_buffer = vec![];
+        BackupObject {
+            blocks,
+            sizes: vec![],
+        }
+        .serialize(&mut Serializer::new(&mut object_buffer))
+        .unwrap();
+        let (object_id, _) = repo.add_block(&object_buffer)?;
+        let entries = EntryList::from(vec![BackupEntry {
+            name: String::from("file"),
+            entry_type: EntryType::File(FileEntryData {
+                block_list_id: object_id,
+            }),
+            meta: get_meta_data(meta_source, &XattrFilter::default())?,
+        }]);
+        let (entry_list_id, _) = repo.store_entry_list(&entries)?;
+        repo.finish_backup(
+            BackupInstance {
+                name: String::from(name),
+                time: 0,
+                entry_list_id,
+                tags: vec![],
+            },
+            false,
+        )
+    }
+
+    #[test]
+    fn prune_removes_only_unreferenced_blocks() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let meta_source = assert_fs::NamedTempFile::new("meta").unwrap();
+        std::fs::write(meta_source.path(), b"").unwrap();
+        let repo = open_test_repository(temp.path())?;
+        let (used, _) = repo.add_block(b"referenced data")?;
+        let (unused, unused_size) = repo.add_block(b"garbage")?;
+        store_instance(&repo, "instance", vec![used.clone()], meta_source.path())?;
+        let blocks_before = repo.list_blocks()?.len();
+
+        let dry_run = prune_repository(&repo, true)?;
+        assert2::assert!(dry_run.unreferenced_blocks == 1);
+        assert2::assert!(dry_run.unreferenced_size == unused_size);
+        assert2::assert!(repo.list_blocks()?.len() == blocks_before);
+
+        let result = prune_repository(&repo, false)?;
+        assert2::assert!(result.unreferenced_blocks == 1);
+        assert2::assert!(repo.has_block(&used)?);
+        assert2::assert!(!repo.has_block(&unused)?);
+
+        // the pack containing both blocks was rewritten without the unused one
+        let mut reopened = BackendRepository::new(temp.path());
+        reopened.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let data = decode_keyed_block(Cursor::new(reopened.read_block(&used)?), reopened.keys()?)?;
+        assert2::assert!(data == b"referenced data");
+        assert2::assert!(!reopened.has_block(&unused)?);
+        assert2::assert!(reopened.list_blocks()?.len() == blocks_before - 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn forgotten_instances_are_pruned() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let meta_source = assert_fs::NamedTempFile::new("meta").unwrap();
+        std::fs::write(meta_source.path(), b"").unwrap();
+        let repo = open_test_repository(temp.path())?;
+        let (data, _) = repo.add_block(b"some data")?;
+        store_instance(&repo, "instance", vec![data.clone()], meta_source.path())?;
+
+        repo.remove_instance("instance")?;
+        assert2::assert!(repo.list_instance_names()?.is_empty());
+        prune_repository(&repo, false)?;
+
+        assert2::assert!(repo.list_blocks()?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,583 @@
+#[cfg(test)]
+mod restoretest {
+    use assert_fs::prelude::*;
+    use backrub::backendrepository::BackendRepository;
+    use backrub::backup::EntryType;
+    use backrub::common::KeySource;
+    use backrub::create::{make_backup, BackupOptions};
+    use backrub::crypto::InputKey;
+    use backrub::errors::Result;
+    use backrub::os::unix::XattrFilter;
+    use backrub::repository::{Repository, RepositoryConfig};
+    use backrub::restore::{restore_backup, RestoreOptions};
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    const CONTENT: &[u8] = b"some file content, that is going to be damaged";
+
+    /// back up a single file and remove its data block from the repository
+    fn damaged_backup(repo_path: &std::path::Path) -> Result<()> {
+        let source_dir = assert_fs::TempDir::new().unwrap();
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        source_dir.child("file").write_binary(CONTENT).unwrap();
+        let mut repo = BackendRepository::new(repo_path);
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        std::env::set_var("BACKRUB_KEY", "MyTestKey");
+        make_backup(
+            repo_path.to_str().unwrap(),
+            &KeySource::default(),
+            &vec![String::from(source_dir.path().to_str().unwrap())],
+            cache_dir.path(),
+            "Damaged",
+            &None,
+            &BackupOptions::default(),
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let instance = repo.open_instance("Damaged")?;
+        for entry in repo.load_entry_list(&instance.entry_list_id)?.0 {
+            if let EntryType::File(file_data) = entry.entry_type {
+                for block in repo.open_object(&file_data.block_list_id)?.blocks {
+                    repo.remove_block(&block)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn restored_files(path: &std::path::Path) -> Vec<std::path::PathBuf> {
+        walkdir::WalkDir::new(path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file())
+            .map(|e| e.into_path())
+            .collect()
+    }
+
+    #[test]
+    fn restore_fails_on_missing_blocks() -> Result<()> {
+        let repo_dir = assert_fs::TempDir::new().unwrap();
+        let restore_dir = assert_fs::TempDir::new().unwrap();
+        damaged_backup(repo_dir.path())?;
+
+        let result = restore_backup(
+            repo_dir.path().to_str().unwrap(),
+            &KeySource::default(),
+            restore_dir.path().to_str().unwrap(),
+            &None,
+            "Damaged",
+            &RestoreOptions::default(),
+        );
+
+        assert2::assert!(result.is_err());
+        assert2::assert!(restored_files(restore_dir.path()).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn best_effort_restore_fills_damaged_regions_with_zeros() -> Result<()> {
+        let repo_dir = assert_fs::TempDir::new().unwrap();
+        let restore_dir = assert_fs::TempDir::new().unwrap();
+        let report = assert_fs::NamedTempFile::new("report").unwrap();
+        damaged_backup(repo_dir.path())?;
+
+        let result = restore_backup(
+            repo_dir.path().to_str().unwrap(),
+            &KeySource::default(),
+            restore_dir.path().to_str().unwrap(),
+            &None,
+            "Damaged",
+            &RestoreOptions {
+                best_effort: true,
+                damage_report: Some(report.path().to_path_buf()),
+            },
+        );
+
+        assert2::assert!(result.is_err());
+        let files = restored_files(restore_dir.path());
+        assert2::assert!(files.len() == 1);
+        assert2::assert!(std::fs::read(&files[0]).unwrap() == vec![0; CONTENT.len()]);
+        let report = std::fs::read_to_string(report.path()).unwrap();
+        assert2::assert!(report.contains(&format!(
+            "damaged at offset 0, {} bytes filled with zeros",
+            CONTENT.len()
+        )));
+
+        Ok(())
+    }
+
+    /// back up a directory with a file linked from two other places
+    fn hard_linked_backup(repo_path: &std::path::Path) -> Result<()> {
+        let source_dir = assert_fs::TempDir::new().unwrap();
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        source_dir.child("a/file").write_binary(CONTENT).unwrap();
+        source_dir.child("b").create_dir_all().unwrap();
+        source_dir.child("single").write_binary(CONTENT).unwrap();
+        std::fs::hard_link(
+            source_dir.child("a/file").path(),
+            source_dir.child("b/link1").path(),
+        )
+        .unwrap();
+        std::fs::hard_link(
+            source_dir.child("a/file").path(),
+            source_dir.child("b/link2").path(),
+        )
+        .unwrap();
+        let repo = BackendRepository::new(repo_path);
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        std::env::set_var("BACKRUB_KEY", "MyTestKey");
+        make_backup(
+            repo_path.to_str().unwrap(),
+            &KeySource::default(),
+            &vec![String::from(source_dir.path().to_str().unwrap())],
+            cache_dir.path(),
+            "Linked",
+            &None,
+            &BackupOptions::default(),
+        )
+    }
+
+    fn find_restored(files: &[std::path::PathBuf], name: &str) -> std::fs::Metadata {
+        let path = files.iter().find(|f| f.ends_with(name)).unwrap();
+        assert2::assert!(std::fs::read(path).unwrap() == CONTENT);
+        std::fs::metadata(path).unwrap()
+    }
+
+    #[test]
+    fn hard_links_are_restored_as_links() -> Result<()> {
+        let repo_dir = assert_fs::TempDir::new().unwrap();
+        let restore_dir = assert_fs::TempDir::new().unwrap();
+        hard_linked_backup(repo_dir.path())?;
+        let mut repo = BackendRepository::new(repo_dir.path());
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let instance = repo.open_instance("Linked")?;
+        let links = repo
+            .load_entry_list(&instance.entry_list_id)?
+            .0
+            .iter()
+            .filter(|e| matches!(e.entry_type, EntryType::HardLink(_)))
+            .count();
+        assert2::assert!(links == 2);
+
+        restore_backup(
+            repo_dir.path().to_str().unwrap(),
+            &KeySource::default(),
+            restore_dir.path().to_str().unwrap(),
+            &None,
+            "Linked",
+            &RestoreOptions::default(),
+        )?;
+
+        let files = restored_files(restore_dir.path());
+        assert2::assert!(files.len() == 4);
+        let file = find_restored(&files, "a/file");
+        assert2::assert!(file.nlink() == 3);
+        assert2::assert!(find_restored(&files, "b/link1").ino() == file.ino());
+        assert2::assert!(find_restored(&files, "b/link2").ino() == file.ino());
+        assert2::assert!(find_restored(&files, "single").nlink() == 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn hard_links_are_restored_without_their_target() -> Result<()> {
+        let repo_dir = assert_fs::TempDir::new().unwrap();
+        let restore_dir = assert_fs::TempDir::new().unwrap();
+        hard_linked_backup(repo_dir.path())?;
+
+        restore_backup(
+            repo_dir.path().to_str().unwrap(),
+            &KeySource::default(),
+            restore_dir.path().to_str().unwrap(),
+            &Some(vec![String::from("/b/")]),
+            "Linked",
+            &RestoreOptions::default(),
+        )?;
+
+        let files = restored_files(restore_dir.path());
+        assert2::assert!(files.len() == 2);
+        let link = find_restored(&files, "b/link1");
+        assert2::assert!(link.nlink() == 2);
+        assert2::assert!(find_restored(&files, "b/link2").ino() == link.ino());
+
+        Ok(())
+    }
+
+    #[test]
+    fn special_files_are_restored() -> Result<()> {
+        let repo_dir = assert_fs::TempDir::new().unwrap();
+        let source_dir = assert_fs::TempDir::new().unwrap();
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        let restore_dir = assert_fs::TempDir::new().unwrap();
+        nix::unistd::mkfifo(
+            source_dir.child("fifo").path(),
+            nix::sys::stat::Mode::from_bits_truncate(0o640),
+        )
+        .unwrap();
+        let _socket =
+            std::os::unix::net::UnixListener::bind(source_dir.child("socket").path()).unwrap();
+        // creating devices needs root and may be forbidden in containers
+        let null = std::fs::metadata("/dev/null").unwrap().rdev();
+        let has_device = nix::sys::stat::mknod(
+            source_dir.child("null").path(),
+            nix::sys::stat::SFlag::S_IFCHR,
+            nix::sys::stat::Mode::from_bits_truncate(0o666),
+            null,
+        )
+        .is_ok();
+        let mut repo = BackendRepository::new(repo_dir.path());
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        std::env::set_var("BACKRUB_KEY", "MyTestKey");
+        make_backup(
+            repo_dir.path().to_str().unwrap(),
+            &KeySource::default(),
+            &vec![String::from(source_dir.path().to_str().unwrap())],
+            cache_dir.path(),
+            "Special",
+            &None,
+            &BackupOptions::default(),
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let instance = repo.open_instance("Special")?;
+        let entries = repo.load_entry_list(&instance.entry_list_id)?;
+        assert2::assert!(entries
+            .0
+            .iter()
+            .any(|e| matches!(e.entry_type, EntryType::Socket)));
+
+        restore_backup(
+            repo_dir.path().to_str().unwrap(),
+            &KeySource::default(),
+            restore_dir.path().to_str().unwrap(),
+            &None,
+            "Special",
+            &RestoreOptions::default(),
+        )?;
+
+        let restored: std::path::PathBuf = [
+            restore_dir.path(),
+            source_dir.path().strip_prefix("/").unwrap(),
+        ]
+        .iter()
+        .collect();
+        let fifo = std::fs::symlink_metadata(restored.join("fifo")).unwrap();
+        assert2::assert!(fifo.file_type().is_fifo());
+        assert2::assert!(fifo.mode() & 0o777 == 0o640);
+        assert2::assert!(!restored.join("socket").exists());
+        if has_device {
+            let device = std::fs::symlink_metadata(restored.join("null")).unwrap();
+            assert2::assert!(device.file_type().is_char_device());
+            assert2::assert!(device.rdev() == null);
+        }
+
+        Ok(())
+    }
+
+    fn set_times(path: &std::path::Path, seconds: i64, nanoseconds: i64) {
+        use nix::sys::time::{TimeSpec, TimeValLike};
+        let time = TimeSpec::seconds(seconds) + TimeSpec::nanoseconds(nanoseconds);
+        nix::sys::stat::utimensat(
+            None,
+            path,
+            &time,
+            &time,
+            nix::sys::stat::UtimensatFlags::NoFollowSymlink,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn times_are_restored() -> Result<()> {
+        let repo_dir = assert_fs::TempDir::new().unwrap();
+        let source_dir = assert_fs::TempDir::new().unwrap();
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        let restore_dir = assert_fs::TempDir::new().unwrap();
+        source_dir.child("dir/file").write_binary(CONTENT).unwrap();
+        std::os::unix::fs::symlink("file", source_dir.child("dir/link").path()).unwrap();
+        set_times(
+            source_dir.child("dir/file").path(),
+            1_000_000_000,
+            123_456_789,
+        );
+        set_times(source_dir.child("dir/link").path(), 1_100_000_000, 5);
+        set_times(source_dir.child("dir").path(), 1_200_000_000, 999_999_999);
+        let repo = BackendRepository::new(repo_dir.path());
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        std::env::set_var("BACKRUB_KEY", "MyTestKey");
+        make_backup(
+            repo_dir.path().to_str().unwrap(),
+            &KeySource::default(),
+            &vec![String::from(source_dir.path().to_str().unwrap())],
+            cache_dir.path(),
+            "Times",
+            &None,
+            &BackupOptions::default(),
+        )?;
+
+        restore_backup(
+            repo_dir.path().to_str().unwrap(),
+            &KeySource::default(),
+            restore_dir.path().to_str().unwrap(),
+            &None,
+            "Times",
+            &RestoreOptions::default(),
+        )?;
+
+        let restored: std::path::PathBuf = [
+            restore_dir.path(),
+            source_dir.path().strip_prefix("/").unwrap(),
+        ]
+        .iter()
+        .collect();
+        let file = std::fs::symlink_metadata(restored.join("dir/file")).unwrap();
+        assert2::assert!((file.mtime(), file.mtime_nsec()) == (1_000_000_000, 123_456_789));
+        assert2::assert!((file.atime(), file.atime_nsec()) == (1_000_000_000, 123_456_789));
+        let link = std::fs::symlink_metadata(restored.join("dir/link")).unwrap();
+        assert2::assert!(link.file_type().is_symlink());
+        assert2::assert!((link.mtime(), link.mtime_nsec()) == (1_100_000_000, 5));
+        let dir = std::fs::symlink_metadata(restored.join("dir")).unwrap();
+        assert2::assert!((dir.mtime(), dir.mtime_nsec()) == (1_200_000_000, 999_999_999));
+
+        Ok(())
+    }
+
+    fn c_string(value: &[u8]) -> std::ffi::CString {
+        std::ffi::CString::new(value).unwrap()
+    }
+
+    fn path_bytes(path: &std::path::Path) -> &[u8] {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes()
+    }
+
+    fn set_xattr(path: &std::path::Path, name: &str, value: &[u8]) -> bool {
+        let path = c_string(path_bytes(path));
+        let name = c_string(name.as_bytes());
+        unsafe {
+            libc::lsetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+            ) == 0
+        }
+    }
+
+    fn get_xattr(path: &std::path::Path, name: &str) -> Option<Vec<u8>> {
+        let path = c_string(path_bytes(path));
+        let name = c_string(name.as_bytes());
+        let mut buffer = vec![0u8; 256];
+        let length = unsafe {
+            libc::lgetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+        if length < 0 {
+            return None;
+        }
+        buffer.truncate(length as usize);
+        Some(buffer)
+    }
+
+    #[test]
+    fn extended_attributes_are_restored() -> Result<()> {
+        let repo_dir = assert_fs::TempDir::new().unwrap();
+        let source_dir = assert_fs::TempDir::new().unwrap();
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        let restore_dir = assert_fs::TempDir::new().unwrap();
+        source_dir.child("dir/file").write_binary(CONTENT).unwrap();
+        std::os::unix::fs::symlink("file", source_dir.child("dir/link").path()).unwrap();
+        if !set_xattr(source_dir.child("dir/file").path(), "user.kept", b"file") {
+            // the file system doesn't support extended attributes
+            return Ok(());
+        }
+        set_xattr(source_dir.child("dir/file").path(), "user.skipped", b"x");
+        set_xattr(source_dir.child("dir").path(), "user.kept", b"dir");
+        // without write permission, only root could set the attributes after the mode
+        source_dir
+            .child("dir/readonly")
+            .write_binary(CONTENT)
+            .unwrap();
+        set_xattr(source_dir.child("dir/readonly").path(), "user.kept", b"ro");
+        std::fs::set_permissions(
+            source_dir.child("dir/readonly").path(),
+            std::os::unix::fs::PermissionsExt::from_mode(0o444),
+        )
+        .unwrap();
+        // only root may set trusted attributes, which are the ones allowed on symlinks
+        let link_xattr = set_xattr(source_dir.child("dir/link").path(), "trusted.kept", b"link");
+        let repo = BackendRepository::new(repo_dir.path());
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        std::env::set_var("BACKRUB_KEY", "MyTestKey");
+        make_backup(
+            repo_dir.path().to_str().unwrap(),
+            &KeySource::default(),
+            &vec![String::from(source_dir.path().to_str().unwrap())],
+            cache_dir.path(),
+            "Xattrs",
+            &None,
+            &BackupOptions {
+                xattr_filter: XattrFilter {
+                    include: vec![],
+                    exclude: vec![String::from("user.skipped")],
+                },
+                ..BackupOptions::default()
+            },
+        )?;
+
+        restore_backup(
+            repo_dir.path().to_str().unwrap(),
+            &KeySource::default(),
+            restore_dir.path().to_str().unwrap(),
+            &None,
+            "Xattrs",
+            &RestoreOptions::default(),
+        )?;
+
+        let restored: std::path::PathBuf = [
+            restore_dir.path(),
+            source_dir.path().strip_prefix("/").unwrap(),
+        ]
+        .iter()
+        .collect();
+        assert2::assert!(
+            get_xattr(&restored.join("dir/file"), "user.kept") == Some(b"file".to_vec())
+        );
+        assert2::assert!(get_xattr(&restored.join("dir/file"), "user.skipped") == None);
+        assert2::assert!(get_xattr(&restored.join("dir"), "user.kept") == Some(b"dir".to_vec()));
+        assert2::assert!(
+            get_xattr(&restored.join("dir/readonly"), "user.kept") == Some(b"ro".to_vec())
+        );
+        if link_xattr {
+            assert2::assert!(
+                get_xattr(&restored.join("dir/link"), "trusted.kept") == Some(b"link".to_vec())
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn xattr_filter_matches_namespaces_and_names() {
+        let filter = XattrFilter {
+            include: vec![String::from("security"), String::from("user.kept")],
+            exclude: vec![String::from("security.selinux")],
+        };
+        assert2::assert!(filter.matches(b"security.capability"));
+        assert2::assert!(filter.matches(b"user.kept"));
+        assert2::assert!(!filter.matches(b"user.kept2"));
+        assert2::assert!(!filter.matches(b"securityx.capability"));
+        assert2::assert!(!filter.matches(b"security.selinux"));
+        assert2::assert!(!filter.matches(b"trusted.x"));
+        assert2::assert!(XattrFilter::default().matches(b"trusted.x"));
+    }
+
+    #[test]
+    fn owners_and_read_only_directories_are_restored() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let repo_dir = assert_fs::TempDir::new().unwrap();
+        let source_dir = assert_fs::TempDir::new().unwrap();
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        let restore_dir = assert_fs::TempDir::new().unwrap();
+        let set_mode = |path: &std::path::Path, mode: u32| {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap()
+        };
+        source_dir
+            .child("ro/sub/file")
+            .write_binary(CONTENT)
+            .unwrap();
+        source_dir.child("program").write_binary(CONTENT).unwrap();
+        std::os::unix::fs::symlink("program", source_dir.child("link").path()).unwrap();
+        // only root can give files away
+        let is_root = nix::unistd::geteuid().is_root();
+        if is_root {
+            let owner = Some(nix::unistd::Uid::from_raw(1234));
+            let group = Some(nix::unistd::Gid::from_raw(1234));
+            nix::unistd::chown(source_dir.child("program").path(), owner, group).unwrap();
+            nix::unistd::fchownat(
+                None,
+                source_dir.child("link").path(),
+                owner,
+                group,
+                nix::unistd::FchownatFlags::NoFollowSymlink,
+            )
+            .unwrap();
+        }
+        set_mode(source_dir.child("program").path(), 0o4755);
+        set_mode(source_dir.child("ro/sub").path(), 0o555);
+        set_mode(source_dir.child("ro").path(), 0o555);
+        let repo = BackendRepository::new(repo_dir.path());
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        std::env::set_var("BACKRUB_KEY", "MyTestKey");
+        make_backup(
+            repo_dir.path().to_str().unwrap(),
+            &KeySource::default(),
+            &vec![String::from(source_dir.path().to_str().unwrap())],
+            cache_dir.path(),
+            "Owners",
+            &None,
+            &BackupOptions::default(),
+        )?;
+
+        let result = restore_backup(
+            repo_dir.path().to_str().unwrap(),
+            &KeySource::default(),
+            restore_dir.path().to_str().unwrap(),
+            &None,
+            "Owners",
+            &RestoreOptions::default(),
+        );
+
+        let restored: std::path::PathBuf = [
+            restore_dir.path(),
+            source_dir.path().strip_prefix("/").unwrap(),
+        ]
+        .iter()
+        .collect();
+        let mode = |name: &str| {
+            std::fs::symlink_metadata(restored.join(name))
+                .unwrap()
+                .mode()
+        };
+        let restored_file = std::fs::read(restored.join("ro/sub/file"));
+        let (ro_mode, sub_mode, program_mode) = (mode("ro"), mode("ro/sub"), mode("program"));
+        let link = std::fs::symlink_metadata(restored.join("link")).unwrap();
+        // allow the temporary directories to be removed
+        for dir in [&source_dir.path().join("ro"), &restored.join("ro")].iter() {
+            set_mode(dir, 0o755);
+            set_mode(&dir.join("sub"), 0o755);
+        }
+        result?;
+        assert2::assert!(restored_file.unwrap() == CONTENT);
+        assert2::assert!(ro_mode & 0o7777 == 0o555);
+        assert2::assert!(sub_mode & 0o7777 == 0o555);
+        assert2::assert!(program_mode & 0o7777 == 0o4755);
+        if is_root {
+            assert2::assert!(link.file_type().is_symlink());
+            assert2::assert!((link.uid(), link.gid()) == (1234, 1234));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+#[cfg(test)]
+mod retentiontest {
+    use backrub::backup::BackupInstance;
+    use backrub::repository::BackupBlockId;
+    use backrub::retention::parse_duration;
+    use backrub::retention::RetentionPolicy;
+
+    // 2020-06-15 12:00 UTC, i.e. around mid-day in most time zones
+    const BASE_TIME: u64 = 1_592_222_400;
+    const HOUR: u64 = 3600;
+    const DAY: u64 = 24 * HOUR;
+
+    fn instance(name: &str, time: u64, tags: &[&str]) -> BackupInstance {
+        BackupInstance {
+            name: String::from(name),
+            time,
+            entry_list_id: BackupBlockId::from_bytes(&[0; 32]).unwrap(),
+            tags: tags.iter().map(|t| String::from(*t)).collect(),
+        }
+    }
+
+    /// three instances per day for the given number of days, oldest first
+    fn daily_instances(days: u64) -> Vec<BackupInstance> {
+        (0..days)
+            .flat_map(|day| {
+                (0..3).map(move |run| {
+                    instance(
+                        &format!("day{}-run{}", day, run),
+                        BASE_TIME + day * DAY + run * HOUR,
+                        &[],
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn kept_names(policy: &RetentionPolicy, instances: &[BackupInstance]) -> Vec<String> {
+        policy
+            .apply(instances)
+            .iter()
+            .filter(|d| d.keep())
+            .map(|d| d.instance.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn keep_last_keeps_the_most_recent_instances() {
+        let instances = daily_instances(4);
+        let policy = RetentionPolicy {
+            keep_last: 2,
+            ..Default::default()
+        };
+
+        assert2::assert!(kept_names(&policy, &instances) == vec!["day3-run2", "day3-run1"]);
+    }
+
+    #[test]
+    fn keep_daily_keeps_the_newest_instance_per_day() {
+        let instances = daily_instances(10);
+        let policy = RetentionPolicy {
+            keep_daily: 3,
+            ..Default::default()
+        };
+
+        assert2::assert!(
+            kept_names(&policy, &instances) == vec!["day9-run2", "day8-run2", "day7-run2"]
+        );
+    }
+
+    #[test]
+    fn reasons_of_all_matching_rules_are_reported() {
+        let instances = daily_instances(10);
+        let policy = RetentionPolicy {
+            keep_last: 1,
+            keep_daily: 2,
+            keep_monthly: 1,
+            ..Default::default()
+        };
+        let decisions = policy.apply(&instances);
+
+        assert2::assert!(decisions.len() == instances.len());
+        assert2::assert!(decisions[0].reasons == vec!["last", "daily", "monthly"]);
+        assert2::assert!(decisions.iter().filter(|d| d.keep()).count() == 2);
+    }
+
+    #[test]
+    fn keep_within_is_relative_to_the_newest_instance() {
+        let instances = daily_instances(10);
+        let policy = RetentionPolicy {
+            keep_within: Some(parse_duration("2d").unwrap()),
+            ..Default::default()
+        };
+
+        // the newest instance is day 9 at 14:00, so everything from day 7 at 14:00 is kept
+        assert2::assert!(
+            kept_names(&policy, &instances)
+                == vec![
+                    "day9-run2",
+                    "day9-run1",
+                    "day9-run0",
+                    "day8-run2",
+                    "day8-run1",
+                    "day8-run0",
+                    "day7-run2"
+                ]
+        );
+    }
+
+    #[test]
+    fn instances_outside_the_scope_are_not_considered() {
+        let instances = vec![
+            instance("home-1", BASE_TIME, &["home"]),
+            instance("home-2", BASE_TIME + DAY, &["home"]),
+            instance("db-1", BASE_TIME + 2 * DAY, &["db"]),
+            instance("home-3", BASE_TIME + 3 * DAY, &[]),
+        ];
+        let by_tag = RetentionPolicy {
+            keep_last: 1,
+            tag: Some(String::from("home")),
+            ..Default::default()
+        };
+        let by_prefix = RetentionPolicy {
+            keep_last: 1,
+            name_prefix: Some(String::from("db")),
+            ..Default::default()
+        };
+
+        let tagged: Vec<String> = by_tag
+            .apply(&instances)
+            .iter()
+            .map(|d| d.instance.name.clone())
+            .collect();
+        assert2::assert!(tagged == vec!["home-2", "home-1"]);
+        assert2::assert!(kept_names(&by_tag, &instances) == vec!["home-2"]);
+        assert2::assert!(by_prefix.apply(&instances).len() == 1);
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert2::assert!(parse_duration("36h").unwrap().as_secs() == 36 * HOUR);
+        assert2::assert!(parse_duration("1w2d").unwrap().as_secs() == 9 * DAY);
+        assert2::assert!(parse_duration("1y").unwrap().as_secs() == 365 * DAY);
+        assert2::assert!(parse_duration("12").is_err());
+        assert2::assert!(parse_duration("3x").is_err());
+        assert2::assert!(parse_duration("9999999999999999y").is_err());
+        assert2::assert!(parse_duration("400000000000y400000000000y").is_err());
+    }
+}
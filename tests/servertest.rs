@@ -0,0 +1,215 @@
+#[cfg(test)]
+mod servertest {
+    use backrub::backend::{Backend, LocalBackend};
+    use backrub::backendrepository::BackendRepository;
+    use backrub::backup::{BackupInstance, EntryList};
+    use backrub::crypto::decode_keyed_block;
+    use backrub::crypto::InputKey;
+    use backrub::errors::Result;
+    use backrub::http::HttpBackend;
+    use backrub::repository::{Repository, RepositoryConfig};
+    use backrub::server::{percent_decode, RepositoryServer, ServerConfig};
+    use std::io::Cursor;
+    use std::path::{Path, PathBuf};
+
+    /**
+     * Serve the directory from a background thread and return its location
+     */
+    fn start_server(path: &Path, append_only: bool) -> String {
+        start_server_with(
+            path,
+            ServerConfig {
+                append_only,
+                ..ServerConfig::default()
+            },
+        )
+    }
+
+    fn start_server_with(path: &Path, config: ServerConfig) -> String {
+        let server = RepositoryServer::bind("127.0.0.1:0", config).unwrap();
+        let location = format!("http://{}/", server.address().unwrap());
+        let path = PathBuf::from(path);
+        std::thread::spawn(move || {
+            server.run(&LocalBackend::new(&path)).ok();
+        });
+        location
+    }
+
+    fn open_test_repository(location: &str) -> Result<BackendRepository> {
+        let mut repo = BackendRepository::for_location(Path::new(location))?;
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        Ok(repo)
+    }
+
+    fn store_instance(
+        repo: &BackendRepository,
+        name: &str,
+        time: u64,
+        overwrite: bool,
+    ) -> Result<()> {
+        let (entry_list_id, _) = repo.store_entry_list(&EntryList::from(vec![]))?;
+        repo.finish_backup(
+            BackupInstance {
+                name: String::from(name),
+                time,
+                entry_list_id,
+                tags: vec![],
+            },
+            overwrite,
+        )
+    }
+
+    #[test]
+    fn repository_roundtrip_over_http() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let location = start_server(temp.path(), false);
+        let repo = open_test_repository(&location)?;
+        let (id, _) = repo.add_block(b"some data")?;
+        repo.flush()?;
+        store_instance(&repo, "my backup", 1, false)?;
+        assert2::assert!(store_instance(&repo, "my backup", 2, false).is_err());
+        store_instance(&repo, "my backup", 3, true)?;
+
+        // the files end up in a normal repository on the server
+        let mut local = BackendRepository::new(temp.path());
+        local.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(
+            decode_keyed_block(Cursor::new(local.read_block(&id)?), local.keys()?)? == b"some data"
+        );
+        assert2::assert!(local.list_instance_names()? == vec!["my backup"]);
+
+        repo.remove_instance("my backup")?;
+        repo.remove_blocks(std::slice::from_ref(&id))?;
+        assert2::assert!(!repo.has_block(&id)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn append_only_server_refuses_to_destroy_data() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let location = start_server(temp.path(), true);
+        let repo = open_test_repository(&location)?;
+        let (id, _) = repo.add_block(b"some data")?;
+        repo.flush()?;
+        store_instance(&repo, "instance", 1, false)?;
+
+        assert2::assert!(store_instance(&repo, "instance", 2, true).is_err());
+        assert2::assert!(repo.remove_instance("instance").is_err());
+        assert2::assert!(repo.remove_blocks(std::slice::from_ref(&id)).is_err());
+        assert2::assert!(repo.has_block(&id)?);
+        assert2::assert!(repo.open_instance("instance")?.time == 1);
+        // locks come and go, but only their owner may change them
+        repo.write_lock("test", b"lock", false)?;
+        let mut other = BackendRepository::for_location(Path::new(&location))?;
+        other.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(other.write_lock("test", b"stolen", true).is_err());
+        assert2::assert!(other.remove_lock("test").is_err());
+        repo.write_lock("test", b"refreshed", true)?;
+        assert2::assert!(other.read_lock("test")? == b"refreshed");
+        repo.remove_lock("test")?;
+        assert2::assert!(repo.list_locks()?.is_empty());
+
+        // writing the same content again is harmless
+        let backend = HttpBackend::new(&location, None)?;
+        backend.write("packs/00/file", b"data", false)?;
+        backend.write("packs/00/file", b"data", true)?;
+        assert2::assert!(backend.write("packs/00/file", b"other", true).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_repository_files_are_served() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        std::fs::write(temp.path().join("secret"), b"secret").unwrap();
+        let location = start_server(&temp.path().join("repo"), false);
+        let backend = HttpBackend::new(&location, None)?;
+        assert2::assert!(backend.read("../secret").is_err());
+        assert2::assert!(backend.write("other/file", b"data", false).is_err());
+        backend.write("instances/a b%c", b"data", false)?;
+        assert2::assert!(backend.list("instances")? == vec![(String::from("a b%c"), 4)]);
+        assert2::assert!(backend.read_range("instances/a b%c", 1, 2)? == b"at");
+        assert2::assert!(backend.size("instances/a b%c")? == 4);
+        Ok(())
+    }
+
+    #[test]
+    fn ranges_are_limited_to_the_file() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let location = start_server(temp.path(), false);
+        let backend = HttpBackend::new(&location, None)?;
+        backend.write("instances/file", b"data", false)?;
+        assert2::assert!(backend.read_range("instances/file", 2, 2)? == b"ta");
+        assert2::assert!(backend.read_range("instances/file", 2, 1 << 40).is_err());
+        assert2::assert!(backend.read_range("instances/file", 4, 1).is_err());
+        assert2::assert!(backend.read_range("instances/file", u64::MAX, 2).is_err());
+
+        let url = format!("{}instances/file", location);
+        let response = ureq::get(&url)
+            .set("Range", "bytes=1-18446744073709551615")
+            .call()
+            .unwrap();
+        assert2::assert!(response.status() == 206);
+        assert2::assert!(response.header("Content-Range") == Some("bytes 1-3/4"));
+        assert2::assert!(response.into_string().unwrap() == "ata");
+        assert2::assert!(matches!(
+            ureq::get(&url).set("Range", "bytes=4-5").call(),
+            Err(ureq::Error::Status(416, _))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn clients_need_the_token_of_the_server() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let location = start_server_with(
+            temp.path(),
+            ServerConfig {
+                token: Some(String::from("secret")),
+                ..ServerConfig::default()
+            },
+        );
+        let backend = HttpBackend::new(&location, Some(String::from("secret")))?;
+        backend.write("instances/file", b"data", false)?;
+        assert2::assert!(backend.read("instances/file")? == b"data");
+        for token in [None, Some(String::from("secreT"))] {
+            let backend = HttpBackend::new(&location, token)?;
+            assert2::assert!(backend.read("instances/file").is_err());
+            assert2::assert!(backend.list("instances").is_err());
+            assert2::assert!(backend.write("instances/other", b"data", false).is_err());
+        }
+        assert2::assert!(backend.list("instances")?.len() == 1);
+        Ok(())
+    }
+
+    #[test]
+    fn large_files_are_refused() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let location = start_server_with(
+            temp.path(),
+            ServerConfig {
+                max_body_size: 4,
+                ..ServerConfig::default()
+            },
+        );
+        let backend = HttpBackend::new(&location, None)?;
+        backend.write("instances/small", b"data", false)?;
+        assert2::assert!(backend.write("instances/large", b"data!", false).is_err());
+        assert2::assert!(!backend.exists("instances/large")?);
+        Ok(())
+    }
+
+    #[test]
+    fn paths_are_percent_decoded() {
+        assert2::assert!(
+            percent_decode("/instances/a%20b%25") == Some(String::from("/instances/a b%"))
+        );
+        assert2::assert!(percent_decode("/instances/%2") == None);
+        assert2::assert!(percent_decode("/instances/%zz") == None);
+    }
+}
@@ -0,0 +1,67 @@
+#[cfg(test)]
+mod sftptest {
+    use backrub::backendrepository::BackendRepository;
+    use backrub::crypto::InputKey;
+    use backrub::errors::Result;
+    use backrub::repository::{Repository, RepositoryConfig};
+    use backrub::sftp::SftpLocation;
+    use std::path::Path;
+
+    /**
+     * The location of a directory for testing against a real SSH server, e.g.
+     * sftp://localhost/~/backrub-test, taken from BACKRUB_TEST_SFTP_LOCATION.
+     * Host key and authentication are set up like for the backrub command. The
+     * tests needing it are ignored unless run with `cargo test -- --ignored`.
+     */
+    fn test_location() -> String {
+        std::env::var("BACKRUB_TEST_SFTP_LOCATION")
+            .expect("BACKRUB_TEST_SFTP_LOCATION must be set to an sftp:// location")
+    }
+
+    #[test]
+    fn locations_are_parsed() -> Result<()> {
+        assert2::assert!(
+            SftpLocation::parse("sftp://backup@nas:2222/volume1/backups/")?
+                == SftpLocation {
+                    user: String::from("backup"),
+                    host: String::from("nas"),
+                    port: 2222,
+                    path: String::from("/volume1/backups"),
+                }
+        );
+        let location = SftpLocation::parse("sftp://nas/~/backups")?;
+        assert2::assert!(location.port == 22);
+        assert2::assert!(location.path == "backups");
+        assert2::assert!(SftpLocation::parse("sftp://nas").is_err());
+        assert2::assert!(SftpLocation::parse("sftp://nas:ssh/backups").is_err());
+        assert2::assert!(SftpLocation::parse("/local/path").is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn repository_roundtrip_over_sftp() -> Result<()> {
+        let location = format!("{}/{:016x}", test_location(), rand::random::<u64>());
+        let mut repo = BackendRepository::for_location(Path::new(&location))?;
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let (id, _) = repo.add_block(b"some data")?;
+        repo.write_lock("test", b"lock", false)?;
+        assert2::assert!(repo.write_lock("test", b"lock", false).is_err());
+        repo.write_lock("test", b"refreshed", true)?;
+        assert2::assert!(repo.read_lock("test")? == b"refreshed");
+        repo.remove_lock("test")?;
+        repo.flush()?;
+
+        let mut reopened = BackendRepository::for_location(Path::new(&location))?;
+        reopened.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(reopened.list_blocks()?.len() == 1);
+        reopened.remove_block(&id)?;
+        assert2::assert!(!reopened.has_block(&id)?);
+
+        Ok(())
+    }
+}
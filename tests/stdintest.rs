@@ -0,0 +1,157 @@
+#[cfg(test)]
+mod stdintest {
+    use assert_fs::prelude::*;
+    use std::io::Write;
+    use std::path::Path;
+    use std::process::{Command, Output, Stdio};
+
+    /**
+     * Run the backrub binary with the given arguments and data on stdin
+     */
+    fn backrub(temp: &Path, args: &[&str], input: &[u8]) -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_backrub"))
+            .args(args)
+            .env("BACKRUB_KEY", "MyTestKey")
+            .env("XDG_CACHE_HOME", temp.join("cache"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    fn init(temp: &Path, repo: &str) {
+        let output = backrub(
+            temp,
+            &[
+                "init",
+                repo,
+                "--kdf-iterations",
+                "1",
+                "--kdf-memory",
+                "1024",
+                "--kdf-lanes",
+                "1",
+            ],
+            b"",
+        );
+        assert2::assert!(output.status.success());
+    }
+
+    #[test]
+    fn stdin_is_backed_up_and_streamed_back() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let repo = temp.child("repo");
+        let repo = repo.path().to_str().unwrap();
+        init(temp.path(), repo);
+        let dump: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+        let sources = temp.child("sources");
+        sources.child("file").write_str("file content").unwrap();
+
+        let output = backrub(
+            temp.path(),
+            &[
+                "create",
+                "-r",
+                repo,
+                "-n",
+                "backup",
+                "--stdin-name",
+                "db.sql",
+                "-s",
+                sources.path().to_str().unwrap(),
+            ],
+            &dump,
+        );
+        assert2::assert!(output.status.success());
+
+        let output = backrub(
+            temp.path(),
+            &[
+                "restore",
+                "-r",
+                repo,
+                "-n",
+                "backup",
+                "--to-stdout",
+                "-i",
+                "^db\\.sql$",
+            ],
+            b"",
+        );
+        assert2::assert!(output.status.success());
+        assert2::assert!(output.stdout == dump);
+
+        // the instance contains more than one file
+        let output = backrub(
+            temp.path(),
+            &["restore", "-r", repo, "-n", "backup", "--to-stdout"],
+            b"",
+        );
+        assert2::assert!(!output.status.success());
+
+        let target = temp.child("target");
+        let output = backrub(
+            temp.path(),
+            &[
+                "restore",
+                "-r",
+                repo,
+                "-n",
+                "backup",
+                "-t",
+                target.path().to_str().unwrap(),
+            ],
+            b"",
+        );
+        assert2::assert!(output.status.success());
+        assert2::assert!(std::fs::read(target.child("db.sql").path()).unwrap() == dump);
+    }
+
+    #[test]
+    fn stdin_alone_is_enough_for_a_backup() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let repo = temp.child("repo");
+        let repo = repo.path().to_str().unwrap();
+        init(temp.path(), repo);
+
+        let output = backrub(
+            temp.path(),
+            &[
+                "create",
+                "-r",
+                repo,
+                "-n",
+                "dump",
+                "--stdin-name",
+                "/dumps/db.sql",
+            ],
+            b"some dump",
+        );
+        assert2::assert!(output.status.success());
+        let output = backrub(
+            temp.path(),
+            &["restore", "-r", repo, "-n", "dump", "--to-stdout"],
+            b"",
+        );
+        assert2::assert!(output.status.success());
+        assert2::assert!(output.stdout == b"some dump");
+
+        let output = backrub(
+            temp.path(),
+            &[
+                "create",
+                "-r",
+                repo,
+                "-n",
+                "other",
+                "--stdin-name",
+                "../db.sql",
+            ],
+            b"some dump",
+        );
+        assert2::assert!(!output.status.success());
+    }
+}
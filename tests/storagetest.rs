@@ -0,0 +1,238 @@
+#[cfg(test)]
+mod storagetest {
+    use assert_fs::prelude::*;
+    use backrub::backend::{Backend, LocalBackend};
+    use backrub::backendrepository::BackendRepository;
+    use backrub::crypto::InputKey;
+    use backrub::errors::{error, Result};
+    use backrub::os::unix::rename_no_replace;
+    use backrub::repository::{Repository, RepositoryConfig};
+    use backrub::storage::{FileStorage, ObjectKind, Storage};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    /**
+     * Backend keeping all files in memory
+     */
+    #[derive(Default)]
+    struct MemoryBackend {
+        files: RefCell<HashMap<String, Vec<u8>>>,
+    }
+
+    impl Backend for MemoryBackend {
+        fn read(&self, path: &str) -> Result<Vec<u8>> {
+            match self.files.borrow().get(path) {
+                Some(data) => Ok(data.clone()),
+                None => error("The file doesn't exist", None),
+            }
+        }
+        fn read_range(&self, path: &str, offset: u64, length: u64) -> Result<Vec<u8>> {
+            let data = self.read(path)?;
+            Ok(Vec::from(
+                &data[offset as usize..(offset + length) as usize],
+            ))
+        }
+        fn write(&self, path: &str, data: &[u8], overwrite: bool) -> Result<()> {
+            let mut files = self.files.borrow_mut();
+            if !overwrite && files.contains_key(path) {
+                return error("The file already exists", None);
+            }
+            files.insert(path.to_string(), Vec::from(data));
+            Ok(())
+        }
+        fn exists(&self, path: &str) -> Result<bool> {
+            Ok(self.files.borrow().contains_key(path))
+        }
+        fn size(&self, path: &str) -> Result<u64> {
+            match self.files.borrow().get(path) {
+                Some(data) => Ok(data.len() as u64),
+                None => error("The file doesn't exist", None),
+            }
+        }
+        fn list(&self, dir: &str) -> Result<Vec<(String, u64)>> {
+            let prefix = if dir.is_empty() {
+                String::new()
+            } else {
+                format!("{}/", dir)
+            };
+            Ok(self
+                .files
+                .borrow()
+                .iter()
+                .filter_map(|(path, data)| {
+                    path.strip_prefix(&prefix)
+                        .map(|name| (name.to_string(), data.len() as u64))
+                })
+                .collect())
+        }
+        fn remove(&self, path: &str) -> Result<()> {
+            match self.files.borrow_mut().remove(path) {
+                Some(_) => Ok(()),
+                None => error("The file doesn't exist", None),
+            }
+        }
+        fn create_dir(&self, _dir: &str) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /**
+     * Storage keeping all objects in memory
+     */
+    #[derive(Default)]
+    struct MemoryStorage {
+        objects: RefCell<HashMap<(ObjectKind, String), Vec<u8>>>,
+    }
+
+    impl Storage for MemoryStorage {
+        fn create(&self) -> Result<()> {
+            Ok(())
+        }
+        fn put(&self, kind: ObjectKind, name: &str, data: &[u8], overwrite: bool) -> Result<()> {
+            let mut objects = self.objects.borrow_mut();
+            let key = (kind, name.to_string());
+            if !overwrite && objects.contains_key(&key) {
+                return error("The object already exists", None);
+            }
+            objects.insert(key, Vec::from(data));
+            Ok(())
+        }
+        fn get(&self, kind: ObjectKind, name: &str) -> Result<Vec<u8>> {
+            match self.objects.borrow().get(&(kind, name.to_string())) {
+                Some(data) => Ok(data.clone()),
+                None => error("The object doesn't exist", None),
+            }
+        }
+        fn get_range(
+            &self,
+            kind: ObjectKind,
+            name: &str,
+            offset: u64,
+            length: u64,
+        ) -> Result<Vec<u8>> {
+            let data = self.get(kind, name)?;
+            Ok(Vec::from(
+                &data[offset as usize..(offset + length) as usize],
+            ))
+        }
+        fn list(&self, kind: ObjectKind) -> Result<Vec<(String, u64)>> {
+            Ok(self
+                .objects
+                .borrow()
+                .iter()
+                .filter(|((k, _), _)| *k == kind)
+                .map(|((_, name), data)| (name.clone(), data.len() as u64))
+                .collect())
+        }
+        fn delete(&self, kind: ObjectKind, name: &str) -> Result<()> {
+            match self.objects.borrow_mut().remove(&(kind, name.to_string())) {
+                Some(_) => Ok(()),
+                None => error("The object doesn't exist", None),
+            }
+        }
+        fn exists(&self, kind: ObjectKind, name: &str) -> Result<bool> {
+            Ok(self
+                .objects
+                .borrow()
+                .contains_key(&(kind, name.to_string())))
+        }
+    }
+
+    #[test]
+    fn repository_works_on_any_storage() -> Result<()> {
+        let storage = Rc::new(MemoryStorage::default());
+        let mut repo = BackendRepository::with_storage(storage.clone());
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let (id, _) = repo.add_block(b"some data")?;
+        repo.flush()?;
+        assert2::assert!(storage.list(ObjectKind::Pack)?.len() == 1);
+        assert2::assert!(storage.list(ObjectKind::PackIndex)?.len() == 1);
+        // nothing but the meta data is stored in the clear
+        let objects = storage.objects.borrow();
+        assert2::assert!(!objects
+            .values()
+            .any(|data| data.windows(9).any(|w| w == b"some data")));
+        drop(objects);
+
+        let mut reopened = BackendRepository::with_storage(storage);
+        reopened.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(reopened.has_block(&id)?);
+        reopened.remove_blocks(std::slice::from_ref(&id))?;
+        assert2::assert!(!reopened.has_block(&id)?);
+        Ok(())
+    }
+
+    #[test]
+    fn repository_works_on_any_backend() -> Result<()> {
+        let backend = Rc::new(MemoryBackend::default());
+        let storage = FileStorage::new(backend.clone());
+        let mut repo = BackendRepository::with_backend(backend.clone());
+        repo.initialize(
+            InputKey::from(b"MyTestKey" as &[u8]),
+            &RepositoryConfig::default(),
+        )?;
+        repo.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        let (id, _) = repo.add_block(b"some data")?;
+        repo.flush()?;
+        assert2::assert!(storage.list(ObjectKind::Pack)?.len() == 1);
+        assert2::assert!(storage.list(ObjectKind::PackIndex)?.len() == 1);
+        // nothing but the meta data is stored in the clear
+        let files = backend.files.borrow();
+        assert2::assert!(!files
+            .values()
+            .any(|data| data.windows(9).any(|w| w == b"some data")));
+        drop(files);
+
+        let mut reopened = BackendRepository::with_backend(backend);
+        reopened.open(InputKey::from(b"MyTestKey" as &[u8]))?;
+        assert2::assert!(reopened.has_block(&id)?);
+        reopened.remove_blocks(std::slice::from_ref(&id))?;
+        assert2::assert!(!reopened.has_block(&id)?);
+        Ok(())
+    }
+
+    #[test]
+    fn file_storage_keeps_the_repository_layout() -> Result<()> {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let storage = FileStorage::new(Rc::new(LocalBackend::new(temp.path())));
+        storage.create()?;
+        storage.put(ObjectKind::Block, "abcdef", b"block", false)?;
+        storage.put(ObjectKind::Pack, "012345", b"pack", false)?;
+        storage.put(ObjectKind::Meta, "backrub", b"meta", false)?;
+        storage.put(ObjectKind::Instance, "instance", b"instance", false)?;
+        assert2::assert!(temp.child("blocks/ab/cdef").path().is_file());
+        assert2::assert!(temp.child("packs/01/2345").path().is_file());
+        assert2::assert!(temp.child("instances/instance").path().is_file());
+        assert2::assert!(storage.list(ObjectKind::Block)? == vec![(String::from("abcdef"), 5)]);
+        assert2::assert!(storage.list(ObjectKind::Meta)? == vec![(String::from("backrub"), 4)]);
+        assert2::assert!(storage.get_range(ObjectKind::Pack, "012345", 1, 2)? == b"ac");
+        assert2::assert!(storage
+            .put(ObjectKind::Key, "../escape", b"key", false)
+            .is_err());
+        storage.delete(ObjectKind::Block, "abcdef")?;
+        assert2::assert!(!storage.exists(ObjectKind::Block, "abcdef")?);
+        Ok(())
+    }
+
+    #[test]
+    fn rename_never_replaces_existing_files() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.child("new").write_str("new").unwrap();
+        temp.child("existing").write_str("existing").unwrap();
+        assert2::assert!(rename_no_replace(
+            temp.child("new").path(),
+            temp.child("existing").path()
+        )
+        .is_err());
+        temp.child("existing").assert("existing");
+        assert2::assert!(
+            rename_no_replace(temp.child("new").path(), temp.child("moved").path()).is_ok()
+        );
+        temp.child("moved").assert("new");
+    }
+}